@@ -0,0 +1,168 @@
+//! Compilation time of synthetic programs which stress different stages of the compiler, run with `cargo bench`.
+//! Each program is compiled as a whole, and then stage by stage, starting from the output of the previous stage.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use std::collections::HashMap;
+
+use tmc::pipeline::{self, ImportCache, Options, PassManager, Program};
+
+/// A chain of `n` let bindings, each one calling the previous one.
+fn let_chain(n: usize) -> String {
+    let bindings = (1..n)
+        .map(|i| format!("    f{} = t: f{} (next t),\n", i, i - 1))
+        .collect::<String>();
+    format!(
+        "alphabet '0' | '1'\nlet\n    f0 = t: next t,\n{}in\n    f{}\n",
+        bindings,
+        n - 1
+    )
+}
+
+/// A match with one arm for each of the `n` symbols of the alphabet.
+fn wide_match(n: usize) -> String {
+    let alphabet = (0..n)
+        .map(|i| format!("'s{}'", i))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let arms = (0..n)
+        .map(|i| format!("    's{}' > set 's{}' t,\n", i, (i + 1) % n))
+        .collect::<String>();
+    format!(
+        "alphabet {}\nt: match get t {{\n{}    any > t,\n}}\n",
+        alphabet, arms
+    )
+}
+
+/// `n` nested `Y` loops, each one running the next one at every step.
+fn nested_loops(n: usize) -> String {
+    let body = (0..n).rev().fold("t".to_owned(), |inner, i| {
+        format!(
+            "((Y f{}: t: match get t {{ '{}' > f{} (next {}), any > t, }}) t)",
+            i,
+            i % 2,
+            i,
+            inner
+        )
+    });
+    format!("alphabet '0' | '1'\nt: {}\n", body)
+}
+
+fn programs() -> Vec<(&'static str, String)> {
+    vec![
+        ("let_chain", let_chain(300)),
+        ("wide_match", wide_match(64)),
+        ("nested_loops", nested_loops(10)),
+        ("inc", include_str!("../samples/inc.tmc").to_owned()),
+        ("loop_n", include_str!("../samples/loop_n.tmc").to_owned()),
+    ]
+}
+
+fn parse(src: &str) -> Program {
+    let files = HashMap::<String, String>::new();
+    pipeline::parse(
+        pipeline::tokenize(
+            src,
+            &files,
+            &pipeline::standard_library(),
+            &ImportCache::default(),
+        )
+        .unwrap(),
+    )
+    .unwrap()
+}
+
+fn compile(c: &mut Criterion) {
+    let options = Options::default();
+    let mut group = c.benchmark_group("compile");
+    group.sample_size(10);
+    for (name, src) in programs() {
+        let program = parse(&src);
+        let alphabet = pipeline::alphabet(&program, &options).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let passes = PassManager::default();
+                pipeline::build(&program, &alphabet, &options, &passes).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn stages(c: &mut Criterion) {
+    let options = Options::default();
+    let passes = || PassManager::default();
+    let mut group = c.benchmark_group("stages");
+    group.sample_size(10);
+    for (name, src) in programs() {
+        let program = parse(&src);
+        let alphabet = pipeline::alphabet(&program, &options).unwrap();
+        let desugared = pipeline::desugar(&program, &alphabet, &passes()).unwrap();
+        let typed = pipeline::type_check(desugared.clone(), 1, &passes()).unwrap();
+        let simplified = pipeline::simplify(typed.clone(), &passes()).unwrap();
+
+        group.bench_function(format!("{}/parse", name), |b| b.iter(|| parse(&src)));
+        group.bench_function(format!("{}/desugar", name), |b| {
+            b.iter(|| pipeline::desugar(&program, &alphabet, &passes()).unwrap())
+        });
+        group.bench_function(format!("{}/type_check", name), |b| {
+            b.iter_batched(
+                || desugared.clone(),
+                |desugared| pipeline::type_check(desugared, 1, &passes()).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(format!("{}/simplify", name), |b| {
+            b.iter_batched(
+                || typed.clone(),
+                |typed| pipeline::simplify(typed, &passes()).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(format!("{}/generate", name), |b| {
+            b.iter_batched(
+                || simplified.clone(),
+                |simplified| pipeline::generate(simplified, 1, options.opt_level).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Tokenizing a program which imports `std/check.tmc`, `std/iter.tmc` and `std/bool.tmc`, with the tokens of the
+/// imports cached or not, next to parsing and the whole compilation of the program.
+fn imports(c: &mut Criterion) {
+    let src = include_str!("../samples/is_binary.tmc");
+    let files = HashMap::<String, String>::new();
+    let lib = pipeline::standard_library();
+    let options = Options::default();
+    let mut group = c.benchmark_group("imports");
+    group.bench_function("tokenize", |b| {
+        b.iter(|| pipeline::tokenize(src, &files, &lib, &ImportCache::default()).unwrap())
+    });
+    let cache = ImportCache::default();
+    group.bench_function("tokenize_cached", |b| {
+        b.iter(|| pipeline::tokenize(src, &files, &lib, &cache).unwrap())
+    });
+    let toks = pipeline::tokenize(src, &files, &lib, &cache).unwrap();
+    group.bench_function("parse", |b| {
+        b.iter_batched(
+            || toks.clone(),
+            |toks| pipeline::parse(toks).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("compile", |b| {
+        b.iter(|| {
+            let program = parse(src);
+            let alphabet = pipeline::alphabet(&program, &options).unwrap();
+            let passes = PassManager::default();
+            pipeline::build(&program, &alphabet, &options, &passes).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compile, stages, imports);
+criterion_main!(benches);
@@ -2,52 +2,106 @@ use super::Annot;
 use crate::data::{Exp, Node, Pat};
 use std::collections::HashSet;
 
-/// Checks if all match patterns are constant.
+/// Checks if all match patterns are constant, and that none of them uses a symbol captured by an outer match arm.
 pub fn const_check(ast: &Exp<Annot>) -> Result<(), String> {
-    is_const(ast, &HashSet::new())?;
+    is_const(ast, &HashSet::new(), &HashSet::new())?;
     Ok(())
 }
 
-fn is_const(exp: &Exp<Annot>, const_exps: &HashSet<String>) -> Result<bool, String> {
+/// Symbols captured by match arms are constant, but they are only replaced once the matches inside their arm have
+/// been simplified, so the patterns of those matches can't use them.
+fn is_const(
+    exp: &Exp<Annot>,
+    const_exps: &HashSet<String>,
+    captured: &HashSet<String>,
+) -> Result<bool, String> {
     match &exp.0 {
         Node::Identifier(id) => Ok(const_exps.contains(id)),
         Node::Symbol(_) => Ok(true),
         Node::Union { lhs, rhs } => {
             let mut ret = true;
-            ret &= is_const(lhs, const_exps)?;
-            ret &= is_const(rhs, const_exps)?;
+            ret &= is_const(lhs, const_exps, captured)?;
+            ret &= is_const(rhs, const_exps, captured)?;
             Ok(ret)
         }
         Node::Match { exp, arms } => {
-            let mut ret = is_const(exp, const_exps)?;
+            let mut ret = is_const(exp, const_exps, captured)?;
             for arm in arms {
                 if let Pat::Union(exp) = &arm.pat {
-                    if !is_const(exp, const_exps)? {
+                    if let Some(id) = find_captured(exp, captured) {
+                        return Err(format!(
+                            "Match pattern at {} uses {}, which was captured by an outer match arm",
+                            exp.1 .1, id
+                        ));
+                    }
+                    if !is_const(exp, const_exps, captured)? {
                         return Err(format!("Match pattern at {} is not constant", exp.1 .1));
                     }
                 }
 
-                if let Some(id) = &arm.catch_id {
-                    let mut const_exps = const_exps.clone();
-                    const_exps.insert(id.clone());
-                    ret = ret && is_const(&arm.exp, &const_exps)?;
-                } else {
-                    ret = ret && is_const(&arm.exp, const_exps)?;
-                }
+                let mut const_exps = const_exps.clone();
+                const_exps.extend(arm.catch_ids().into_iter().cloned());
+                let mut captured = captured.clone();
+                captured.extend(arm.catch_ids().into_iter().cloned());
+                ret &= is_const(&arm.exp, &const_exps, &captured)?;
             }
             Ok(ret)
         }
         Node::Function { arg, exp } => {
             let mut const_exps = const_exps.clone();
             const_exps.insert(arg.clone());
-            is_const(exp, &const_exps)
+            let mut captured = captured.clone();
+            captured.remove(arg);
+            is_const(exp, &const_exps, &captured)
         }
         Node::Application { func, arg } => {
+            // Functions are checked assuming their argument is constant, unless they're applied right away to one
+            // which isn't, as happens with let bindings.
+            if let Node::Function { arg: id, exp } = &func.0 {
+                let arg_const = is_const(arg, const_exps, captured)?;
+                let mut const_exps = const_exps.clone();
+                if arg_const {
+                    const_exps.insert(id.clone());
+                } else {
+                    const_exps.remove(id);
+                }
+                let mut captured = captured.clone();
+                captured.remove(id);
+                return Ok(is_const(exp, &const_exps, &captured)? && arg_const);
+            }
+
+            let mut ret = true;
+            ret &= is_const(func, const_exps, captured)?;
+            ret &= is_const(arg, const_exps, captured)?;
+            Ok(ret)
+        }
+        Node::Tuple(tracks) => {
             let mut ret = true;
-            ret &= is_const(func, const_exps)?;
-            ret &= is_const(arg, const_exps)?;
+            for track in tracks {
+                if let Pat::Union(exp) = &track.pat {
+                    ret &= is_const(exp, const_exps, captured)?;
+                }
+            }
             Ok(ret)
         }
         _ => unreachable!(),
     }
 }
+
+/// Finds an identifier captured by an outer match arm in a pattern.
+fn find_captured<'a>(exp: &'a Exp<Annot>, captured: &HashSet<String>) -> Option<&'a String> {
+    match &exp.0 {
+        Node::Identifier(id) if captured.contains(id) => Some(id),
+        Node::Union { lhs, rhs } => {
+            find_captured(lhs, captured).or_else(|| find_captured(rhs, captured))
+        }
+        Node::Application { func, arg } => {
+            find_captured(func, captured).or_else(|| find_captured(arg, captured))
+        }
+        Node::Tuple(tracks) => tracks.iter().find_map(|t| match &t.pat {
+            Pat::Union(exp) => find_captured(exp, captured),
+            Pat::Any => None,
+        }),
+        _ => None,
+    }
+}
@@ -54,13 +54,26 @@ fn traverse(exp: &Exp<Annot>, consumed: &mut HashSet<String>, is_ref: bool) -> R
             };
 
             match (&**func_arg_t, &**func_ret_t) {
-                (Type::Tape, o) if o != &Type::Tape && o != &Type::Halt && o != &Type::Symbol => {
+                (Type::Tape, o)
+                    if o != &Type::Tape
+                        && o != &Type::Halt
+                        && o != &Type::Symbol
+                        && o.tape_function_arity().is_none() =>
+                {
                     return Err(format!(
-                        "Function at {} receives tape as argument but returns {}, while only tape, symbol or halt are allowed",
+                        "Function at {} receives tape as argument but returns {}, while only tape, symbol, halt or functions of tapes are allowed",
                         func.1.1,
                         o
                     ));
                 }
+                _ if matches!(&func.0, Node::Identifier(id) if id == "Y")
+                    && func_ret_t.tape_function_arity().is_none() =>
+                {
+                    return Err(format!(
+                        "Recursive function at {} must take tapes and return a tape, but its type is {}",
+                        arg.1 .1, func_ret_t
+                    ));
+                }
                 _ => {
                     traverse(func, consumed, false)?;
                     traverse(arg, consumed, false)?;
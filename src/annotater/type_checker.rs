@@ -1,22 +1,19 @@
-use crate::data::{Arm, Exp, Node, Pat, TokenLoc, Type, TypeTable};
+use crate::data::{Arm, Exp, ExpRef, Node, Pat, TokenLoc, Track, Type, TypeTable};
 use std::collections::HashMap;
 use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Annot(pub Type, pub TokenLoc);
 
 /// Annotates an AST with types, checking for type errors.
-/// All remaining unresolved types
-pub fn type_check(ast: Exp<TokenLoc>) -> Result<Exp<Annot>, String> {
+/// The AST must evaluate to a function which takes the given number of tapes and returns a tape.
+pub fn type_check(ast: Exp<TokenLoc>, tapes: usize) -> Result<Exp<Annot>, String> {
     let mut type_table = TypeTable::new();
-    let ast_t = Type::Function {
-        arg: Box::new(Type::Tape),
-        ret: Box::new(Type::Tape),
-    };
+    let ast_t = Type::tape_function(tapes);
 
     let mut ast = check_exp(
         ast,
-        &define_builtin_functions()
+        &mut define_builtin_functions()
             .into_iter()
             .map(|(i, f)| (i, (true, f)))
             .collect(),
@@ -28,10 +25,13 @@ pub fn type_check(ast: Exp<TokenLoc>) -> Result<Exp<Annot>, String> {
     resolve_exp(ast, &mut type_table, false, &ast_t)
 }
 
+/// Type of each identifier in scope, and whether it is a builtin.
+type Vars = HashMap<String, (bool, Type)>;
+
 /// Checks the types of an expression.
 fn check_exp(
     exp: Exp<TokenLoc>,
-    vars: &HashMap<String, (bool, Type)>,
+    vars: &mut Vars,
     type_table: &mut TypeTable,
     ret_t: &Type,
 ) -> Result<Exp<Annot>, String> {
@@ -41,8 +41,22 @@ fn check_exp(
                 .get(&id)
                 .ok_or_else(|| format!("Undefined identifier {} at {}", id, exp.1))?;
             if *fixed {
-                type_table.cast(var_t, ret_t, &exp.1)?;
-                Ok(Exp(Node::Identifier(id), Annot(var_t.clone(), exp.1)))
+                // The Y combinator works on functions which take any number of tapes.
+                let var_t = match id.as_str() {
+                    "Y" => {
+                        let func_t = type_table.push();
+                        Type::Function {
+                            arg: Box::new(Type::Function {
+                                arg: Box::new(func_t.clone()),
+                                ret: Box::new(func_t.clone()),
+                            }),
+                            ret: Box::new(func_t),
+                        }
+                    }
+                    _ => var_t.clone(),
+                };
+                type_table.cast(&var_t, ret_t, &exp.1)?;
+                Ok(Exp(Node::Identifier(id), Annot(var_t, exp.1)))
             } else {
                 let t = type_table.push();
                 type_table.cast(var_t, &t, &exp.1)?;
@@ -57,13 +71,13 @@ fn check_exp(
         }
 
         Node::Union { lhs, rhs } => {
-            let lhs = check_exp(*lhs, vars, type_table, &Type::Union)?;
-            let rhs = check_exp(*rhs, vars, type_table, &Type::Union)?;
+            let lhs = check_exp(ExpRef::unwrap_or_clone(lhs), vars, type_table, &Type::Union)?;
+            let rhs = check_exp(ExpRef::unwrap_or_clone(rhs), vars, type_table, &Type::Union)?;
             type_table.cast(&Type::Union, ret_t, &exp.1)?;
             Ok(Exp(
                 Node::Union {
-                    lhs: Box::new(lhs),
-                    rhs: Box::new(rhs),
+                    lhs: ExpRef::new(lhs),
+                    rhs: ExpRef::new(rhs),
                 },
                 Annot(Type::Union, exp.1),
             ))
@@ -73,33 +87,46 @@ fn check_exp(
             exp: match_exp,
             arms,
         } => {
-            let match_exp = check_exp(*match_exp, vars, type_table, &Type::Symbol)?;
+            let match_exp = check_exp(
+                ExpRef::unwrap_or_clone(match_exp),
+                vars,
+                type_table,
+                &Type::Symbol,
+            )?;
 
             let mut new_arms = Vec::new();
             for arm in arms.into_iter() {
+                let catch_ids = arm
+                    .catch_ids()
+                    .into_iter()
+                    .map(|id| (id.clone(), (false, Type::Symbol)))
+                    .collect();
+
                 let pat = match arm.pat {
                     Pat::Union(exp) => {
-                        let exp = check_exp(exp, vars, type_table, &Type::Union)?;
-                        Pat::Union(exp)
+                        let exp = check_exp(
+                            ExpRef::unwrap_or_clone(exp),
+                            vars,
+                            type_table,
+                            &Type::Union,
+                        )?;
+                        Pat::Union(ExpRef::new(exp))
                     }
                     Pat::Any => Pat::Any,
                 };
 
-                let mut vars = vars.clone();
-                if let Some(catch_id) = &arm.catch_id {
-                    vars.insert(catch_id.clone(), (false, Type::Symbol));
-                }
-
                 new_arms.push(Arm {
                     catch_id: arm.catch_id,
                     pat,
-                    exp: check_exp(arm.exp, &vars, type_table, ret_t)?,
+                    exp: ExpRef::new(scoped(vars, catch_ids, |vars| {
+                        check_exp(ExpRef::unwrap_or_clone(arm.exp), vars, type_table, ret_t)
+                    })?),
                 });
             }
 
             Ok(Exp(
                 Node::Match {
-                    exp: Box::new(match_exp),
+                    exp: ExpRef::new(match_exp),
                     arms: new_arms,
                 },
                 Annot(ret_t.clone(), exp.1),
@@ -113,9 +140,15 @@ fn check_exp(
             let func_arg_t = type_table.push();
             let func_ret_t = type_table.push();
 
-            let mut vars = vars.clone();
-            vars.insert(arg.clone(), (false, func_arg_t.clone()));
-            let function_exp = check_exp(*function_exp, &vars, type_table, &func_ret_t)?;
+            let bind = vec![(arg.clone(), (false, func_arg_t.clone()))];
+            let function_exp = scoped(vars, bind, |vars| {
+                check_exp(
+                    ExpRef::unwrap_or_clone(function_exp),
+                    vars,
+                    type_table,
+                    &func_ret_t,
+                )
+            })?;
 
             let func_t = Type::Function {
                 arg: Box::new(func_arg_t),
@@ -126,7 +159,7 @@ fn check_exp(
             Ok(Exp(
                 Node::Function {
                     arg,
-                    exp: Box::new(function_exp),
+                    exp: ExpRef::new(function_exp),
                 },
                 Annot(func_t, exp.1),
             ))
@@ -134,27 +167,77 @@ fn check_exp(
 
         Node::Application { func, arg } => {
             let arg_t = type_table.push();
-            let arg = check_exp(*arg, vars, type_table, &arg_t)?;
+            let arg = check_exp(ExpRef::unwrap_or_clone(arg), vars, type_table, &arg_t)?;
 
             let func_t = Type::Function {
                 arg: Box::new(arg_t),
                 ret: Box::new(ret_t.clone()),
             };
-            let func = check_exp(*func, vars, type_table, &func_t)?;
+            let func = check_exp(ExpRef::unwrap_or_clone(func), vars, type_table, &func_t)?;
 
             Ok(Exp(
                 Node::Application {
-                    func: Box::new(func),
-                    arg: Box::new(arg),
+                    func: ExpRef::new(func),
+                    arg: ExpRef::new(arg),
                 },
                 Annot(ret_t.clone(), exp.1),
             ))
         }
 
+        Node::Tuple(tracks) => {
+            // Tuples of symbols are symbols, and tuples of unions are unions.
+            let mut new_tracks = Vec::new();
+            for track in tracks {
+                let pat = match track.pat {
+                    Pat::Union(exp) => Pat::Union(ExpRef::new(check_exp(
+                        ExpRef::unwrap_or_clone(exp),
+                        vars,
+                        type_table,
+                        ret_t,
+                    )?)),
+                    Pat::Any => {
+                        type_table.cast(&Type::Union, ret_t, &exp.1)?;
+                        Pat::Any
+                    }
+                };
+                new_tracks.push(Track {
+                    catch_id: track.catch_id,
+                    pat,
+                });
+            }
+
+            Ok(Exp(Node::Tuple(new_tracks), Annot(ret_t.clone(), exp.1)))
+        }
+
         _ => unreachable!(),
     }
 }
 
+/// Runs a function with the given identifiers bound, restoring the identifiers they hide afterwards. The identifiers
+/// in scope are updated in place instead of being copied, as copying them for every binding would take time quadratic
+/// in the length of `let` chains.
+fn scoped<T>(
+    vars: &mut Vars,
+    binds: Vec<(String, (bool, Type))>,
+    f: impl FnOnce(&mut Vars) -> T,
+) -> T {
+    let hidden = binds
+        .into_iter()
+        .map(|(id, var)| {
+            let old = vars.insert(id.clone(), var);
+            (id, old)
+        })
+        .collect::<Vec<_>>();
+    let result = f(vars);
+    for (id, old) in hidden.into_iter().rev() {
+        match old {
+            Some(old) => vars.insert(id, old),
+            None => vars.remove(&id),
+        };
+    }
+    result
+}
+
 /// Resolves all unresolved types (other than tapes) in an expression.
 fn resolve_exp(
     exp: Exp<Annot>,
@@ -165,8 +248,18 @@ fn resolve_exp(
     let exp = match exp.0 {
         Node::Union { lhs, rhs } => Exp(
             Node::Union {
-                lhs: Box::new(resolve_exp(*lhs, type_table, false, &Type::Union)?),
-                rhs: Box::new(resolve_exp(*rhs, type_table, false, &Type::Union)?),
+                lhs: ExpRef::new(resolve_exp(
+                    ExpRef::unwrap_or_clone(lhs),
+                    type_table,
+                    false,
+                    &Type::Union,
+                )?),
+                rhs: ExpRef::new(resolve_exp(
+                    ExpRef::unwrap_or_clone(rhs),
+                    type_table,
+                    false,
+                    &Type::Union,
+                )?),
             },
             Annot(Type::Union, exp.1 .1),
         ),
@@ -175,29 +268,44 @@ fn resolve_exp(
             exp: match_exp,
             arms,
         } => {
-            let match_exp = resolve_exp(*match_exp, type_table, false, &Type::Symbol)?;
+            let match_exp = resolve_exp(
+                ExpRef::unwrap_or_clone(match_exp),
+                type_table,
+                false,
+                &Type::Symbol,
+            )?;
 
             let mut new_arms = Vec::new();
             for arm in arms.into_iter() {
                 let pat = match arm.pat {
                     Pat::Union(exp) => {
-                        let exp = resolve_exp(exp, type_table, false, &Type::Union)?;
-                        Pat::Union(exp)
+                        let exp = resolve_exp(
+                            ExpRef::unwrap_or_clone(exp),
+                            type_table,
+                            false,
+                            &Type::Union,
+                        )?;
+                        Pat::Union(ExpRef::new(exp))
                     }
                     Pat::Any => Pat::Any,
                 };
 
-                let exp = resolve_exp(arm.exp, type_table, allow_unresolved, &exp.1 .0)?;
+                let exp = resolve_exp(
+                    ExpRef::unwrap_or_clone(arm.exp),
+                    type_table,
+                    allow_unresolved,
+                    &exp.1 .0,
+                )?;
                 new_arms.push(Arm {
                     catch_id: arm.catch_id,
                     pat,
-                    exp,
+                    exp: ExpRef::new(exp),
                 });
             }
 
             Exp(
                 Node::Match {
-                    exp: Box::new(match_exp.clone()),
+                    exp: ExpRef::new(match_exp),
                     arms: new_arms,
                 },
                 Annot(type_table.resolve(&exp.1 .0), exp.1 .1),
@@ -214,7 +322,12 @@ fn resolve_exp(
             Exp(
                 Node::Function {
                     arg,
-                    exp: Box::new(resolve_exp(*body_exp, type_table, allow_unresolved, &ret)?),
+                    exp: ExpRef::new(resolve_exp(
+                        ExpRef::unwrap_or_clone(body_exp),
+                        type_table,
+                        allow_unresolved,
+                        &ret,
+                    )?),
                 },
                 Annot(type_table.resolve(&exp.1 .0), exp.1 .1),
             )
@@ -229,16 +342,46 @@ fn resolve_exp(
                 unreachable!()
             };
 
-            let func = resolve_exp(*func, type_table, allow_unresolved, &func_t)?;
+            let func = resolve_exp(
+                ExpRef::unwrap_or_clone(func),
+                type_table,
+                allow_unresolved,
+                &func_t,
+            )?;
             Exp(
                 Node::Application {
-                    func: Box::new(func),
-                    arg: Box::new(resolve_exp(*arg, type_table, allow_unresolved, arg_t)?),
+                    func: ExpRef::new(func),
+                    arg: ExpRef::new(resolve_exp(
+                        ExpRef::unwrap_or_clone(arg),
+                        type_table,
+                        allow_unresolved,
+                        arg_t,
+                    )?),
                 },
                 Annot(type_table.resolve(&exp.1 .0), exp.1 .1),
             )
         }
 
+        Node::Tuple(tracks) => {
+            let tuple_t = type_table.resolve(&exp.1 .0);
+            let mut new_tracks = Vec::new();
+            for track in tracks {
+                new_tracks.push(Track {
+                    catch_id: track.catch_id,
+                    pat: match track.pat {
+                        Pat::Union(exp) => Pat::Union(ExpRef::new(resolve_exp(
+                            ExpRef::unwrap_or_clone(exp),
+                            type_table,
+                            false,
+                            &tuple_t,
+                        )?)),
+                        Pat::Any => Pat::Any,
+                    },
+                });
+            }
+            Exp(Node::Tuple(new_tracks), Annot(tuple_t, exp.1 .1))
+        }
+
         node => Exp(node, Annot(type_table.resolve(&exp.1 .0), exp.1 .1)),
     };
 
@@ -255,7 +398,7 @@ fn resolve_exp(
 }
 
 /// Defines the types of the built-in functions.
-fn define_builtin_functions() -> HashMap<String, Type> {
+pub fn define_builtin_functions() -> HashMap<String, Type> {
     let mut vars = HashMap::new();
 
     // accept :: tape -> halt
@@ -315,7 +458,28 @@ fn define_builtin_functions() -> HashMap<String, Type> {
         },
     );
 
-    // Y :: ((tape -> tape) -> tape -> tape) -> (tape -> tape)
+    // either :: (tape -> tape) -> (tape -> tape) -> tape -> tape
+    vars.insert(
+        "either".to_owned(),
+        Type::Function {
+            arg: Box::new(Type::Function {
+                arg: Box::new(Type::Tape),
+                ret: Box::new(Type::Tape),
+            }),
+            ret: Box::new(Type::Function {
+                arg: Box::new(Type::Function {
+                    arg: Box::new(Type::Tape),
+                    ret: Box::new(Type::Tape),
+                }),
+                ret: Box::new(Type::Function {
+                    arg: Box::new(Type::Tape),
+                    ret: Box::new(Type::Tape),
+                }),
+            }),
+        },
+    );
+
+    // Y :: ((tape -> tape) -> tape -> tape) -> (tape -> tape), or with any other number of tapes
     vars.insert(
         "Y".to_owned(),
         Type::Function {
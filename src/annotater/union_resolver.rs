@@ -1,5 +1,5 @@
 use super::Annot;
-use crate::data::{Arm, Exp, Node, Pat, Type};
+use crate::data::{Arm, Exp, Node, Pat, Track, Type};
 use std::collections::HashMap;
 
 /// Fixes a type annotated AST by resolving every unresolved union type.
@@ -40,35 +40,48 @@ fn fix_ids(exp: Exp<Annot>, count: &mut usize) -> Exp<Annot> {
     Exp(
         match exp.0 {
             Node::Union { lhs, rhs } => Node::Union {
-                lhs: Box::new(fix_ids(*lhs, count)),
-                rhs: Box::new(fix_ids(*rhs, count)),
+                lhs: lhs.map(|e| fix_ids(e, count)),
+                rhs: rhs.map(|e| fix_ids(e, count)),
             },
 
             Node::Match { exp, arms } => Node::Match {
-                exp: Box::new(fix_ids(*exp, count)),
+                exp: exp.map(|e| fix_ids(e, count)),
                 arms: arms
                     .into_iter()
                     .map(|arm| Arm {
                         pat: match arm.pat {
-                            Pat::Union(exp) => Pat::Union(fix_ids(exp, count)),
+                            Pat::Union(exp) => Pat::Union(exp.map(|e| fix_ids(e, count))),
                             Pat::Any => Pat::Any,
                         },
                         catch_id: arm.catch_id,
-                        exp: fix_ids(arm.exp, count),
+                        exp: arm.exp.map(|e| fix_ids(e, count)),
                     })
                     .collect(),
             },
 
             Node::Function { arg, exp } => Node::Function {
                 arg,
-                exp: Box::new(fix_ids(*exp, count)),
+                exp: exp.map(|e| fix_ids(e, count)),
             },
 
             Node::Application { func, arg } => Node::Application {
-                func: Box::new(fix_ids(*func, count)),
-                arg: Box::new(fix_ids(*arg, count)),
+                func: func.map(|e| fix_ids(e, count)),
+                arg: arg.map(|e| fix_ids(e, count)),
             },
 
+            Node::Tuple(tracks) => Node::Tuple(
+                tracks
+                    .into_iter()
+                    .map(|track| Track {
+                        catch_id: track.catch_id,
+                        pat: match track.pat {
+                            Pat::Union(exp) => Pat::Union(exp.map(|e| fix_ids(e, count))),
+                            Pat::Any => Pat::Any,
+                        },
+                    })
+                    .collect(),
+            ),
+
             n => n,
         },
         annot,
@@ -131,8 +144,8 @@ fn collect_casts(
         }
 
         Node::Union { lhs, rhs } => {
-            collect_casts(&lhs, casts, ids, Some(&Type::Union));
-            collect_casts(&rhs, casts, ids, Some(&Type::Union));
+            collect_casts(lhs, casts, ids, Some(&Type::Union));
+            collect_casts(rhs, casts, ids, Some(&Type::Union));
             collect_casts_in_type(Some(&Type::Union), ret_t, casts);
         }
 
@@ -141,19 +154,17 @@ fn collect_casts(
             arms,
         } => {
             collect_casts_in_type(Some(&exp.1 .0), ret_t, casts);
-            collect_casts(&match_exp, casts, ids, Some(&Type::Symbol));
+            collect_casts(match_exp, casts, ids, Some(&Type::Symbol));
             for arm in arms {
                 if let Pat::Union(exp) = &arm.pat {
-                    collect_casts(&exp, casts, ids, Some(&Type::Union));
+                    collect_casts(exp, casts, ids, Some(&Type::Union));
                 }
 
-                if let Some(id) = &arm.catch_id {
-                    let mut ids = ids.clone();
+                let mut ids = ids.clone();
+                for id in arm.catch_ids() {
                     ids.insert(id.clone(), Type::Symbol);
-                    collect_casts(&arm.exp, casts, &ids, Some(&exp.1 .0));
-                } else {
-                    collect_casts(&arm.exp, casts, ids, Some(&exp.1 .0));
                 }
+                collect_casts(&arm.exp, casts, &ids, Some(&exp.1 .0));
             }
         }
 
@@ -168,9 +179,9 @@ fn collect_casts(
             if func_arg_t.is_unresolved_union() {
                 let mut ids = ids.clone();
                 ids.insert(arg.clone(), *func_arg_t.clone());
-                collect_casts(&func_exp, casts, &ids, Some(&func_ret_t));
+                collect_casts(func_exp, casts, &ids, Some(func_ret_t));
             } else {
-                collect_casts(&func_exp, casts, ids, Some(&func_ret_t));
+                collect_casts(func_exp, casts, ids, Some(func_ret_t));
             }
         }
 
@@ -180,11 +191,19 @@ fn collect_casts(
                 _ => unreachable!(),
             };
 
-            collect_casts_in_type(Some(&arg.1 .0), Some(&func_arg_t), casts);
-            collect_casts_in_type(Some(&func_ret_t), Some(&exp.1 .0), casts);
+            collect_casts_in_type(Some(&arg.1 .0), Some(func_arg_t), casts);
+            collect_casts_in_type(Some(func_ret_t), Some(&exp.1 .0), casts);
+            collect_casts_in_type(Some(&exp.1 .0), ret_t, casts);
+            collect_casts(func, casts, ids, None);
+            collect_casts(arg, casts, ids, None);
+        }
+        Node::Tuple(tracks) => {
+            for track in tracks {
+                if let Pat::Union(track) = &track.pat {
+                    collect_casts(track, casts, ids, Some(&exp.1 .0));
+                }
+            }
             collect_casts_in_type(Some(&exp.1 .0), ret_t, casts);
-            collect_casts(&func, casts, ids, None);
-            collect_casts(&arg, casts, ids, None);
         }
         _ => (),
     }
@@ -216,9 +235,9 @@ fn generate_resolved(count: usize, casts: &[(usize, usize)]) -> Vec<Type> {
     }
 
     // Resolve remaining unresolved types to union.
-    for i in 2..count {
-        if types[i] == Type::UnresolvedUnion(0) {
-            types[i] = Type::Union;
+    for t in types.iter_mut().skip(2) {
+        if *t == Type::UnresolvedUnion(0) {
+            *t = Type::Union;
         }
     }
 
@@ -244,35 +263,48 @@ fn remove_unresolved(exp: Exp<Annot>, types: &[Type]) -> Exp<Annot> {
     Exp(
         match exp.0 {
             Node::Union { lhs, rhs } => Node::Union {
-                lhs: Box::new(remove_unresolved(*lhs, types)),
-                rhs: Box::new(remove_unresolved(*rhs, types)),
+                lhs: lhs.map(|e| remove_unresolved(e, types)),
+                rhs: rhs.map(|e| remove_unresolved(e, types)),
             },
 
             Node::Match { exp, arms } => Node::Match {
-                exp: Box::new(remove_unresolved(*exp, types)),
+                exp: exp.map(|e| remove_unresolved(e, types)),
                 arms: arms
                     .into_iter()
                     .map(|arm| Arm {
                         pat: match arm.pat {
-                            Pat::Union(exp) => Pat::Union(remove_unresolved(exp, types)),
+                            Pat::Union(exp) => Pat::Union(exp.map(|e| remove_unresolved(e, types))),
                             Pat::Any => Pat::Any,
                         },
                         catch_id: arm.catch_id,
-                        exp: remove_unresolved(arm.exp, types),
+                        exp: arm.exp.map(|e| remove_unresolved(e, types)),
                     })
                     .collect(),
             },
 
             Node::Function { arg, exp } => Node::Function {
                 arg,
-                exp: Box::new(remove_unresolved(*exp, types)),
+                exp: exp.map(|e| remove_unresolved(e, types)),
             },
 
             Node::Application { func, arg } => Node::Application {
-                func: Box::new(remove_unresolved(*func, types)),
-                arg: Box::new(remove_unresolved(*arg, types)),
+                func: func.map(|e| remove_unresolved(e, types)),
+                arg: arg.map(|e| remove_unresolved(e, types)),
             },
 
+            Node::Tuple(tracks) => Node::Tuple(
+                tracks
+                    .into_iter()
+                    .map(|track| Track {
+                        catch_id: track.catch_id,
+                        pat: match track.pat {
+                            Pat::Union(exp) => Pat::Union(exp.map(|e| remove_unresolved(e, types))),
+                            Pat::Any => Pat::Any,
+                        },
+                    })
+                    .collect(),
+            ),
+
             n => n,
         },
         annot,
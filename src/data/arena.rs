@@ -0,0 +1,376 @@
+use super::{Exp, Node, Pat};
+
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+/// Annotations which expressions can be interned with.
+pub trait Internable: Clone + Eq + Hash + 'static {}
+
+impl<T: Clone + Eq + Hash + 'static> Internable for T {}
+
+/// Handle to an expression interned in the arena of the current thread.
+/// Expressions with the same nodes and annotations are stored once, so two handles are equal when they point to the
+/// same node, and comparing or hashing them doesn't visit their subtrees.
+pub struct ExpRef<Annot>(Rc<Entry<Annot>>);
+
+struct Entry<Annot> {
+    exp: Exp<Annot>,
+    hash: u64,
+    /// Hash of the expression which ignores annotations, so that expressions equal by `eq_ignore_annot` have the
+    /// same shape. It is only computed when first needed.
+    shape: Cell<Option<u64>>,
+    /// Identifiers occurring free in the expression.
+    ids: Ids,
+}
+
+/// Interned expressions with a given annotation type, by hash. Entries are weak, so that expressions are freed once
+/// no handle points to them. When two live expressions have the same hash, only the newest is interned, which keeps
+/// handles correct but lets equal expressions have different nodes.
+struct Arena<Annot> {
+    entries: HashMap<u64, Weak<Entry<Annot>>, BuildHasherDefault<FxHasher>>,
+    /// Number of entries left by the last sweep of the freed ones.
+    swept: usize,
+}
+
+thread_local! {
+    static ARENAS: RefCell<Vec<(TypeId, Box<dyn Any>)>> = const { RefCell::new(Vec::new()) };
+}
+
+impl<Annot: Internable> ExpRef<Annot> {
+    /// Interns the given expression, returning the existing node if an equal one is still alive.
+    pub fn new(exp: Exp<Annot>) -> ExpRef<Annot> {
+        let mut hasher = FxHasher::default();
+        exp.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        ARENAS.with(|arenas| {
+            let mut arenas = arenas.borrow_mut();
+            let index = match arenas.iter().position(|(t, _)| *t == TypeId::of::<Annot>()) {
+                Some(index) => index,
+                None => {
+                    arenas.push((TypeId::of::<Annot>(), Box::new(Arena::<Annot>::new())));
+                    arenas.len() - 1
+                }
+            };
+            let arena = arenas[index].1.downcast_mut::<Arena<Annot>>().unwrap();
+            arena.intern(hash, exp)
+        })
+    }
+
+    /// Returns the expression the handle points to, copying only its top node.
+    pub fn unwrap_or_clone(exp: ExpRef<Annot>) -> Exp<Annot> {
+        match Rc::try_unwrap(exp.0) {
+            Ok(entry) => entry.exp,
+            Err(rc) => rc.exp.clone(),
+        }
+    }
+
+    /// Rewrites the expression with the given function. A shared expression is copied, and its handle kept if the
+    /// copy is left unchanged.
+    pub fn map(self, f: impl FnOnce(Exp<Annot>) -> Exp<Annot>) -> ExpRef<Annot> {
+        match Rc::try_unwrap(self.0) {
+            Ok(entry) => ExpRef::new(f(entry.exp)),
+            Err(rc) => match f(rc.exp.clone()) {
+                exp if exp == rc.exp => ExpRef(rc),
+                exp => ExpRef::new(exp),
+            },
+        }
+    }
+}
+
+impl<Annot> ExpRef<Annot> {
+    /// Checks if two expressions are equivalent, ignoring annotations. Handles to the same node are equal, and
+    /// expressions with different shapes are not, without visiting their subtrees.
+    pub fn eq_ignore_annot(&self, other: &ExpRef<Annot>) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+            || (self.shape() == other.shape() && self.0.exp.eq_ignore_annot(&other.0.exp))
+    }
+
+    /// Checks if the identifier occurs free in the expression, without visiting its subtrees.
+    pub fn uses_id(&self, id: &str) -> bool {
+        match &self.0.ids {
+            Some(ids) => ids.binary_search_by(|i| i.as_str().cmp(id)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Checks if this is the only handle to the expression.
+    pub fn is_unique(&self) -> bool {
+        Rc::strong_count(&self.0) == 1
+    }
+
+    /// Returns the shape of the expression, computing it the first time it is needed.
+    fn shape(&self) -> u64 {
+        match self.0.shape.get() {
+            Some(shape) => shape,
+            None => {
+                let shape = shape(&self.0.exp);
+                self.0.shape.set(Some(shape));
+                shape
+            }
+        }
+    }
+}
+
+impl<Annot: Internable> Arena<Annot> {
+    fn new() -> Arena<Annot> {
+        Arena {
+            entries: HashMap::default(),
+            swept: 0,
+        }
+    }
+
+    fn intern(&mut self, hash: u64, exp: Exp<Annot>) -> ExpRef<Annot> {
+        if let Some(entry) = self.entries.get(&hash).and_then(Weak::upgrade) {
+            if entry.exp == exp {
+                return ExpRef(entry);
+            }
+        }
+
+        let entry = Rc::new(Entry {
+            shape: Cell::new(None),
+            ids: ids(&exp),
+            hash,
+            exp,
+        });
+        self.entries.insert(hash, Rc::downgrade(&entry));
+
+        // Sweeping whenever the arena doubles keeps the cost of freed entries amortized constant.
+        if self.entries.len() > 2 * self.swept.max(1024) {
+            self.entries.retain(|_, e| e.strong_count() > 0);
+            self.swept = self.entries.len();
+        }
+
+        ExpRef(entry)
+    }
+}
+
+/// Identifiers occurring free in an expression, sorted, or `None` if there are none. Expressions share the identifiers
+/// of their children whenever they are the same, which they are along chains of applications or bindings.
+type Ids = Option<Rc<[String]>>;
+
+/// Returns the identifiers occurring free in the expression, from the ones of its children.
+fn ids<Annot>(exp: &Exp<Annot>) -> Ids {
+    fn pat_ids<Annot>(pat: &Pat<Annot>) -> Ids {
+        match pat {
+            Pat::Union(exp) => exp.0.ids.clone(),
+            Pat::Any => None,
+        }
+    }
+
+    match &exp.0 {
+        Node::Identifier(id) => Some(Rc::from([id.clone()])),
+        Node::Symbol(_) | Node::Abort => None,
+        Node::Union { lhs, rhs } => union(&lhs.0.ids, &rhs.0.ids),
+        Node::Match { exp, arms } => arms.iter().fold(exp.0.ids.clone(), |ids, arm| {
+            let arm_ids = without(&arm.exp.0.ids, &arm.catch_ids());
+            union(&union(&ids, &pat_ids(&arm.pat)), &arm_ids)
+        }),
+        // Each binding is in scope in the following ones.
+        Node::Let { exp, binds } => binds
+            .iter()
+            .rev()
+            .fold(exp.0.ids.clone(), |ids, (id, _, exp)| {
+                union(&exp.0.ids, &without(&ids, &[id]))
+            }),
+        Node::Function { arg, exp } => without(&exp.0.ids, &[arg]),
+        Node::Application { func, arg } => union(&func.0.ids, &arg.0.ids),
+        Node::Tuple(tracks) => tracks
+            .iter()
+            .fold(None, |ids, track| union(&ids, &pat_ids(&track.pat))),
+    }
+}
+
+fn union(lhs: &Ids, rhs: &Ids) -> Ids {
+    match (lhs, rhs) {
+        (None, ids) | (ids, None) => ids.clone(),
+        (Some(l), Some(r)) if Rc::ptr_eq(l, r) || l == r => lhs.clone(),
+        (Some(l), Some(r)) => {
+            let mut ids: Vec<String> = l.iter().chain(r.iter()).cloned().collect();
+            ids.sort_unstable();
+            ids.dedup();
+            Some(ids.into())
+        }
+    }
+}
+
+fn without(ids: &Ids, bound: &[&String]) -> Ids {
+    match ids {
+        Some(ids) if bound.iter().any(|id| ids.binary_search(id).is_ok()) => {
+            let ids: Vec<String> = ids
+                .iter()
+                .filter(|id| !bound.contains(id))
+                .cloned()
+                .collect();
+            match ids.is_empty() {
+                true => None,
+                false => Some(ids.into()),
+            }
+        }
+        ids => ids.clone(),
+    }
+}
+
+/// Hashes the expression ignoring its annotations, from the shapes of its children. Unions are equal when they have
+/// the same symbols whatever their structure, and matches when they have the same arms in any order, so their shapes
+/// don't depend on those.
+fn shape<Annot>(exp: &Exp<Annot>) -> u64 {
+    fn pat_shape<Annot>(pat: &Pat<Annot>) -> Option<u64> {
+        match pat {
+            Pat::Union(exp) => Some(exp.shape()),
+            Pat::Any => None,
+        }
+    }
+
+    let mut hasher = FxHasher::default();
+    std::mem::discriminant(&exp.0).hash(&mut hasher);
+    match &exp.0 {
+        Node::Identifier(id) | Node::Symbol(id) => id.hash(&mut hasher),
+        Node::Abort | Node::Union { .. } => {}
+        Node::Match { exp, arms } => {
+            exp.shape().hash(&mut hasher);
+            let mut arms: Vec<_> = arms
+                .iter()
+                .map(|arm| (&arm.catch_id, pat_shape(&arm.pat), arm.exp.shape()))
+                .collect();
+            arms.sort_unstable();
+            arms.dedup();
+            arms.hash(&mut hasher);
+        }
+        Node::Let { exp, binds } => {
+            exp.shape().hash(&mut hasher);
+            for (id, optional, exp) in binds {
+                (id, optional, exp.shape()).hash(&mut hasher);
+            }
+        }
+        Node::Function { arg, exp } => (arg, exp.shape()).hash(&mut hasher),
+        Node::Application { func, arg } => (func.shape(), arg.shape()).hash(&mut hasher),
+        Node::Tuple(tracks) => {
+            for track in tracks {
+                (&track.catch_id, pat_shape(&track.pat)).hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+impl<Annot> Deref for ExpRef<Annot> {
+    type Target = Exp<Annot>;
+
+    fn deref(&self) -> &Exp<Annot> {
+        &self.0.exp
+    }
+}
+
+impl<Annot> Clone for ExpRef<Annot> {
+    fn clone(&self) -> ExpRef<Annot> {
+        ExpRef(self.0.clone())
+    }
+}
+
+impl<Annot: PartialEq> PartialEq for ExpRef<Annot> {
+    fn eq(&self, other: &ExpRef<Annot>) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || (self.0.hash == other.0.hash && self.0.exp == other.0.exp)
+    }
+}
+
+impl<Annot: Eq> Eq for ExpRef<Annot> {}
+
+impl<Annot> Hash for ExpRef<Annot> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash)
+    }
+}
+
+impl<Annot: fmt::Debug> fmt::Debug for ExpRef<Annot> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.exp.fmt(f)
+    }
+}
+
+/// Multiplicative hasher, as used by rustc, which is much faster than the default one on the small keys of nodes.
+#[derive(Default)]
+struct FxHasher(u64);
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(func: Exp<usize>, arg: Exp<usize>, annot: usize) -> Exp<usize> {
+        Exp(
+            Node::Application {
+                func: ExpRef::new(func),
+                arg: ExpRef::new(arg),
+            },
+            annot,
+        )
+    }
+
+    fn id(s: &str, annot: usize) -> Exp<usize> {
+        Exp(Node::Identifier(s.to_owned()), annot)
+    }
+
+    #[test]
+    fn test_interning() {
+        let a = ExpRef::new(app(id("f", 0), id("x", 0), 0));
+        let b = ExpRef::new(app(id("f", 0), id("x", 0), 0));
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+
+        // Equal expressions with different annotations are different nodes, but have the same shape.
+        let c = ExpRef::new(app(id("f", 1), id("x", 1), 1));
+        assert!(a != c);
+        assert!(a.eq_ignore_annot(&c));
+        assert!(!a.eq_ignore_annot(&ExpRef::new(app(id("x", 0), id("f", 0), 0))));
+
+        assert!(a.uses_id("f") && a.uses_id("x"));
+        let func = ExpRef::new(Exp(
+            Node::Function {
+                arg: "x".to_owned(),
+                exp: a,
+            },
+            0,
+        ));
+        assert!(func.uses_id("f") && !func.uses_id("x"));
+    }
+}
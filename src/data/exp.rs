@@ -1,59 +1,73 @@
-use std::collections::HashSet;
+use super::{tuple_symbol, ExpRef, Internable};
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Node<Annot> {
     Identifier(String),
     Symbol(String),
     Abort,
 
     Union {
-        lhs: Box<Exp<Annot>>,
-        rhs: Box<Exp<Annot>>,
+        lhs: ExpRef<Annot>,
+        rhs: ExpRef<Annot>,
     },
 
     Match {
-        exp: Box<Exp<Annot>>,
+        exp: ExpRef<Annot>,
         arms: Vec<Arm<Annot>>,
     },
 
     Let {
-        exp: Box<Exp<Annot>>,
-        binds: Vec<(String, bool, Exp<Annot>)>,
+        exp: ExpRef<Annot>,
+        binds: Vec<(String, bool, ExpRef<Annot>)>,
     },
 
     Function {
         arg: String,
-        exp: Box<Exp<Annot>>,
+        exp: ExpRef<Annot>,
     },
 
     Application {
-        func: Box<Exp<Annot>>,
-        arg: Box<Exp<Annot>>,
+        func: ExpRef<Annot>,
+        arg: ExpRef<Annot>,
     },
+
+    Tuple(Vec<Track<Annot>>),
 }
 
 /// Represents a match arm.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Arm<Annot> {
     pub catch_id: Option<String>,
     pub pat: Pat<Annot>,
-    pub exp: Exp<Annot>,
+    pub exp: ExpRef<Annot>,
+}
+
+/// Represents a component of a tuple symbol. In match patterns, it may match several symbols and capture the one
+/// matched, if the tuple is the whole pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Track<Annot> {
+    pub catch_id: Option<String>,
+    pub pat: Pat<Annot>,
 }
 
 /// Represents a match pattern.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Pat<Annot> {
-    Union(Exp<Annot>),
+    Union(ExpRef<Annot>),
     Any,
 }
 
-/// Represents an expression in the abstract syntax tree.
-#[derive(Debug, Clone, PartialEq)]
+/// Represents an expression in the abstract syntax tree. Its children are interned, see `ExpRef`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Exp<Annot>(pub Node<Annot>, pub Annot);
 
 impl<Annot> Exp<Annot> {
-    /// Checks if two expressions are equivalent, ignoring annotations.
+    /// Checks if two expressions are equivalent, ignoring annotations. Unions are equivalent if they have the same
+    /// symbols, and matches if they have the same arms, in any order.
     pub fn eq_ignore_annot(&self, other: &Exp<Annot>) -> bool {
         match (&self.0, &other.0) {
             (Node::Identifier(id), Node::Identifier(id2)) => id == id2,
@@ -89,17 +103,18 @@ impl<Annot> Exp<Annot> {
                     arms: arms2,
                 },
             ) => {
+                let eq_arm = |arm: &Arm<Annot>, arm2: &Arm<Annot>| {
+                    arm.pat.eq_ignore_annot(&arm2.pat)
+                        && arm.catch_id == arm2.catch_id
+                        && arm.exp.eq_ignore_annot(&arm2.exp)
+                };
                 exp.eq_ignore_annot(exp2)
-                    && arms.iter().all(|arm| {
-                        arms2
-                            .iter()
-                            .find(|arm2| {
-                                arm.pat.eq_ignore_annot(&arm2.pat)
-                                    && arm.catch_id == arm2.catch_id
-                                    && arm.exp.eq_ignore_annot(&arm2.exp)
-                            })
-                            .is_some()
-                    })
+                    && arms
+                        .iter()
+                        .all(|arm| arms2.iter().any(|arm2| eq_arm(arm, arm2)))
+                    && arms2
+                        .iter()
+                        .all(|arm2| arms.iter().any(|arm| eq_arm(arm, arm2)))
             }
             (
                 Node::Let { exp, binds },
@@ -109,10 +124,11 @@ impl<Annot> Exp<Annot> {
                 },
             ) => {
                 exp.eq_ignore_annot(exp2)
+                    && binds.len() == binds2.len()
                     && binds
                         .iter()
                         .zip(binds2.iter())
-                        .all(|(b1, b2)| b1.0 == b2.0 && b1.1 == b1.1 && b1.2.eq_ignore_annot(&b2.2))
+                        .all(|(b1, b2)| b1.0 == b2.0 && b1.1 == b2.1 && b1.2.eq_ignore_annot(&b2.2))
             }
             (
                 Node::Function { arg, exp },
@@ -128,6 +144,13 @@ impl<Annot> Exp<Annot> {
                     arg: arg2,
                 },
             ) => func.eq_ignore_annot(func2) && arg.eq_ignore_annot(arg2),
+            (Node::Tuple(tracks), Node::Tuple(tracks2)) => {
+                tracks.len() == tracks2.len()
+                    && tracks
+                        .iter()
+                        .zip(tracks2.iter())
+                        .all(|(t, t2)| t.catch_id == t2.catch_id && t.pat.eq_ignore_annot(&t2.pat))
+            }
             _ => false,
         }
     }
@@ -138,6 +161,10 @@ impl<Annot> Exp<Annot> {
             Node::Symbol(s) => {
                 set.insert(s.clone());
             }
+            Node::Union { lhs, rhs } => {
+                lhs.collect_symbols(set);
+                rhs.collect_symbols(set);
+            }
             Node::Match { exp, arms } => {
                 exp.collect_symbols(set);
                 for arm in arms {
@@ -160,6 +187,52 @@ impl<Annot> Exp<Annot> {
                 func.collect_symbols(set);
                 arg.collect_symbols(set);
             }
+            Node::Tuple(tracks) => {
+                for track in tracks {
+                    if let Pat::Union(exp) = &track.pat {
+                        exp.collect_symbols(set);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Collects the number of elements of every tuple in the expression, recursively.
+    pub fn collect_tuple_sizes(&self, set: &mut BTreeSet<usize>) {
+        match &self.0 {
+            Node::Union { lhs, rhs } => {
+                lhs.collect_tuple_sizes(set);
+                rhs.collect_tuple_sizes(set);
+            }
+            Node::Match { exp, arms } => {
+                exp.collect_tuple_sizes(set);
+                for arm in arms {
+                    if let Pat::Union(exp) = &arm.pat {
+                        exp.collect_tuple_sizes(set);
+                    }
+                    arm.exp.collect_tuple_sizes(set);
+                }
+            }
+            Node::Let { exp, binds } => {
+                exp.collect_tuple_sizes(set);
+                for (_, _, exp) in binds {
+                    exp.collect_tuple_sizes(set);
+                }
+            }
+            Node::Function { exp, .. } => exp.collect_tuple_sizes(set),
+            Node::Application { func, arg } => {
+                func.collect_tuple_sizes(set);
+                arg.collect_tuple_sizes(set);
+            }
+            Node::Tuple(tracks) => {
+                set.insert(tracks.len());
+                for track in tracks {
+                    if let Pat::Union(exp) = &track.pat {
+                        exp.collect_tuple_sizes(set);
+                    }
+                }
+            }
             _ => (),
         }
     }
@@ -168,55 +241,102 @@ impl<Annot> Exp<Annot> {
     /// The function is first called on every subexpression, and then on the current expression.
     pub fn transform<F>(self, f: &F) -> Exp<Annot>
     where
+        Annot: Internable,
+        F: Fn(Exp<Annot>) -> Exp<Annot>,
+    {
+        f(self.map_children(&|x| x.map(|e| e.transform(f))))
+    }
+
+    /// Recursively transforms the expression like `transform`, but transforms each distinct subexpression only once.
+    /// The results are recorded in the given map, and reused wherever the subexpression occurs again.
+    pub fn transform_shared<F>(
+        self,
+        f: &F,
+        done: &RefCell<HashMap<ExpRef<Annot>, ExpRef<Annot>>>,
+    ) -> Exp<Annot>
+    where
+        Annot: Internable,
         F: Fn(Exp<Annot>) -> Exp<Annot>,
     {
-        f(Exp(
+        f(self.map_children(&|x| {
+            // Expressions which only occur here can't be found again.
+            if x.is_unique() {
+                return x.map(|e| e.transform_shared(f, done));
+            }
+            if let Some(y) = done.borrow().get(&x) {
+                return y.clone();
+            }
+            let y = x.clone().map(|e| e.transform_shared(f, done));
+            done.borrow_mut().insert(x, y.clone());
+            y
+        }))
+    }
+
+    /// Rebuilds the expression with each of its direct subexpressions replaced using the given function.
+    fn map_children<G>(self, g: &G) -> Exp<Annot>
+    where
+        G: Fn(ExpRef<Annot>) -> ExpRef<Annot>,
+    {
+        Exp(
             match self.0 {
                 Node::Union { lhs, rhs } => Node::Union {
-                    lhs: Box::new(lhs.transform(f)),
-                    rhs: Box::new(rhs.transform(f)),
+                    lhs: g(lhs),
+                    rhs: g(rhs),
                 },
 
                 Node::Match {
                     exp: match_exp,
                     arms,
                 } => Node::Match {
-                    exp: Box::new(match_exp.transform(f)),
+                    exp: g(match_exp),
                     arms: arms
                         .into_iter()
                         .map(|arm| Arm {
                             pat: match arm.pat {
-                                Pat::Union(u) => Pat::Union(u.transform(f)),
+                                Pat::Union(u) => Pat::Union(g(u)),
                                 Pat::Any => Pat::Any,
                             },
                             catch_id: arm.catch_id,
-                            exp: arm.exp.transform(f),
+                            exp: g(arm.exp),
                         })
                         .collect(),
                 },
 
                 Node::Let { exp, binds } => Node::Let {
-                    exp: Box::new(exp.transform(f)),
+                    exp: g(exp),
                     binds: binds
                         .into_iter()
-                        .map(|(id, optional, exp)| (id, optional, exp.transform(f)))
+                        .map(|(id, optional, exp)| (id, optional, g(exp)))
                         .collect(),
                 },
 
                 Node::Function { arg, exp: func_exp } => Node::Function {
                     arg,
-                    exp: Box::new(func_exp.transform(f)),
+                    exp: g(func_exp),
                 },
 
                 Node::Application { func, arg } => Node::Application {
-                    func: Box::new(func.transform(f)),
-                    arg: Box::new(arg.transform(f)),
+                    func: g(func),
+                    arg: g(arg),
                 },
 
+                Node::Tuple(tracks) => Node::Tuple(
+                    tracks
+                        .into_iter()
+                        .map(|track| Track {
+                            catch_id: track.catch_id,
+                            pat: match track.pat {
+                                Pat::Union(u) => Pat::Union(g(u)),
+                                Pat::Any => Pat::Any,
+                            },
+                        })
+                        .collect(),
+                ),
+
                 n => n,
             },
             self.1,
-        ))
+        )
     }
 
     /// Collects symbols used in the expression, if its a union expression.
@@ -228,6 +348,29 @@ impl<Annot> Exp<Annot> {
                 true
             }
             Node::Union { lhs, rhs } => lhs.union_to_set(symbols) && rhs.union_to_set(symbols),
+            Node::Tuple(tracks) => {
+                // Every combination of the symbols matched by each track.
+                let mut tuples = vec![Vec::new()];
+                for track in tracks {
+                    let mut set = HashSet::new();
+                    match &track.pat {
+                        Pat::Union(exp) if exp.union_to_set(&mut set) => {}
+                        _ => return false,
+                    }
+                    tuples = tuples
+                        .into_iter()
+                        .flat_map(|t| {
+                            set.iter().map(move |s| {
+                                let mut t = t.clone();
+                                t.push(s.clone());
+                                t
+                            })
+                        })
+                        .collect();
+                }
+                symbols.extend(tuples.iter().map(|t| tuple_symbol(t)));
+                true
+            }
             _ => false,
         }
     }
@@ -235,15 +378,15 @@ impl<Annot> Exp<Annot> {
     /// Generates a union expression from a set of symbols.
     pub fn union_from_set(symbols: &HashSet<String>, annot: &Annot) -> Exp<Annot>
     where
-        Annot: Clone,
+        Annot: Internable,
     {
-        let mut symbols = symbols.iter().map(|s| s.clone());
+        let mut symbols = symbols.iter().cloned();
         let mut exp = Exp(Node::Symbol(symbols.next().unwrap()), annot.clone());
         for symbol in symbols {
             exp = Exp(
                 Node::Union {
-                    lhs: Box::new(exp),
-                    rhs: Box::new(Exp(Node::Symbol(symbol), annot.clone())),
+                    lhs: ExpRef::new(exp),
+                    rhs: ExpRef::new(Exp(Node::Symbol(symbol), annot.clone())),
                 },
                 annot.clone(),
             );
@@ -252,6 +395,28 @@ impl<Annot> Exp<Annot> {
     }
 }
 
+impl<Annot> Arm<Annot> {
+    /// Returns the identifiers bound by the arm: the capture of the whole symbol and the captures of its tracks.
+    pub fn catch_ids(&self) -> Vec<&String> {
+        let tracks = match &self.pat {
+            Pat::Union(exp) => match &exp.0 {
+                Node::Tuple(tracks) => tracks.as_slice(),
+                _ => &[],
+            },
+            Pat::Any => &[],
+        };
+        self.catch_id
+            .iter()
+            .chain(tracks.iter().filter_map(|t| t.catch_id.as_ref()))
+            .collect()
+    }
+
+    /// Checks if the arm binds the given identifier, hiding any outer binding from its expression.
+    pub fn binds(&self, id: &str) -> bool {
+        self.catch_ids().into_iter().any(|c| c == id)
+    }
+}
+
 impl<Annot> Pat<Annot> {
     pub fn eq_ignore_annot(&self, other: &Pat<Annot>) -> bool {
         match (self, other) {
@@ -289,12 +454,12 @@ where
         Node::Abort => writeln!(f, "abort{}", annot),
         Node::Union { lhs, rhs } => {
             writeln!(f, "|{}", annot)?;
-            fmt_expression(f, &lhs, indent + 1)?;
-            fmt_expression(f, &rhs, indent + 1)
+            fmt_expression(f, lhs, indent + 1)?;
+            fmt_expression(f, rhs, indent + 1)
         }
         Node::Match { exp: mexp, arms } => {
             writeln!(f, "match{}", annot)?;
-            fmt_expression(f, &mexp, indent + 1)?;
+            fmt_expression(f, mexp, indent + 1)?;
             for arm in arms.iter() {
                 fmt_indent(f, indent + 1)?;
                 if let Some(id) = &arm.catch_id {
@@ -304,7 +469,7 @@ where
                 }
 
                 match &arm.pat {
-                    Pat::Union(pat) => fmt_expression(f, &pat, indent + 2)?,
+                    Pat::Union(pat) => fmt_expression(f, pat, indent + 2)?,
                     Pat::Any => {
                         fmt_indent(f, indent + 2)?;
                         writeln!(f, "any")?;
@@ -319,21 +484,62 @@ where
             for (id, optional, exp) in binds {
                 fmt_indent(f, indent + 1)?;
                 writeln!(f, "{} {}", id, if *optional { "?" } else { "=" })?;
-                fmt_expression(f, &exp, indent + 2)?;
+                fmt_expression(f, exp, indent + 2)?;
             }
 
             fmt_indent(f, indent)?;
             writeln!(f, "in")?;
-            fmt_expression(f, &body, indent + 1)
+            fmt_expression(f, body, indent + 1)
         }
         Node::Function { arg, exp: body } => {
             writeln!(f, "{}:{}", arg, annot)?;
-            fmt_expression(f, &body, indent + 1)
+            fmt_expression(f, body, indent + 1)
         }
         Node::Application { func, arg } => {
             writeln!(f, "${}", annot)?;
-            fmt_expression(f, &func, indent + 1)?;
-            fmt_expression(f, &arg, indent + 1)
+            fmt_expression(f, func, indent + 1)?;
+            fmt_expression(f, arg, indent + 1)
         }
+        Node::Tuple(tracks) => {
+            writeln!(f, "(){}", annot)?;
+            for track in tracks {
+                fmt_indent(f, indent + 1)?;
+                match &track.catch_id {
+                    Some(id) => writeln!(f, "{} @", id)?,
+                    None => writeln!(f, "_ @")?,
+                }
+                match &track.pat {
+                    Pat::Union(pat) => fmt_expression(f, pat, indent + 2)?,
+                    Pat::Any => {
+                        fmt_indent(f, indent + 2)?;
+                        writeln!(f, "any")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(s: &str) -> Exp<()> {
+        Exp(Node::Symbol(s.to_owned()), ())
+    }
+
+    fn let_exp(optional: bool) -> Exp<()> {
+        let binds = vec![("x".to_owned(), optional, ExpRef::new(symbol("a")))];
+        let exp = ExpRef::new(Exp(Node::Identifier("x".to_owned()), ()));
+        Exp(Node::Let { exp, binds }, ())
+    }
+
+    #[test]
+    fn test_eq_ignore_annot() {
+        assert!(let_exp(true).eq_ignore_annot(&let_exp(true)));
+        assert!(let_exp(false).eq_ignore_annot(&let_exp(false)));
+        assert!(!let_exp(true).eq_ignore_annot(&let_exp(false)));
+        assert!(!let_exp(false).eq_ignore_annot(&let_exp(true)));
     }
 }
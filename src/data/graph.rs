@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+/// Transition of a machine, as seen when joining its states.
+pub(crate) trait Edge: Clone {
+    /// Returns the states the transition leaves from and leads to.
+    fn ends(&self) -> (usize, usize);
+
+    fn set_ends(&mut self, from: usize, to: usize);
+
+    /// Checks if the transition doesn't read, write or move on any tape.
+    fn is_empty(&self) -> bool;
+
+    /// Checks if the transition matches any symbol.
+    fn reads_any(&self) -> bool;
+}
+
+/// Joins the states of a machine linked by empty transitions, removes the states which can't be reached, and, when
+/// a way to merge two transitions is given, replaces the states with one incoming and one outgoing transition by a
+/// single transition. Returns the new number of states and the transitions, with the reserved states 0, 1 and 2
+/// kept in place.
+///
+/// Every state is checked again when its transitions change, instead of searching the whole machine for the next
+/// change to make, so long chains of states are joined in time proportional to their length.
+pub(crate) fn simplify<T: Edge>(
+    state_count: usize,
+    transitions: Vec<T>,
+    bypass: Option<fn(T, T) -> Option<T>>,
+) -> (usize, Vec<T>) {
+    let mut graph = Graph {
+        transitions: Vec::new(),
+        parent: (0..state_count).collect(),
+        removed: vec![false; state_count],
+        incoming: vec![Vec::new(); state_count],
+        outgoing: vec![Vec::new(); state_count],
+        queue: (0..state_count).collect(),
+        queued: vec![true; state_count],
+    };
+    for t in transitions {
+        graph.push(t);
+    }
+
+    while let Some(state) = graph.queue.pop_front() {
+        graph.queued[state] = false;
+        if !graph.removed[state] && graph.find(state) == state {
+            graph.visit(state, bypass);
+        }
+    }
+
+    // Number the remaining states in order, which keeps the reserved states first.
+    let mut count = 0;
+    let numbers = (0..state_count)
+        .map(|state| {
+            let number = count;
+            if !graph.removed[state] && graph.find(state) == state {
+                count += 1;
+            }
+            number
+        })
+        .collect::<Vec<_>>();
+    let transitions = std::mem::take(&mut graph.transitions)
+        .into_iter()
+        .flatten()
+        .map(|mut t| {
+            let (from, to) = t.ends();
+            t.set_ends(numbers[graph.find(from)], numbers[graph.find(to)]);
+            t
+        })
+        .collect();
+    (count, transitions)
+}
+
+struct Graph<T> {
+    /// Transitions in the order they were added, with `None` for the removed ones.
+    transitions: Vec<Option<T>>,
+    /// State each state was joined into, the smallest state of the ones joined being kept.
+    parent: Vec<usize>,
+    removed: Vec<bool>,
+    /// Indices of the transitions entering and leaving each kept state.
+    incoming: Vec<Vec<usize>>,
+    outgoing: Vec<Vec<usize>>,
+    /// States to check again, as their transitions changed.
+    queue: VecDeque<usize>,
+    queued: Vec<bool>,
+}
+
+impl<T: Edge> Graph<T> {
+    /// Returns the state a state was joined into.
+    fn find(&mut self, mut state: usize) -> usize {
+        while self.parent[state] != state {
+            self.parent[state] = self.parent[self.parent[state]];
+            state = self.parent[state];
+        }
+        state
+    }
+
+    /// Returns the states a transition leaves from and leads to, once joined.
+    fn ends(&mut self, i: usize) -> (usize, usize) {
+        let (from, to) = self.transitions[i].as_ref().unwrap().ends();
+        (self.find(from), self.find(to))
+    }
+
+    fn push(&mut self, t: T) {
+        let (from, to) = t.ends();
+        let (from, to) = (self.find(from), self.find(to));
+        let i = self.transitions.len();
+        self.transitions.push(Some(t));
+        self.outgoing[from].push(i);
+        self.incoming[to].push(i);
+        self.enqueue(from);
+        self.enqueue(to);
+    }
+
+    fn remove(&mut self, i: usize) -> T {
+        let (from, to) = self.ends(i);
+        for (list, state) in [(&mut self.outgoing, from), (&mut self.incoming, to)] {
+            let position = list[state].iter().position(|&j| j == i).unwrap();
+            list[state].swap_remove(position);
+        }
+        self.enqueue(from);
+        self.enqueue(to);
+        self.transitions[i].take().unwrap()
+    }
+
+    fn enqueue(&mut self, state: usize) {
+        if !self.queued[state] {
+            self.queued[state] = true;
+            self.queue.push_back(state);
+        }
+    }
+
+    /// Checks if a state chooses between several transitions which match any symbol, which must not be joined with
+    /// the states they lead to.
+    fn is_fork(&self, state: usize) -> bool {
+        self.outgoing[state]
+            .iter()
+            .filter(|&&i| self.transitions[i].as_ref().unwrap().reads_any())
+            .count()
+            > 1
+    }
+
+    /// Applies the first change which can be made around the state.
+    fn visit(&mut self, state: usize, bypass: Option<fn(T, T) -> Option<T>>) {
+        // Remove dead states.
+        if self.incoming[state].is_empty() && state > 2 {
+            for i in self.outgoing[state].clone() {
+                self.remove(i);
+            }
+            self.removed[state] = true;
+            return;
+        }
+
+        // Remove useless transitions.
+        for i in self.outgoing[state].clone() {
+            let (from, to) = self.ends(i);
+            if from == to && self.transitions[i].as_ref().unwrap().is_empty() {
+                self.remove(i);
+            }
+        }
+
+        // Join the states linked by an empty transition.
+        let linked = self.outgoing[state]
+            .iter()
+            .chain(self.incoming[state].iter())
+            .copied()
+            .filter(|&i| self.transitions[i].as_ref().unwrap().is_empty())
+            .collect::<Vec<_>>();
+        for i in linked {
+            let (from, to) = self.ends(i);
+            if from != to
+                && (self.incoming[to].len() == 1 || self.outgoing[from].len() == 1)
+                && !self.is_fork(from)
+                && to != 1
+                && to != 2
+            {
+                self.join(from, to);
+                return;
+            }
+        }
+
+        // Replace states with only one incoming and outgoing transition by a single transition.
+        if let (Some(bypass), [incoming], [outgoing]) = (
+            bypass,
+            self.incoming[state].as_slice(),
+            self.outgoing[state].as_slice(),
+        ) {
+            let (incoming, outgoing) = (*incoming, *outgoing);
+            if state <= 2 {
+                return;
+            }
+            let t = |graph: &mut Graph<T>, i: usize| {
+                let (from, to) = graph.ends(i);
+                let mut t = graph.transitions[i].clone().unwrap();
+                t.set_ends(from, to);
+                t
+            };
+            let (first, second) = (t(self, incoming), t(self, outgoing));
+            if let Some(t) = bypass(first, second) {
+                // A state which only loops on itself is removed along with its transition.
+                self.remove(incoming);
+                if incoming != outgoing {
+                    self.remove(outgoing);
+                    self.push(t);
+                }
+                self.removed[state] = true;
+            }
+        }
+    }
+
+    /// Joins two states, keeping the smallest one.
+    fn join(&mut self, lhs: usize, rhs: usize) {
+        let (lhs, rhs) = (lhs.min(rhs), lhs.max(rhs));
+        if lhs == rhs {
+            return;
+        }
+        self.parent[rhs] = lhs;
+        let outgoing = std::mem::take(&mut self.outgoing[rhs]);
+        let incoming = std::mem::take(&mut self.incoming[rhs]);
+        self.outgoing[lhs].extend(outgoing);
+        self.incoming[lhs].extend(incoming);
+        self.enqueue(lhs);
+    }
+}
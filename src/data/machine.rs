@@ -1,5 +1,10 @@
+use super::graph::{self, Edge};
+use super::TokenLoc;
+
+use std::collections::{BTreeSet, HashSet};
+
 /// Represents a possible direction for a machine to move in.
-#[derive(Debug, Copy, PartialEq, Clone)]
+#[derive(Debug, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Direction {
     Left,
     Right,
@@ -7,11 +12,57 @@ pub enum Direction {
 }
 
 /// Internal representation of a turing machine transition.
+/// The locations point to the source expressions which generated the transition.
 #[derive(Debug, Clone)]
 pub struct Transition {
     pub from: (usize, Option<String>),
     pub to: (usize, Option<String>),
     pub dir: Direction,
+    pub locs: Vec<TokenLoc>,
+}
+
+impl Transition {
+    /// Makes the transition write the symbol it reads, when it reads a given symbol and writes any.
+    fn keep_symbol(&mut self) {
+        if self.from.1.is_some() && self.to.1.is_none() {
+            self.to.1 = self.from.1.clone();
+        }
+    }
+}
+
+impl Edge for Transition {
+    fn ends(&self) -> (usize, usize) {
+        (self.from.0, self.to.0)
+    }
+
+    fn set_ends(&mut self, from: usize, to: usize) {
+        (self.from.0, self.to.0) = (from, to);
+    }
+
+    fn is_empty(&self) -> bool {
+        (&self.from.1, &self.to.1) == (&None, &None) && self.dir == Direction::Stay
+    }
+
+    fn reads_any(&self) -> bool {
+        self.from.1.is_none()
+    }
+}
+
+#[cfg(test)]
+impl Transition {
+    /// Creates a transition which isn't generated from any source expression, to build machines in tests.
+    pub(crate) fn new(
+        from: (usize, Option<&str>),
+        to: (usize, Option<&str>),
+        dir: Direction,
+    ) -> Self {
+        Self {
+            from: (from.0, from.1.map(|s| s.to_owned())),
+            to: (to.0, to.1.map(|s| s.to_owned())),
+            dir,
+            locs: Vec::new(),
+        }
+    }
 }
 
 /// Internal representation of a turing machine, used by the generator.
@@ -42,125 +93,91 @@ impl Machine {
         self.transitions.push(transition);
     }
 
-    /// Simplifies the machine by removing most transitions with None symbols.
-    pub fn simplify(&mut self) {
-        // Join equivalent states.
-        loop {
-            let mut changed = false;
+    /// Returns the indices of the transitions which may be taken from a state when reading the given symbol.
+    /// Transitions which match the symbol exactly take priority over transitions with a None (any) symbol, so there
+    /// is more than one only if the machine is nondeterministic.
+    pub fn branches(&self, state: usize, symbol: &str) -> Vec<usize> {
+        let outgoing = (0..self.transitions.len()).filter(|&i| self.transitions[i].from.0 == state);
+        self.branches_among(outgoing, symbol)
+    }
 
-            for t in self.transitions.iter_mut() {
-                if t.from.1.is_some() && t.to.1.is_none() {
-                    t.to.1 = t.from.1.clone();
-                    changed = true;
-                }
-            }
+    /// Same as `branches`, but only considering the given transitions, which should all start from the same state.
+    pub fn branches_among<I>(&self, outgoing: I, symbol: &str) -> Vec<usize>
+    where
+        I: Iterator<Item = usize> + Clone,
+    {
+        let exact = outgoing
+            .clone()
+            .filter(|&i| self.transitions[i].from.1.as_deref() == Some(symbol))
+            .collect::<Vec<_>>();
+        match exact.is_empty() {
+            true => outgoing
+                .filter(|&i| self.transitions[i].from.1.is_none())
+                .collect(),
+            false => exact,
+        }
+    }
 
-            if let Some((first, second)) = self.transitions.iter().find_map(|t| {
-                if (&t.from.1, &t.to.1) == (&None, &None)
-                    && t.dir == Direction::Stay
-                    && (self.indeg(t.to.0) == 1 || self.outdeg(t.from.0) == 1)
-                    && t.to.0 != 1
-                    && t.to.0 != 2
-                {
-                    Some((t.from.0, t.to.0))
-                } else {
-                    None
-                }
-            }) {
-                self.merge_states(first, second);
-                changed = true;
-            }
+    /// Checks if the machine never has more than one transition to choose from.
+    pub fn is_deterministic(&self) -> bool {
+        let mut seen = HashSet::new();
+        self.transitions.iter().all(|t| seen.insert(&t.from))
+    }
 
-            // Search states with only one incoming and outgoing transition, and replace them by a single transition.
-            if let Some(state) =
-                (0..self.state_count).find(|&s| self.indeg(s) == 1 && self.outdeg(s) == 1)
-            {
-                // Get the transitions.
-                if let Some(t) = {
-                    let incoming = self.transitions.iter().find(|t| t.to.0 == state).unwrap();
-                    let outgoing = self.transitions.iter().find(|t| t.from.0 == state).unwrap();
-                    Self::merge_transitions(incoming.clone(), outgoing.clone())
-                } {
-                    self.push_transition(t);
-                    self.remove_state(state);
-                    changed = true;
-                }
-            }
+    /// Returns the transitions which may be taken when the tape initially only contains the given symbols.
+    /// Any symbol written by one of these transitions is assumed to be readable everywhere afterwards.
+    pub fn reachable_transitions(&self, symbols: &BTreeSet<String>) -> Vec<&Transition> {
+        let mut symbols = symbols.clone();
+        let mut states = BTreeSet::from([0]);
+        let mut taken = vec![false; self.transitions.len()];
 
-            // Remove useless transitions.
-            let len = self.transitions.len();
-            self.transitions.retain(|t| {
-                (&t.from.1, &t.to.1) != (&None, &None)
-                    || t.dir != Direction::Stay
-                    || t.from.0 != t.to.0
-            });
-            if len != self.transitions.len() {
-                changed = true;
-            }
+        loop {
+            let mut changed = false;
+            for (i, t) in self.transitions.iter().enumerate() {
+                let readable = match &t.from.1 {
+                    Some(s) => symbols.contains(s),
+                    None => true,
+                };
+                if taken[i] || !states.contains(&t.from.0) || !readable {
+                    continue;
+                }
 
-            // Remove dead states.
-            if let Some(state) =
-                (0..self.state_count).find(|&s| self.indeg(s) == 0 && s != 0 && s != 1 && s != 2)
-            {
-                self.remove_state(state);
+                taken[i] = true;
                 changed = true;
+                states.insert(t.to.0);
+                if let Some(s) = &t.to.1 {
+                    symbols.insert(s.clone());
+                }
             }
 
             if !changed {
                 break;
             }
         }
-    }
 
-    /// Removes a state from the machine.
-    fn remove_state(&mut self, state: usize) {
-        assert!(state < self.state_count);
         self.transitions
-            .retain(|t| t.from.0 != state && t.to.0 != state);
-        self.state_count -= 1;
-        self.transitions.iter_mut().for_each(|t| {
-            if t.from.0 > state {
-                t.from.0 -= 1;
-            }
-            if t.to.0 > state {
-                t.to.0 -= 1;
-            }
-        });
-    }
-
-    /// Merges two states.
-    fn merge_states(&mut self, lhs: usize, rhs: usize) {
-        let (lhs, rhs) = if lhs > rhs {
-            (rhs, lhs)
-        } else if lhs == rhs {
-            return;
-        } else {
-            (lhs, rhs)
-        };
-
-        self.transitions.iter_mut().for_each(|t| {
-            if t.from.0 == rhs {
-                t.from.0 = lhs;
-            }
-            if t.to.0 == rhs {
-                t.to.0 = lhs;
-            }
-        });
-
-        self.remove_state(rhs);
-    }
-
-    /// Checks the indegree of a state.
-    fn indeg(&self, state: usize) -> usize {
-        self.transitions.iter().filter(|t| t.to.0 == state).count()
+            .iter()
+            .zip(taken)
+            .filter(|(_, taken)| *taken)
+            .map(|(t, _)| t)
+            .collect()
     }
 
-    /// Checks the outdegree of a state.
-    fn outdeg(&self, state: usize) -> usize {
-        self.transitions
-            .iter()
-            .filter(|t| t.from.0 == state)
-            .count()
+    /// Simplifies the machine by removing most transitions with None symbols.
+    pub fn simplify(&mut self) {
+        for t in self.transitions.iter_mut() {
+            t.keep_symbol();
+        }
+        let transitions = std::mem::take(&mut self.transitions);
+        (self.state_count, self.transitions) = graph::simplify(
+            self.state_count,
+            transitions,
+            Some(|incoming, outgoing| {
+                let mut t = Self::merge_transitions(incoming, outgoing)?;
+                t.keep_symbol();
+                Some(t)
+            }),
+        );
     }
 
     fn merge_transitions(incoming: Transition, outgoing: Transition) -> Option<Transition> {
@@ -168,50 +185,69 @@ impl Machine {
             return None;
         }
 
+        let mut locs = incoming.locs;
+        for loc in outgoing.locs {
+            if !locs.contains(&loc) {
+                locs.push(loc);
+            }
+        }
+
         if incoming.dir == Direction::Stay && outgoing.dir == Direction::Stay {
-            if incoming.to.1 == outgoing.from.1 || outgoing.from.1 == None {
-                if incoming.to.1 == outgoing.to.1 || outgoing.to.1 == None {
+            if incoming.to.1 == outgoing.from.1 || outgoing.from.1.is_none() {
+                if incoming.to.1 == outgoing.to.1 || outgoing.to.1.is_none() {
                     return Some(Transition {
                         from: incoming.from,
                         to: (outgoing.to.0, incoming.to.1),
                         dir: Direction::Stay,
+                        locs,
                     });
                 } else {
                     return Some(Transition {
                         from: incoming.from,
                         to: outgoing.to,
                         dir: Direction::Stay,
+                        locs,
                     });
                 }
             }
         } else if incoming.dir == Direction::Stay {
-            if incoming.to.1 == outgoing.from.1 || outgoing.from.1 == None {
-                if incoming.to.1 == outgoing.to.1 || outgoing.to.1 == None {
+            if incoming.to.1 == outgoing.from.1 || outgoing.from.1.is_none() {
+                if incoming.to.1 == outgoing.to.1 || outgoing.to.1.is_none() {
                     return Some(Transition {
                         from: incoming.from,
                         to: (outgoing.to.0, incoming.to.1),
                         dir: outgoing.dir,
+                        locs,
                     });
                 } else {
                     return Some(Transition {
                         from: incoming.from,
                         to: outgoing.to,
                         dir: outgoing.dir,
+                        locs,
                     });
                 }
             }
-        } else if outgoing.dir == Direction::Stay {
-            if incoming.to.1 == outgoing.from.1 || outgoing.from.1 == None {
-                if incoming.to.1 == outgoing.to.1 || outgoing.to.1 == None {
-                    return Some(Transition {
-                        from: incoming.from,
-                        to: (outgoing.to.0, incoming.to.1),
-                        dir: incoming.dir,
-                    });
-                }
-            }
+        } else if outgoing.dir == Direction::Stay
+            && outgoing.from.1.is_none()
+            && outgoing.to.1.is_none()
+        {
+            // The outgoing transition reads and writes the cell moved to, so it can only be dropped if it does
+            // neither.
+            return Some(Transition {
+                from: incoming.from,
+                to: (outgoing.to.0, incoming.to.1),
+                dir: incoming.dir,
+                locs,
+            });
         }
 
         None
     }
 }
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
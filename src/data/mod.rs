@@ -1,9 +1,18 @@
+mod arena;
 mod exp;
+mod graph;
 mod machine;
+mod multi;
+mod symbol;
+mod target;
 mod token;
 mod types;
 
+pub use arena::*;
 pub use exp::*;
 pub use machine::*;
+pub use multi::*;
+pub use symbol::*;
+pub use target::*;
 pub use token::*;
 pub use types::*;
@@ -0,0 +1,258 @@
+use super::graph::{self, Edge};
+use super::{Direction, Machine, TokenLoc, Transition};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Transition of a machine with several tapes, which reads and writes one symbol and moves once on each tape.
+/// As in single tape transitions, `None` symbols match any symbol and keep the symbol read.
+#[derive(Debug, Clone)]
+pub struct MultiTransition {
+    pub from: (usize, Vec<Option<String>>),
+    pub to: (usize, Vec<Option<String>>),
+    pub dirs: Vec<Direction>,
+    pub locs: Vec<TokenLoc>,
+}
+
+impl Edge for MultiTransition {
+    fn ends(&self) -> (usize, usize) {
+        (self.from.0, self.to.0)
+    }
+
+    fn set_ends(&mut self, from: usize, to: usize) {
+        (self.from.0, self.to.0) = (from, to);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.from.1.iter().all(|s| s.is_none())
+            && self.to.1.iter().all(|s| s.is_none())
+            && self.dirs.iter().all(|&d| d == Direction::Stay)
+    }
+
+    fn reads_any(&self) -> bool {
+        self.from.1.iter().all(|s| s.is_none())
+    }
+}
+
+/// Internal representation of a turing machine with several tapes.
+/// The indices 0, 1 and 2 are reserved for the initial, accepting and rejecting states.
+#[derive(Debug, Clone)]
+pub struct MultiMachine {
+    pub tapes: usize,
+    pub state_count: usize,
+    pub transitions: Vec<MultiTransition>,
+}
+
+impl MultiMachine {
+    /// Create a new machine with the given number of tapes and without any transitions.
+    pub fn new(tapes: usize) -> Self {
+        Self {
+            tapes,
+            state_count: 3,
+            transitions: Vec::new(),
+        }
+    }
+
+    /// Adds a new state to the machine.
+    pub fn push_state(&mut self) -> usize {
+        self.state_count += 1;
+        self.state_count - 1
+    }
+
+    /// Adds a new transition to the machine.
+    pub fn push_transition(&mut self, transition: MultiTransition) {
+        self.transitions.push(transition);
+    }
+
+    /// Adds a transition which only reads, writes and moves on one of the tapes.
+    pub fn push_single(&mut self, tape: usize, t: Transition) {
+        let mut from = vec![None; self.tapes];
+        let mut to = vec![None; self.tapes];
+        let mut dirs = vec![Direction::Stay; self.tapes];
+        from[tape] = t.from.1;
+        to[tape] = t.to.1;
+        dirs[tape] = t.dir;
+
+        self.push_transition(MultiTransition {
+            from: (t.from.0, from),
+            to: (t.to.0, to),
+            dirs,
+            locs: t.locs,
+        });
+    }
+
+    /// Converts a machine with a single tape into a regular machine.
+    pub fn into_single(self) -> Machine {
+        assert_eq!(self.tapes, 1);
+        Machine {
+            state_count: self.state_count,
+            transitions: self
+                .transitions
+                .into_iter()
+                .map(|t| Transition {
+                    from: (t.from.0, t.from.1.into_iter().next().unwrap()),
+                    to: (t.to.0, t.to.1.into_iter().next().unwrap()),
+                    dir: t.dirs[0],
+                    locs: t.locs,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns every symbol read or written by the machine, except the blank.
+    pub fn symbols(&self) -> BTreeSet<String> {
+        self.transitions
+            .iter()
+            .flat_map(|t| t.from.1.iter().chain(t.to.1.iter()))
+            .flatten()
+            .filter(|s| !s.is_empty())
+            .cloned()
+            .collect()
+    }
+
+    /// Finds the transition taken from a state when reading the given symbols, if any. Transitions which match more
+    /// symbols explicitly are preferred, and the first one is taken among equally specific transitions.
+    pub fn find(&self, state: usize, read: &[String]) -> Option<usize> {
+        let matches = |t: &MultiTransition| {
+            t.from.0 == state
+                && t.from
+                    .1
+                    .iter()
+                    .zip(read)
+                    .all(|(f, r)| f.as_ref().is_none_or(|f| f == r))
+        };
+        self.transitions
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| matches(t))
+            .min_by_key(|(i, t)| (t.from.1.iter().filter(|f| f.is_none()).count(), *i))
+            .map(|(i, _)| i)
+    }
+
+    /// Checks if the machine never has more than one transition to choose from.
+    pub fn is_deterministic(&self) -> bool {
+        let mut seen = HashSet::new();
+        self.transitions.iter().all(|t| seen.insert(&t.from))
+    }
+
+    /// Returns the transitions which may be taken when the tapes initially only contain the given symbols.
+    /// Any symbol written by one of these transitions is assumed to be readable everywhere afterwards.
+    pub fn reachable_transitions(&self, symbols: &BTreeSet<String>) -> Vec<&MultiTransition> {
+        let mut symbols = symbols.clone();
+        let mut states = BTreeSet::from([0]);
+        let mut taken = vec![false; self.transitions.len()];
+
+        loop {
+            let mut changed = false;
+            for (i, t) in self.transitions.iter().enumerate() {
+                let readable = t.from.1.iter().flatten().all(|s| symbols.contains(s));
+                if taken[i] || !states.contains(&t.from.0) || !readable {
+                    continue;
+                }
+
+                taken[i] = true;
+                changed = true;
+                states.insert(t.to.0);
+                symbols.extend(t.to.1.iter().flatten().cloned());
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        self.transitions
+            .iter()
+            .zip(taken)
+            .filter(|(_, taken)| *taken)
+            .map(|(t, _)| t)
+            .collect()
+    }
+
+    /// Simplifies the machine by joining states linked by transitions which don't touch any tape.
+    pub fn simplify(&mut self) {
+        for t in self.transitions.iter_mut() {
+            for (from, to) in t.from.1.iter().zip(t.to.1.iter_mut()) {
+                if from.is_some() && to.is_none() {
+                    *to = from.clone();
+                }
+            }
+        }
+        let transitions = std::mem::take(&mut self.transitions);
+        (self.state_count, self.transitions) = graph::simplify(self.state_count, transitions, None);
+    }
+
+    /// Minimizes the machine by merging the states which take the same transitions to equivalent states, and
+    /// removing the transitions which become duplicates.
+    pub fn minimize(&mut self) {
+        // Start with the accepting and rejecting states apart from the others, and split the classes of states until
+        // every state of a class has the same transitions, up to the classes of the states they lead to.
+        let mut classes = (0..self.state_count)
+            .map(|s| if s == 1 || s == 2 { s } else { 0 })
+            .collect::<Vec<_>>();
+        loop {
+            let mut signatures = vec![BTreeSet::new(); self.state_count];
+            for t in self.transitions.iter() {
+                signatures[t.from.0].insert((&t.from.1, &t.to.1, &t.dirs, classes[t.to.0]));
+            }
+
+            let mut ids = HashMap::new();
+            let refined = (0..self.state_count)
+                .map(|s| {
+                    let next = ids.len();
+                    *ids.entry((classes[s], &signatures[s])).or_insert(next)
+                })
+                .collect::<Vec<_>>();
+            let done = ids.len() == classes.iter().collect::<HashSet<_>>().len();
+            classes = refined;
+            if done {
+                break;
+            }
+        }
+
+        // Replace each state by the first state of its class. The reserved states come first, so they keep their
+        // indices.
+        let mut states = HashMap::new();
+        let renamed = classes
+            .iter()
+            .map(|c| {
+                let next = states.len();
+                *states.entry(c).or_insert(next)
+            })
+            .collect::<Vec<_>>();
+        self.state_count = states.len();
+
+        let mut transitions: Vec<MultiTransition> = Vec::new();
+        let mut indices = HashMap::<_, usize>::new();
+        for mut t in std::mem::take(&mut self.transitions) {
+            t.from.0 = renamed[t.from.0];
+            t.to.0 = renamed[t.to.0];
+            let key = (t.from.clone(), t.to.clone(), t.dirs.clone());
+            match indices.get(&key) {
+                Some(&i) => {
+                    let locs = &mut transitions[i].locs;
+                    for loc in t.locs {
+                        if !locs.contains(&loc) {
+                            locs.push(loc);
+                        }
+                    }
+                }
+                None => {
+                    indices.insert(key, transitions.len());
+                    transitions.push(t);
+                }
+            }
+        }
+        self.transitions = transitions;
+    }
+}
+
+impl From<Machine> for MultiMachine {
+    fn from(machine: Machine) -> Self {
+        let mut m = MultiMachine::new(1);
+        m.state_count = machine.state_count;
+        for t in machine.transitions {
+            m.push_single(0, t);
+        }
+        m
+    }
+}
@@ -0,0 +1,80 @@
+/// Returns the symbol for a tuple of symbols, such as `(1,x)`. Tuples made only of blanks are blank, so that blank
+/// cells are blank on every track.
+pub fn tuple_symbol(parts: &[String]) -> String {
+    if parts.iter().all(|p| p.is_empty()) {
+        String::new()
+    } else {
+        format!("({})", parts.join(","))
+    }
+}
+
+/// Splits a tuple symbol with the given number of tracks into its parts, if it is one.
+pub fn tuple_parts(symbol: &str, tracks: usize) -> Option<Vec<String>> {
+    if symbol.is_empty() {
+        return Some(vec![String::new(); tracks]);
+    }
+
+    let inner = symbol.strip_prefix('(')?.strip_suffix(')')?;
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            ',' if depth == 0 => parts.push(std::mem::take(&mut part)),
+            '(' | ')' => {
+                depth += if c == '(' { 1 } else { -1 };
+                part.push(c);
+            }
+            c => part.push(c),
+        }
+    }
+    parts.push(part);
+
+    match parts.len() == tracks {
+        true => Some(parts),
+        false => None,
+    }
+}
+
+/// Returns every tuple symbol with the given number of tracks, each holding one of the given symbols, except the
+/// blank.
+pub fn tuple_symbols(symbols: &[String], tracks: usize) -> Vec<String> {
+    let mut tuples = vec![Vec::new()];
+    for _ in 0..tracks {
+        tuples = tuples
+            .into_iter()
+            .flat_map(|t: Vec<String>| {
+                symbols.iter().map(move |s| {
+                    let mut t = t.clone();
+                    t.push(s.clone());
+                    t
+                })
+            })
+            .collect();
+    }
+    tuples
+        .iter()
+        .map(|t| tuple_symbol(t))
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuple_symbols() {
+        let parts = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(tuple_symbol(&parts(&["1", "x"])), "(1,x)");
+        assert_eq!(tuple_symbol(&parts(&["", ""])), "");
+        assert_eq!(tuple_parts("(1,)", 2), Some(parts(&["1", ""])));
+        assert_eq!(tuple_parts("((0,1),x)", 2), Some(parts(&["(0,1)", "x"])));
+        assert_eq!(tuple_parts("", 3), Some(parts(&["", "", ""])));
+        assert_eq!(tuple_parts("(1,x)", 3), None);
+        assert_eq!(tuple_parts("1", 2), None);
+
+        let tuples = tuple_symbols(&parts(&["", "1"]), 2);
+        assert_eq!(tuples, vec!["(,1)", "(1,)", "(1,1)"]);
+    }
+}
@@ -0,0 +1,32 @@
+/// Kind of tape assumed by the exported machine.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TapeKind {
+    /// Infinite in both directions.
+    TwoWay,
+    /// Infinite only to the right, with a marker on the leftmost cell.
+    OneWay,
+}
+
+/// Conventions followed by the simulator for which machines are exported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// Symbol used for blank cells.
+    pub blank: String,
+    pub tape: TapeKind,
+    /// Symbol written on the leftmost cell of one-way tapes.
+    pub left_marker: String,
+    /// Symbol written on blank cells of one-way tapes once they are visited, so that the end of the visited part
+    /// of the tape can be found.
+    pub visited_blank: String,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            blank: "_".to_owned(),
+            tape: TapeKind::TwoWay,
+            left_marker: ">".to_owned(),
+            visited_blank: ".".to_owned(),
+        }
+    }
+}
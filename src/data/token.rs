@@ -23,7 +23,7 @@ pub enum Token {
     Catch,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TokenLoc {
     pub line: usize,
     pub col: usize,
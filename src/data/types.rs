@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::fmt;
 
 /// Represents the possible expression types.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Symbol,
     Union,
@@ -22,6 +22,25 @@ pub struct TypeTable {
 }
 
 impl Type {
+    /// Returns the type of a function which takes the given number of tapes, one at a time, and returns a tape.
+    pub fn tape_function(tapes: usize) -> Type {
+        (0..tapes).fold(Type::Tape, |ret, _| Type::Function {
+            arg: Box::new(Type::Tape),
+            ret: Box::new(ret),
+        })
+    }
+
+    /// Returns the number of tapes taken by a function which only takes tapes and returns a tape, if it is one.
+    pub fn tape_function_arity(&self) -> Option<usize> {
+        match self {
+            Type::Function { arg, ret } if **arg == Type::Tape => match &**ret {
+                Type::Tape => Some(1),
+                ret => Some(ret.tape_function_arity()? + 1),
+            },
+            _ => None,
+        }
+    }
+
     /// Returns true if the type contains an Unresolved type (UnresolvedUnion is not considered).
     pub fn is_unresolved_non_union(&self) -> bool {
         match self {
@@ -33,6 +52,15 @@ impl Type {
         }
     }
 
+    /// Returns true if the type contains the Unresolved type with the given id.
+    pub fn contains(&self, id: usize) -> bool {
+        match self {
+            Type::Function { arg, ret } => arg.contains(id) || ret.contains(id),
+            Type::Unresolved(other) => *other == id,
+            _ => false,
+        }
+    }
+
     /// Returns true if the type contains an UnresolvedUnion type.
     pub fn is_unresolved_union(&self) -> bool {
         match self {
@@ -118,6 +146,14 @@ impl TypeTable {
             }
 
             (from @ Type::Function { .. }, Type::Unresolved(to)) => {
+                if from.contains(to) {
+                    return Err(format!(
+                        "Cannot cast {} to {} at {}, as its type would be infinite",
+                        from,
+                        Type::Unresolved(to),
+                        loc
+                    ));
+                }
                 let func = Type::Function {
                     arg: Box::new(self.push()),
                     ret: Box::new(self.push()),
@@ -127,6 +163,14 @@ impl TypeTable {
             }
 
             (Type::Unresolved(from), to @ Type::Function { .. }) => {
+                if to.contains(from) {
+                    return Err(format!(
+                        "Cannot cast {} to {} at {}, as its type would be infinite",
+                        Type::Unresolved(from),
+                        to,
+                        loc
+                    ));
+                }
                 let func = Type::Function {
                     arg: Box::new(self.push()),
                     ret: Box::new(self.push()),
@@ -163,7 +207,7 @@ impl TypeTable {
                 ret: Box::new(self.resolve(ret)),
             },
             Type::Unresolved(id) => {
-                if let Some(resolved) = self.resolved.get(id).map(|t| t.clone()) {
+                if let Some(resolved) = self.resolved.get(id).cloned() {
                     let resolved = self.resolve(&resolved);
                     self.resolved.insert(*id, resolved.clone());
                     resolved
@@ -175,3 +219,42 @@ impl TypeTable {
         }
     }
 }
+
+impl Default for TypeTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infinite_cast() {
+        let loc = TokenLoc {
+            line: 1,
+            col: 1,
+            import: None,
+        };
+        let mut table = TypeTable::new();
+        let a = table.push();
+        let b = table.push();
+        let func = Type::Function {
+            arg: Box::new(a.clone()),
+            ret: Box::new(b),
+        };
+        assert!(func.contains(0) && !func.contains(2));
+
+        assert_eq!(
+            table.cast(&a, &func, &loc),
+            Err(
+                "Cannot cast u0 to (u0 -> u1) at line 1, column 1, as its type would be infinite"
+                    .to_owned()
+            )
+        );
+        assert!(table.cast(&func, &a, &loc).is_err());
+        let c = table.push();
+        assert!(table.cast(&c, &func, &loc).is_ok());
+    }
+}
@@ -1,4 +1,10 @@
-use crate::data::{Direction, Machine};
+use crate::data::{Direction, Machine, Target};
+
+use std::collections::HashSet;
+
+/// Characters with a special meaning in the awmorp format. The emulator always reads '_' as the blank symbol, even
+/// if the machine uses another one.
+const RESERVED: [char; 3] = ['_', ';', '*'];
 
 /// Converts a state to a string compatible with the awmorp format.
 fn convert_state(state: usize) -> String {
@@ -14,15 +20,21 @@ fn convert_state(state: usize) -> String {
 }
 
 /// Converts a symbol to a character compatible with the awmorp format.
-fn convert_symbol(symbol: &Option<String>) -> Result<char, String> {
+fn convert_symbol(symbol: &Option<String>, target: &Target) -> Result<char, String> {
     match symbol {
-        Some(s) if s.is_empty() => Ok('_'),
+        Some(s) if s.is_empty() => convert_blank(&target.blank),
+        Some(s) if *s == target.blank => Err(format!(
+            "Unsupported symbol '{}', reserved for the blank symbol",
+            s
+        )),
         Some(s) if s.len() > 1 => Err(format!(
             "Unsupported symbol '{}', only one character allowed",
             s
         )),
         Some(s) => match s.chars().next().unwrap() {
-            '_' | ';' | '*' => Err(format!("Unsupported symbol '{}', reserved symbol", s)),
+            c if RESERVED.contains(&c) => {
+                Err(format!("Unsupported symbol '{}', reserved symbol", s))
+            }
             c if c.is_whitespace() => Err(format!(
                 "Unsupported symbol '{}', whitespace not allowed",
                 s
@@ -33,6 +45,25 @@ fn convert_symbol(symbol: &Option<String>) -> Result<char, String> {
     }
 }
 
+/// Returns the characters which can't be used by symbols other than the blank.
+pub fn reserved(target: &Target) -> HashSet<char> {
+    target.blank.chars().chain(RESERVED).collect()
+}
+
+/// Formats text as comment lines, which are ignored by the emulator.
+pub fn comment(text: &str) -> String {
+    text.lines().map(|l| format!("; {}\n", l)).collect()
+}
+
+/// Checks that the blank symbol can be used in the awmorp format.
+fn convert_blank(blank: &str) -> Result<char, String> {
+    let mut chars = blank.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c == '_' || !RESERVED.contains(&c) && !c.is_whitespace() => Ok(c),
+        _ => Err(format!("Unsupported blank symbol '{}'", blank)),
+    }
+}
+
 /// Converts a direction to a string compatible with the awmorp format.
 fn convert_direction(dir: Direction) -> &'static str {
     match dir {
@@ -44,7 +75,11 @@ fn convert_direction(dir: Direction) -> &'static str {
 
 /// Exports a turing machine to the format used in the turing machine emulator
 /// at https://github.com/awmorp/turing.
-pub fn export(machine: Machine) -> Result<String, String> {
+pub fn export(machine: Machine, target: &Target) -> Result<String, String> {
+    if !machine.is_deterministic() {
+        return Err("Nondeterministic machines aren't supported, use the jflap format".to_owned());
+    }
+
     let mut result = String::new();
 
     let mut transitions = machine.transitions;
@@ -54,8 +89,8 @@ pub fn export(machine: Machine) -> Result<String, String> {
         result.push_str(&format!(
             "{} {} {} {} {}\n",
             convert_state(t.from.0),
-            convert_symbol(&t.from.1)?,
-            convert_symbol(&t.to.1)?,
+            convert_symbol(&t.from.1, target)?,
+            convert_symbol(&t.to.1, target)?,
             convert_direction(t.dir),
             convert_state(t.to.0),
         ));
@@ -0,0 +1,157 @@
+use crate::data::{Direction, Machine};
+
+use std::collections::{BTreeSet, HashSet};
+
+/// Number of states placed on each row of the layout.
+const COLUMNS: usize = 8;
+/// Distance between neighbouring states in the layout.
+const SPACING: usize = 120;
+
+/// Converts a state to a name compatible with the JFLAP format.
+fn convert_state(state: usize) -> String {
+    match state {
+        0 => "q0".to_owned(),
+        1 => "qAccept".to_owned(),
+        2 => "qReject".to_owned(),
+        _ => format!("q{}", state - 2),
+    }
+}
+
+/// Converts a symbol to a character compatible with the JFLAP format, where blanks are left empty.
+fn convert_symbol(symbol: &str) -> Result<String, String> {
+    let mut chars = symbol.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok(String::new()),
+        (Some(c), None) if reserved().contains(&c) => {
+            Err(format!("Unsupported symbol '{}', reserved symbol", symbol))
+        }
+        (Some(c), None) if c.is_whitespace() => Err(format!(
+            "Unsupported symbol '{}', whitespace not allowed",
+            symbol
+        )),
+        (Some('<'), None) => Ok("&lt;".to_owned()),
+        (Some('>'), None) => Ok("&gt;".to_owned()),
+        (Some('&'), None) => Ok("&amp;".to_owned()),
+        (Some(c), None) => Ok(c.to_string()),
+        _ => Err(format!(
+            "Unsupported symbol '{}', only one character allowed",
+            symbol
+        )),
+    }
+}
+
+/// Returns the characters which can't be used by symbols other than the blank, as JFLAP uses them to match any
+/// symbol or any symbol but one.
+pub fn reserved() -> HashSet<char> {
+    ['~', '!'].into_iter().collect()
+}
+
+/// Converts a direction to a string compatible with the JFLAP format.
+fn convert_direction(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Left => "L",
+        Direction::Right => "R",
+        Direction::Stay => "S",
+    }
+}
+
+/// Formats text as an XML comment, which is ignored by JFLAP.
+pub fn comment(text: &str) -> String {
+    match text.is_empty() {
+        true => String::new(),
+        false => format!("<!--\n{}-->\n", text.replace("--", "- -")),
+    }
+}
+
+/// Exports a turing machine to the JFLAP file format (.jff), which supports nondeterministic machines. JFLAP
+/// accepts the input if any branch reaches the accepting state.
+///
+/// The format has no wildcards, so transitions which match any symbol are expanded for each symbol used by the
+/// machine which isn't matched exactly by another transition of the same state.
+pub fn export(machine: Machine) -> Result<String, String> {
+    let mut result = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n<structure>\n\t<type>turing</type>\n\t<automaton>\n".to_owned();
+
+    for state in 0..machine.state_count {
+        result.push_str(&format!(
+            "\t\t<state id=\"{}\" name=\"{}\">\n\t\t\t<x>{}</x>\n\t\t\t<y>{}</y>\n",
+            state,
+            convert_state(state),
+            SPACING * (state % COLUMNS + 1),
+            SPACING * (state / COLUMNS + 1),
+        ));
+        match state {
+            0 => result.push_str("\t\t\t<initial/>\n"),
+            1 => result.push_str("\t\t\t<final/>\n"),
+            _ => {}
+        }
+        result.push_str("\t\t</state>\n");
+    }
+
+    let symbols = std::iter::once(String::new())
+        .chain(
+            machine
+                .transitions
+                .iter()
+                .flat_map(|t| t.from.1.iter().chain(t.to.1.iter()))
+                .filter(|s| !s.is_empty())
+                .cloned(),
+        )
+        .collect::<BTreeSet<_>>();
+
+    for state in 0..machine.state_count {
+        for read in symbols.iter() {
+            for i in machine.branches(state, read) {
+                let t = &machine.transitions[i];
+                let written = t.to.1.as_ref().unwrap_or(read);
+                result.push_str(&format!(
+                    "\t\t<transition>\n\t\t\t<from>{}</from>\n\t\t\t<to>{}</to>\n\t\t\t{}\n\t\t\t{}\n\t\t\t<move>{}</move>\n\t\t</transition>\n",
+                    state,
+                    t.to.0,
+                    element("read", &convert_symbol(read)?),
+                    element("write", &convert_symbol(written)?),
+                    convert_direction(t.dir),
+                ));
+            }
+        }
+    }
+
+    result.push_str("\t</automaton>\n</structure>\n");
+    Ok(result)
+}
+
+/// Formats an XML element holding a symbol, which is empty for the blank.
+fn element(name: &str, symbol: &str) -> String {
+    match symbol.is_empty() {
+        true => format!("<{}/>", name),
+        false => format!("<{0}>{1}</{0}>", name, symbol),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Transition;
+
+    #[test]
+    fn test_export() {
+        // Forks into two branches, which move in opposite directions over non-blank cells.
+        let mut m = Machine::new();
+        for (dir, to) in [(Direction::Left, 1), (Direction::Right, 2)] {
+            m.push_transition(Transition::new((0, None), (to, None), dir));
+        }
+        m.push_transition(Transition::new(
+            (0, Some("")),
+            (1, Some("<")),
+            Direction::Stay,
+        ));
+
+        let out = export(m).unwrap();
+        assert_eq!(out.matches("<transition>").count(), 3);
+        assert!(out.contains("<read/>\n\t\t\t<write>&lt;</write>\n\t\t\t<move>S</move>"));
+        assert!(out.contains("<read>&lt;</read>\n\t\t\t<write>&lt;</write>\n\t\t\t<move>L</move>"));
+        assert!(out.contains("<read>&lt;</read>\n\t\t\t<write>&lt;</write>\n\t\t\t<move>R</move>"));
+        assert!(out.contains(
+            "<state id=\"1\" name=\"qAccept\">\n\t\t\t<x>240</x>\n\t\t\t<y>120</y>\n\t\t\t<final/>"
+        ));
+    }
+}
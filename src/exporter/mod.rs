@@ -1 +1,3 @@
 pub mod awmorp;
+pub mod jflap;
+pub mod tmsim;
@@ -0,0 +1,181 @@
+use crate::data::{Direction, MultiMachine};
+
+use std::collections::HashSet;
+
+/// Symbol used by the simulator for blank cells.
+const BLANK: char = '_';
+
+/// Maximum number of combinations of symbols checked for a transition, over all states. Each of them may become a
+/// transition of the exported machine, as wildcards are expanded.
+const MAX_READS: usize = 1 << 20;
+
+/// Converts a state to a string compatible with the tmsim format.
+fn convert_state(state: usize) -> String {
+    match state {
+        0 => "q0".to_owned(),
+        1 => "qAccept".to_owned(),
+        2 => "qReject".to_owned(),
+        _ => format!("q{}", state - 2),
+    }
+}
+
+/// Converts a symbol to a character compatible with the tmsim format.
+fn convert_symbol(symbol: &str) -> Result<char, String> {
+    let mut chars = symbol.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok(BLANK),
+        (Some(c), None) if reserved().contains(&c) => {
+            Err(format!("Unsupported symbol '{}', reserved symbol", symbol))
+        }
+        (Some(c), None) if c.is_whitespace() => Err(format!(
+            "Unsupported symbol '{}', whitespace not allowed",
+            symbol
+        )),
+        (Some(c), None) => Ok(c),
+        _ => Err(format!(
+            "Unsupported symbol '{}', only one character allowed",
+            symbol
+        )),
+    }
+}
+
+/// Returns the characters which can't be used by symbols other than the blank.
+pub fn reserved() -> HashSet<char> {
+    [BLANK, ','].into_iter().collect()
+}
+
+/// Converts a direction to a string compatible with the tmsim format.
+fn convert_direction(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Left => "<",
+        Direction::Right => ">",
+        Direction::Stay => "-",
+    }
+}
+
+/// Exports a turing machine to the format used in the turing machine simulator at
+/// https://turingmachinesimulator.com, which supports machines with several tapes.
+///
+/// The format has no wildcards, so transitions which match any symbol are expanded for each symbol used by the
+/// machine. The reject state has no transitions, which makes the simulator reject the input when reaching it. Machines
+/// with too many combinations of symbols and tapes to expand are rejected.
+pub fn export(machine: &MultiMachine, name: &str) -> Result<String, String> {
+    if !machine.is_deterministic() {
+        return Err("Nondeterministic machines aren't supported, use the jflap format".to_owned());
+    }
+
+    let mut result = format!(
+        "name: {}\ninit: {}\naccept: {}\n",
+        name,
+        convert_state(0),
+        convert_state(1)
+    );
+
+    let symbols = std::iter::once(String::new())
+        .chain(machine.symbols())
+        .collect::<Vec<_>>();
+    for sym in symbols.iter() {
+        convert_symbol(sym)?;
+    }
+
+    let mut states = machine
+        .transitions
+        .iter()
+        .map(|t| t.from.0)
+        .collect::<Vec<_>>();
+    states.sort_unstable();
+    states.dedup();
+
+    let reads = symbols
+        .len()
+        .checked_pow(machine.tapes as u32)
+        .and_then(|n| n.checked_mul(states.len()));
+    if reads.is_none_or(|n| n > MAX_READS) {
+        return Err(format!(
+            "Too many transitions to expand, as the machine uses {} symbols on {} tapes in {} states (at most {} combinations are supported)",
+            symbols.len(),
+            machine.tapes,
+            states.len(),
+            MAX_READS
+        ));
+    }
+
+    for state in states {
+        // Every combination of symbols read on the tapes.
+        let mut reads = vec![Vec::new()];
+        for _ in 0..machine.tapes {
+            reads = reads
+                .into_iter()
+                .flat_map(|r| {
+                    symbols.iter().map(move |s| {
+                        let mut r = r.clone();
+                        r.push(s.clone());
+                        r
+                    })
+                })
+                .collect();
+        }
+
+        for read in reads {
+            let t = match machine.find(state, &read) {
+                Some(i) => &machine.transitions[i],
+                None => continue,
+            };
+            let written =
+                t.to.1
+                    .iter()
+                    .zip(read.iter())
+                    .map(|(w, r)| w.as_ref().unwrap_or(r));
+
+            result.push('\n');
+            result.push_str(&convert_state(state));
+            for sym in read.iter() {
+                result.push_str(&format!(",{}", convert_symbol(sym)?));
+            }
+            result.push('\n');
+            result.push_str(&convert_state(t.to.0));
+            for sym in written {
+                result.push_str(&format!(",{}", convert_symbol(sym)?));
+            }
+            for dir in t.dirs.iter() {
+                result.push_str(&format!(",{}", convert_direction(*dir)));
+            }
+            result.push('\n');
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Transition;
+
+    #[test]
+    fn test_export() {
+        // Writes one of many symbols on the first tape, depending on the symbol read.
+        let machine = |tapes, symbols: u32| {
+            let mut m = MultiMachine::new(tapes);
+            for c in ('a'..).take(symbols as usize) {
+                m.push_single(
+                    0,
+                    Transition {
+                        from: (0, Some(c.to_string())),
+                        to: (1, Some(c.to_ascii_uppercase().to_string())),
+                        dir: Direction::Right,
+                        locs: Vec::new(),
+                    },
+                );
+            }
+            m
+        };
+
+        let out = export(&machine(2, 2), "test").unwrap();
+        assert_eq!(out.matches("\nq0,").count(), 10);
+        assert!(out.contains("\nq0,a,_\nqAccept,A,_,>,-\n"));
+
+        let err = export(&machine(4, 26), "test").unwrap_err();
+        assert!(err.starts_with("Too many transitions to expand"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Files which programs can import, by their path relative to the directory of the main file. They are shared with
+/// the thread which runs the compiler.
+pub trait FileSystem: Sync {
+    /// Returns the contents of the file at the given path, or None if there is no such file.
+    fn read(&self, path: &Path) -> Option<String>;
+}
+
+/// Files stored in a directory on disk.
+#[derive(Debug, Clone)]
+pub struct Directory(pub PathBuf);
+
+impl FileSystem for Directory {
+    fn read(&self, path: &Path) -> Option<String> {
+        std::fs::read_to_string(self.0.join(path)).ok()
+    }
+}
+
+/// Files stored in memory, with paths such as `lib/iter.tmc` as keys.
+impl FileSystem for HashMap<String, String> {
+    fn read(&self, path: &Path) -> Option<String> {
+        let key = path
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?
+            .join("/");
+        self.get(&key).cloned()
+    }
+}
+
+/// File system without any files, for programs which can only import the standard library.
+#[derive(Debug, Clone, Copy)]
+pub struct NoFiles;
+
+impl FileSystem for NoFiles {
+    fn read(&self, _: &Path) -> Option<String> {
+        None
+    }
+}
+
+/// File system which records the path of every file read from it, whether it exists or not, to find the files which a
+/// program imports.
+#[derive(Debug, Default)]
+pub struct Recorded<F> {
+    pub files: F,
+    paths: Mutex<Vec<PathBuf>>,
+}
+
+impl<F> Recorded<F> {
+    pub fn new(files: F) -> Self {
+        Self {
+            files,
+            paths: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the paths read since the last call, in the order they were first read.
+    pub fn take(&self) -> Vec<PathBuf> {
+        let mut paths = std::mem::take(&mut *self.paths.lock().unwrap());
+        let mut seen = std::collections::HashSet::new();
+        paths.retain(|p| seen.insert(p.clone()));
+        paths
+    }
+}
+
+impl<F: FileSystem> FileSystem for Recorded<F> {
+    fn read(&self, path: &Path) -> Option<String> {
+        self.paths.lock().unwrap().push(path.to_path_buf());
+        self.files.read(path)
+    }
+}
@@ -0,0 +1,48 @@
+/// Number of spaces each tab in the indentation of a line stands for.
+const TAB_WIDTH: usize = 4;
+
+/// Normalises the whitespace of a program without changing its tokens: tabs in the indentation of each line are
+/// replaced with spaces, trailing whitespace is removed, runs of blank lines are merged into one, and the file ends
+/// with a single new line. This isn't a pretty printer, so lines are neither reindented nor reflowed, and comments
+/// are kept as they are.
+pub fn format(src: &str) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in src.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+
+        let code = line.trim_start();
+        let indent = line[..line.len() - code.len()]
+            .chars()
+            .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+            .sum::<usize>();
+        out.push_str(&" ".repeat(indent));
+        out.push_str(code);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let src = "\n\n# Flips a bit.  \nlet\n\tflip = x: match x {\n\t  '0' > '1',\t\n\n\n\t  '1' > '0',\n\t},\nin\n\n";
+        let formatted = format(src);
+        assert_eq!(
+            formatted,
+            "# Flips a bit.\nlet\n    flip = x: match x {\n      '0' > '1',\n\n      '1' > '0',\n    },\nin\n"
+        );
+        assert_eq!(format(&formatted), formatted);
+    }
+}
@@ -1,208 +1,427 @@
 use crate::annotater::Annot;
-use crate::data::{Direction, Exp, Machine, Node, Pat, Transition};
+use crate::data::{Direction, Exp, Machine, MultiMachine, Node, Pat, Transition};
 
 use std::collections::{HashMap, HashSet};
 
-/// Generates a turing machine from an expression which evaluates to a tape -> tape function.
-pub fn generate(ast: Exp<Annot>) -> Machine {
-    let mut m = Machine::new();
-    assert!(generate_function(&ast, &mut m, 0, 1, &HashMap::new()));
-    m.simplify();
-    m
+/// Tape on which the result of an expression ends up, once the transitions generated for it have been taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Output {
+    /// The transitions never reach the destination state, as they halt, abort or jump into a recursive function.
+    Never,
+    Tape(usize),
+    /// The tape depends on the path taken.
+    Mixed,
 }
 
-/// Generates a turing machine from a function.
-pub fn generate_function(
+impl Output {
+    /// Returns the output of an expression which may take the path of either output.
+    fn join(self, other: Output) -> Output {
+        match (self, other) {
+            (Output::Never, o) | (o, Output::Never) => o,
+            (Output::Tape(a), Output::Tape(b)) if a == b => Output::Tape(a),
+            _ => Output::Mixed,
+        }
+    }
+}
+
+/// Tapes bound to each tape identifier in scope.
+type Tapes = HashMap<String, usize>;
+/// Entry state of each recursive function in scope, and the tapes it was entered with.
+type Rec = HashMap<String, (usize, Vec<usize>)>;
+
+/// Generates a turing machine from an expression which evaluates to a tape -> tape function. Unless the machine is
+/// simplified, each expression keeps its own states and transitions.
+pub fn generate(ast: Exp<Annot>, simplified: bool) -> Result<Machine, String> {
+    let mut m = generate_raw(ast, 1)?.into_single();
+    if simplified {
+        m.simplify();
+    }
+    Ok(m)
+}
+
+/// Generates a turing machine from an expression which evaluates to a function taking the given number of tapes and
+/// returning a tape.
+pub fn generate_multi(
+    ast: Exp<Annot>,
+    tapes: usize,
+    simplified: bool,
+) -> Result<MultiMachine, String> {
+    if tapes == 1 {
+        return Ok(generate(ast, simplified)?.into());
+    }
+
+    let mut m = generate_raw(ast, tapes)?;
+    if simplified {
+        m.simplify();
+    }
+    Ok(m)
+}
+
+fn generate_raw(ast: Exp<Annot>, tapes: usize) -> Result<MultiMachine, String> {
+    let mut m = MultiMachine::new(tapes);
+    let tapes = (0..tapes).collect::<Vec<_>>();
+    generate_function(
+        &ast,
+        &mut m,
+        &tapes,
+        0,
+        1,
+        &mut HashMap::new(),
+        &HashMap::new(),
+    )?;
+    Ok(m)
+}
+
+/// Generates a turing machine from a function applied to the given tapes.
+fn generate_function(
     ast: &Exp<Annot>,
-    m: &mut Machine,
+    m: &mut MultiMachine,
+    tapes: &[usize],
     src: usize,
     dst: usize,
-    rec: &HashMap<String, usize>,
-) -> bool {
-    generate_set(&ast, m, src, dst)
-        || generate_move(&ast, m, src, dst)
-        || generate_halt(&ast, m, src)
-        || generate_y(&ast, m, src, dst, rec)
-        || match &ast.0 {
-            Node::Function { arg, exp } => generate_from_tape(exp, &arg, m, src, dst, rec),
-            Node::Identifier(id) => {
-                m.push_transition(Transition {
-                    from: (src, None),
-                    to: (*rec.get(id).unwrap(), None),
-                    dir: Direction::Stay,
-                });
-                true
+    env: &mut Tapes,
+    rec: &Rec,
+) -> Result<Output, String> {
+    if let [tape] = *tapes {
+        let out = generate_set(ast, m, tape, src, dst)
+            .or_else(|| generate_move(ast, m, tape, src, dst))
+            .or_else(|| generate_halt(ast, m, tape, src));
+        if let Some(out) = out {
+            return Ok(out);
+        }
+    }
+
+    if let Some(out) = generate_either(ast, m, tapes, src, dst, env, rec)? {
+        return Ok(out);
+    }
+    if let Some(out) = generate_y(ast, m, tapes, src, dst, env, rec)? {
+        return Ok(out);
+    }
+
+    match &ast.0 {
+        Node::Function { arg, exp } => {
+            // The argument is bound in place and the tape it hides restored afterwards, as copying the tapes in scope
+            // for every function would take time quadratic in the length of `let` chains.
+            let hidden = env.insert(arg.clone(), tapes[0]);
+            let out = match &tapes[1..] {
+                [] => generate_from_tape(exp, m, src, dst, env, rec),
+                rest => generate_function(exp, m, rest, src, dst, env, rec),
+            };
+            match hidden {
+                Some(tape) => env.insert(arg.clone(), tape),
+                None => env.remove(arg),
+            };
+            out
+        }
+        Node::Identifier(id) => {
+            let (state, rec_tapes) = rec.get(id).ok_or_else(|| {
+                format!(
+                    "Function {} used at {} isn't known when generating the machine",
+                    id, ast.1 .1
+                )
+            })?;
+            if rec_tapes != tapes {
+                return Err(format!(
+                    "Recursive function at {} must receive its tapes in the same order",
+                    ast.1 .1
+                ));
+            }
+            jump(m, src, *state, ast);
+            Ok(Output::Never)
+        }
+        Node::Application { func, arg } => {
+            // Partial application of a function which takes several tapes.
+            let s = m.push_state();
+            match generate_from_tape(arg, m, src, s, env, rec)? {
+                Output::Tape(tape) => {
+                    let tapes = std::iter::once(tape)
+                        .chain(tapes.iter().copied())
+                        .collect::<Vec<_>>();
+                    generate_function(func, m, &tapes, s, dst, env, rec)
+                }
+                out => mixed(out, arg),
             }
-            Node::Abort => true,
-            _ => false,
         }
+        Node::Abort => Ok(Output::Never),
+        _ => Err(format!(
+            "Expression at {} can't be applied to tapes when generating the machine",
+            ast.1 .1
+        )),
+    }
 }
 
-/// Generates a turing machine from an expression knowing that an identifier is a tape.
+/// Generates a turing machine from a tape expression.
 fn generate_from_tape(
     ast: &Exp<Annot>,
-    id: &str,
-    m: &mut Machine,
+    m: &mut MultiMachine,
     src: usize,
     dst: usize,
-    rec: &HashMap<String, usize>,
-) -> bool {
+    env: &mut Tapes,
+    rec: &Rec,
+) -> Result<Output, String> {
     match &ast.0 {
-        Node::Identifier(id2) => {
-            assert_eq!(id, id2);
-            m.push_transition(Transition {
-                from: (src, None),
-                to: (dst, None),
-                dir: Direction::Stay,
-            });
-            true
-        }
-        Node::Abort => true,
-        _ => {
-            generate_application(ast, id, m, src, dst, rec)
-                || generate_match(ast, id, m, src, dst, rec)
+        Node::Identifier(id) => {
+            let tape = env.get(id).ok_or_else(|| {
+                format!(
+                    "Tape {} used at {} isn't bound to any tape when generating the machine",
+                    id, ast.1 .1
+                )
+            })?;
+            jump(m, src, dst, ast);
+            Ok(Output::Tape(*tape))
         }
+        Node::Abort => Ok(Output::Never),
+        _ => match generate_application(ast, m, src, dst, env, rec)? {
+            Some(out) => Ok(out),
+            None => generate_match(ast, m, src, dst, env, rec)?.ok_or_else(|| {
+                format!(
+                    "Expression at {} doesn't evaluate to a tape when generating the machine",
+                    ast.1 .1
+                )
+            }),
+        },
     }
 }
 
 /// Generates a turing machine from an application of a tape -> tape function.
 fn generate_application(
     ast: &Exp<Annot>,
-    id: &str,
-    m: &mut Machine,
+    m: &mut MultiMachine,
     src: usize,
     dst: usize,
-    rec: &HashMap<String, usize>,
-) -> bool {
+    env: &mut Tapes,
+    rec: &Rec,
+) -> Result<Option<Output>, String> {
     match &ast.0 {
         Node::Application { func, arg } => {
             let s = m.push_state();
-            generate_from_tape(arg, id, m, src, s, rec) && generate_function(func, m, s, dst, rec)
+            match generate_from_tape(arg, m, src, s, env, rec)? {
+                Output::Tape(tape) => {
+                    generate_function(func, m, &[tape], s, dst, env, rec).map(Some)
+                }
+                out => mixed(out, arg).map(Some),
+            }
         }
-        _ => false,
+        _ => Ok(None),
     }
 }
 
 /// Generates a turing machine from a match expression.
 fn generate_match(
     ast: &Exp<Annot>,
-    id: &str,
-    m: &mut Machine,
+    m: &mut MultiMachine,
     src: usize,
     dst: usize,
-    rec: &HashMap<String, usize>,
-) -> bool {
+    env: &mut Tapes,
+    rec: &Rec,
+) -> Result<Option<Output>, String> {
     match &ast.0 {
         Node::Match { exp, arms } => {
-            assert!(matches!(&exp.0, Node::Identifier(id2) if id == id2));
             let s = m.push_state();
-            assert!(generate_from_tape(exp, id, m, src, s, rec));
+            let tape = match generate_from_tape(exp, m, src, s, env, rec)? {
+                Output::Tape(tape) => tape,
+                Output::Never => return Ok(Some(Output::Never)),
+                Output::Mixed => {
+                    return Err(format!(
+                        "The tape matched at {} depends on the path taken",
+                        exp.1 .1
+                    ))
+                }
+            };
 
+            let mut out = Output::Never;
             for arm in arms {
                 let mut symbols = HashSet::new();
-                match &arm.pat {
-                    Pat::Union(u) => assert!(u.union_to_set(&mut symbols)),
-                    _ => unreachable!(),
+                let constant = match &arm.pat {
+                    Pat::Union(u) => u.union_to_set(&mut symbols),
+                    Pat::Any => false,
                 };
+                if !constant {
+                    return Err(format!(
+                        "Match arm at {} doesn't have a constant set of symbols as its pattern",
+                        arm.exp.1 .1
+                    ));
+                }
 
                 if !symbols.is_empty() {
                     let a = m.push_state();
                     for sym in symbols {
-                        m.push_transition(Transition {
-                            from: (s, Some(sym.clone())),
-                            to: (a, Some(sym)),
-                            dir: Direction::Stay,
-                        });
+                        m.push_single(
+                            tape,
+                            Transition {
+                                from: (s, Some(sym.clone())),
+                                to: (a, Some(sym)),
+                                dir: Direction::Stay,
+                                locs: vec![arm.exp.1 .1.clone()],
+                            },
+                        );
                     }
-                    assert!(generate_from_tape(&arm.exp, id, m, a, dst, rec));
+                    out = out.join(generate_from_tape(&arm.exp, m, a, dst, env, rec)?);
                 }
             }
 
-            true
+            Ok(Some(out))
         }
-        _ => false,
+        _ => Ok(None),
     }
 }
 
 /// Generates a turing machine from a set function.
-fn generate_set(func: &Exp<Annot>, m: &mut Machine, src: usize, dst: usize) -> bool {
-    if let Node::Application { func, arg } = &func.0 {
+fn generate_set(
+    ast: &Exp<Annot>,
+    m: &mut MultiMachine,
+    tape: usize,
+    src: usize,
+    dst: usize,
+) -> Option<Output> {
+    if let Node::Application { func, arg } = &ast.0 {
         match (&func.0, &arg.0) {
             (Node::Identifier(func), Node::Symbol(s)) if func == "set" => {
-                m.push_transition(Transition {
-                    from: (src, None),
-                    to: (dst, Some(s.clone())),
-                    dir: Direction::Stay,
-                });
-                true
+                m.push_single(
+                    tape,
+                    Transition {
+                        from: (src, None),
+                        to: (dst, Some(s.clone())),
+                        dir: Direction::Stay,
+                        locs: vec![ast.1 .1.clone()],
+                    },
+                );
+                Some(Output::Tape(tape))
             }
-            _ => false,
+            _ => None,
         }
     } else {
-        false
+        None
     }
 }
 
 /// Generates a turing machine from a next/prev function.
-fn generate_move(func: &Exp<Annot>, m: &mut Machine, src: usize, dst: usize) -> bool {
-    match &func.0 {
+fn generate_move(
+    ast: &Exp<Annot>,
+    m: &mut MultiMachine,
+    tape: usize,
+    src: usize,
+    dst: usize,
+) -> Option<Output> {
+    match &ast.0 {
         Node::Identifier(func) if func == "prev" || func == "next" => {
-            m.push_transition(Transition {
-                from: (src, None),
-                to: (dst, None),
-                dir: if func == "prev" {
-                    Direction::Left
-                } else {
-                    Direction::Right
+            m.push_single(
+                tape,
+                Transition {
+                    from: (src, None),
+                    to: (dst, None),
+                    dir: if func == "prev" {
+                        Direction::Left
+                    } else {
+                        Direction::Right
+                    },
+                    locs: vec![ast.1 .1.clone()],
                 },
-            });
-            true
+            );
+            Some(Output::Tape(tape))
         }
-        _ => false,
+        _ => None,
     }
 }
 
 /// Generates a turing machine from a halt expression.
-fn generate_halt(func: &Exp<Annot>, m: &mut Machine, src: usize) -> bool {
-    match &func.0 {
+fn generate_halt(
+    ast: &Exp<Annot>,
+    m: &mut MultiMachine,
+    tape: usize,
+    src: usize,
+) -> Option<Output> {
+    match &ast.0 {
         Node::Identifier(func) if func == "accept" || func == "reject" => {
-            m.push_transition(Transition {
-                from: (src, None),
-                to: (if func == "accept" { 1 } else { 2 }, None),
-                dir: Direction::Stay,
-            });
-            true
+            m.push_single(
+                tape,
+                Transition {
+                    from: (src, None),
+                    to: (if func == "accept" { 1 } else { 2 }, None),
+                    dir: Direction::Stay,
+                    locs: vec![ast.1 .1.clone()],
+                },
+            );
+            Some(Output::Never)
+        }
+        _ => None,
+    }
+}
+
+/// Generates a turing machine from a nondeterministic choice between two functions, which forks into a branch for
+/// each of them.
+fn generate_either(
+    ast: &Exp<Annot>,
+    m: &mut MultiMachine,
+    tapes: &[usize],
+    src: usize,
+    dst: usize,
+    env: &mut Tapes,
+    rec: &Rec,
+) -> Result<Option<Output>, String> {
+    if let Node::Application { func, arg: second } = &ast.0 {
+        if let Node::Application { func, arg: first } = &func.0 {
+            if matches!(&func.0, Node::Identifier(func) if func == "either") {
+                let mut out = Output::Never;
+                for branch in [first, second] {
+                    let s = m.push_state();
+                    jump(m, src, s, branch);
+                    out = out.join(generate_function(branch, m, tapes, s, dst, env, rec)?);
+                }
+                return Ok(Some(out));
+            }
         }
-        _ => false,
     }
+
+    Ok(None)
 }
 
 /// Generates a turing machine from a Y combinator expression.
 fn generate_y(
-    func: &Exp<Annot>,
-    m: &mut Machine,
+    ast: &Exp<Annot>,
+    m: &mut MultiMachine,
+    tapes: &[usize],
     src: usize,
     dst: usize,
-    rec: &HashMap<String, usize>,
-) -> bool {
-    if let Node::Application { func, arg } = &func.0 {
-        match (&func.0, &arg.0) {
-            (Node::Identifier(func), Node::Function { arg: rec_id, exp }) if func == "Y" => {
+    env: &mut Tapes,
+    rec: &Rec,
+) -> Result<Option<Output>, String> {
+    if let Node::Application { func, arg } = &ast.0 {
+        if let (Node::Identifier(func), Node::Function { arg: rec_id, exp }) = (&func.0, &arg.0) {
+            if func == "Y" {
                 let s = m.push_state();
-                m.push_transition(Transition {
-                    from: (src, None),
-                    to: (s, None),
-                    dir: Direction::Stay,
-                });
+                jump(m, src, s, ast);
 
                 let mut rec = rec.clone();
-                rec.insert(rec_id.clone(), s);
-                assert!(generate_function(exp, m, s, dst, &rec));
-                true
+                rec.insert(rec_id.clone(), (s, tapes.to_vec()));
+                return generate_function(exp, m, tapes, s, dst, env, &rec).map(Some);
             }
-            _ => false,
         }
-    } else {
-        false
+    }
+
+    Ok(None)
+}
+
+/// Adds a transition which doesn't touch any tape.
+fn jump(m: &mut MultiMachine, src: usize, dst: usize, ast: &Exp<Annot>) {
+    m.push_single(
+        0,
+        Transition {
+            from: (src, None),
+            to: (dst, None),
+            dir: Direction::Stay,
+            locs: vec![ast.1 .1.clone()],
+        },
+    );
+}
+
+/// Handles tape arguments which don't end up on a single tape.
+fn mixed(out: Output, arg: &Exp<Annot>) -> Result<Output, String> {
+    match out {
+        Output::Never => Ok(Output::Never),
+        _ => Err(format!(
+            "The tape passed as argument at {} depends on the path taken",
+            arg.1 .1
+        )),
     }
 }
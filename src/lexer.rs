@@ -1,11 +1,30 @@
 use crate::data::{Token, TokenLoc};
+use crate::files::FileSystem;
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-struct State<'a, 'b> {
-    dir: Option<&'a std::path::Path>,
-    lib: &'b HashMap<String, String>,
+/// File system from which imports are read, along with the directory of the file being tokenized in it.
+pub type Files<'a> = Option<(&'a dyn FileSystem, &'a Path)>;
+
+/// Tokens of imported files, by their path, along with the hash of the contents they were tokenized from. The cache can
+/// be kept between compilations, so that only the imports which changed are tokenized again, replacing their old tokens.
+#[derive(Debug, Default)]
+pub struct ImportCache(Mutex<HashMap<String, (u64, Arc<Lexed>)>>);
+
+/// Tokens of a single file, along with the files it imports and the position of their tokens.
+#[derive(Debug)]
+struct Lexed {
+    toks: Vec<(Token, TokenLoc)>,
+    imports: Vec<(usize, String, TokenLoc)>,
+}
+
+struct State {
     toks: Vec<(Token, TokenLoc)>,
+    imports: Vec<(usize, String, TokenLoc)>,
     loc: TokenLoc,
     acc: String,
     in_quotes: bool,
@@ -38,43 +57,129 @@ const KEYWORDS: [(&str, &Token); 4] = [
 
 /// Converts a string into a vector of tokens.
 /// Any import expression is replaced with the contents of the file.
-/// Default libraries can be added by adding them to the `libs` map, which can then be imported by their key.
+/// Default libraries can be added by adding them to the `libs` map, which can then be imported by their key. Other
+/// files are searched for in the given file system, relative to the directory of the importing file.
 pub fn tokenize(
     src: &str,
-    dir: Option<&std::path::Path>,
+    files: Files,
     lib: &HashMap<String, String>,
     import_name: Option<String>,
 ) -> Result<Vec<(Token, TokenLoc)>, String> {
-    let mut state = State::new(dir, lib, import_name);
+    tokenize_with(src, files, lib, import_name, &ImportCache::default())
+}
+
+/// Converts a string into a vector of tokens, like `tokenize`, taking the tokens of imported files from the cache.
+pub fn tokenize_with(
+    src: &str,
+    files: Files,
+    lib: &HashMap<String, String>,
+    import_name: Option<String>,
+    cache: &ImportCache,
+) -> Result<Vec<(Token, TokenLoc)>, String> {
+    let lexed = lex(src, import_name)?;
+    let mut toks = Vec::new();
+    expand(&lexed, files, lib, cache, &mut Vec::new(), &mut toks)?;
+    Ok(toks)
+}
+
+impl ImportCache {
+    /// Returns the number of imported files in the cache.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Returns true if no imported file is in the cache.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Returns the tokens of an imported file, tokenizing it if it isn't in the cache yet.
+    fn get(&self, src: &str, import_name: &str) -> Result<Arc<Lexed>, String> {
+        let mut hasher = DefaultHasher::new();
+        src.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        match self.0.lock().unwrap().get(import_name) {
+            Some((h, lexed)) if *h == hash => return Ok(lexed.clone()),
+            _ => {}
+        }
+        let lexed = Arc::new(lex(src, Some(import_name.to_owned()))?);
+        self.0
+            .lock()
+            .unwrap()
+            .insert(import_name.to_owned(), (hash, lexed.clone()));
+        Ok(lexed)
+    }
+}
+
+// Tokenizes a single file, leaving its imports to be expanded.
+fn lex(src: &str, import_name: Option<String>) -> Result<Lexed, String> {
+    let mut state = State::new(import_name);
     for chr in src.chars() {
         state.push(chr)?;
     }
     state.consume()?;
-    Ok(state.toks)
+    Ok(Lexed {
+        toks: state.toks,
+        imports: state.imports,
+    })
 }
 
-/// Loads a string from a file and runs tokenize() on it.
-pub fn tokenize_from_file(
-    path: &std::path::Path,
+// Appends the tokens of a file to the output, replacing each import with the tokens of the imported file.
+// The paths of the files being imported are kept to find cyclic imports.
+fn expand(
+    lexed: &Lexed,
+    files: Files,
     lib: &HashMap<String, String>,
-    import_name: Option<String>,
-) -> Result<Vec<(Token, TokenLoc)>, String> {
-    let src = std::fs::read_to_string(path)
-        .map_err(|e| format!("Couldn't tokenize file '{}': {}", path.to_str().unwrap(), e))?;
-    tokenize(&src, path.parent(), lib, import_name)
+    cache: &ImportCache,
+    importing: &mut Vec<PathBuf>,
+    out: &mut Vec<(Token, TokenLoc)>,
+) -> Result<(), String> {
+    let mut start = 0;
+    for (at, path, loc) in &lexed.imports {
+        out.extend_from_slice(&lexed.toks[start..*at]);
+        start = *at;
+
+        let import = |src: &str, files: Files, id: PathBuf, importing: &mut Vec<PathBuf>, out| {
+            if importing.contains(&id) {
+                return Err(format!("Cyclic import of file {} at {}", path, loc));
+            }
+            // Tokens are located by the path of the file relative to the main file, so that it can be read again.
+            let lexed = cache.get(src, &id.to_string_lossy())?;
+            importing.push(id);
+            expand(&lexed, files, lib, cache, importing, out)?;
+            importing.pop();
+            Ok(())
+        };
+
+        if let Some(src) = lib.get(path) {
+            import(src, None, PathBuf::from(path), importing, out)?;
+            continue;
+        }
+
+        // Try searching for the file in the directory of the current file.
+        let found = files.and_then(|(fs, dir)| {
+            let p = dir.join(path);
+            fs.read(&p).map(|src| (fs, src, p))
+        });
+        match found {
+            Some((fs, src, p)) => {
+                let dir = p.parent().unwrap_or_else(|| Path::new(""));
+                import(&src, Some((fs, dir)), p.clone(), importing, out)?;
+            }
+            None => return Err(format!("Couldn't import file {} at {}", path, loc)),
+        }
+    }
+    out.extend_from_slice(&lexed.toks[start..]);
+    Ok(())
 }
 
-impl<'a, 'b> State<'a, 'b> {
+impl State {
     // Initializes the lexer state.
-    fn new(
-        dir: Option<&'a std::path::Path>,
-        lib: &'b HashMap<String, String>,
-        import_name: Option<String>,
-    ) -> Self {
+    fn new(import_name: Option<String>) -> Self {
         Self {
-            dir,
-            lib,
             toks: Vec::new(),
+            imports: Vec::new(),
             loc: TokenLoc {
                 line: 1,
                 col: 1,
@@ -87,31 +192,6 @@ impl<'a, 'b> State<'a, 'b> {
         }
     }
 
-    // Imports a file and appends the tokens in it to the current program.
-    fn import(&mut self, path: String) -> Result<(), String> {
-        match self.lib.get(&path) {
-            Some(src) => {
-                self.toks
-                    .extend(tokenize(src, None, self.lib, Some(path.clone()))?);
-                Ok(())
-            }
-            None => {
-                // Try searching for the file in the current directory.
-                if let Some(dir) = &self.dir {
-                    let mut p = dir.to_path_buf();
-                    p.push(&path);
-                    if p.exists() {
-                        self.toks
-                            .extend(tokenize_from_file(&p, self.lib, Some(path.clone()))?);
-                        return Ok(());
-                    }
-                }
-
-                Err(format!("Couldn't import file {} at {}", path, self.loc))
-            }
-        }
-    }
-
     // Pushes another character for the lexer to process.
     fn push(&mut self, chr: char) -> Result<(), String> {
         if self.is_comment {
@@ -123,7 +203,8 @@ impl<'a, 'b> State<'a, 'b> {
         } else if self.in_quotes {
             if chr == '\'' {
                 if self.is_import {
-                    self.import(self.acc.clone())?;
+                    self.imports
+                        .push((self.toks.len(), self.acc.clone(), self.loc.clone()));
                     self.is_import = false;
                 } else {
                     self.push_tok(Token::Symbol(self.acc.clone()));
@@ -245,6 +326,65 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_import_cache() {
+        let mut files = HashMap::from([
+            ("a.tmc".to_owned(), "'a' import 'b.tmc'".to_owned()),
+            ("b.tmc".to_owned(), "'b'".to_owned()),
+        ]);
+        let cache = ImportCache::default();
+        let symbols = |files: &HashMap<String, String>| {
+            tokenize_with(
+                "import 'a.tmc' 'c'",
+                Some((files, Path::new(""))),
+                &HashMap::new(),
+                None,
+                &cache,
+            )
+            .map(|toks| {
+                toks.into_iter()
+                    .map(|t| t.0.to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(symbols(&files).unwrap(), ["'a'", "'b'", "'c'"]);
+        assert_eq!(cache.len(), 2);
+
+        // Changes to nested imports are seen even if the importing file didn't change, and replace the old tokens.
+        files.insert("b.tmc".to_owned(), "'d'".to_owned());
+        assert_eq!(symbols(&files).unwrap(), ["'a'", "'d'", "'c'"]);
+        assert_eq!(cache.len(), 2);
+
+        files.insert("b.tmc".to_owned(), "import 'a.tmc'".to_owned());
+        assert_eq!(
+            symbols(&files).unwrap_err(),
+            "Cyclic import of file a.tmc at line 1, column 8, import b.tmc"
+        );
+    }
+
+    #[test]
+    fn test_nested_import_locs() {
+        let files = HashMap::from([
+            (
+                "sub/lib.tmc".to_owned(),
+                "import 'helper.tmc' 'a'".to_owned(),
+            ),
+            ("sub/helper.tmc".to_owned(), "'b'".to_owned()),
+        ]);
+        let imports = tokenize(
+            "import 'sub/lib.tmc'",
+            Some((&files, Path::new(""))),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(_, loc)| loc.import.unwrap())
+        .collect::<Vec<_>>();
+        assert_eq!(imports, ["sub/helper.tmc", "sub/lib.tmc"]);
+    }
+
     #[test]
     fn test_identifiers() {
         let tokens = tokenize("_ a a_ b1 c_0", None, &HashMap::new(), None)
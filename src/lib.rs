@@ -0,0 +1,20 @@
+//! Compiler from a functional language to turing machines.
+//!
+//! The [`pipeline`] module exposes each stage of the compiler, working on in-memory sources whose imports are read
+//! from a [`files::FileSystem`].
+
+pub mod annotater;
+pub mod data;
+pub mod exporter;
+pub mod files;
+pub mod formatter;
+pub mod generator;
+pub mod lexer;
+pub mod parser;
+pub mod pipeline;
+pub mod simplifier;
+pub mod simulator;
+pub mod transformer;
+pub mod validator;
+
+pub use pipeline::{compile, Error, Format, OptLevel, Options, Stage};
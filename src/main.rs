@@ -1,71 +1,308 @@
-use clap::{ArgEnum, ArgGroup, Parser};
+use clap::{ArgEnum, ArgGroup, Args, Parser, Subcommand};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::io::Read;
+use std::path::PathBuf;
+use std::time::Instant;
 
-mod annotater;
-mod data;
-mod exporter;
-mod generator;
-mod lexer;
-mod parser;
-mod simplifier;
-
-macro_rules! load_lib {
-    ($a:expr) => {
-        {
-            let mut lib = HashMap::new();
-            lib.insert($a.to_owned(), include_str!(concat!("../", $a)).to_owned());
-            lib
+use tmc::files::{Directory, FileSystem, Recorded};
+use tmc::pipeline::{self, Desugared, PassManager, Program, Typed};
+use tmc::{data, formatter, simulator};
+
+#[derive(Debug, Clone, ArgEnum)]
+enum Format {
+    /// Turing machine format used at https://github.com/awmorp/turing
+    Awmorp,
+    /// Turing machine format used at https://turingmachinesimulator.com, which supports several tapes
+    Tmsim,
+    /// JFLAP file format (.jff), which supports nondeterministic machines
+    Jflap,
+}
+
+impl From<&Format> for pipeline::Format {
+    fn from(format: &Format) -> Self {
+        match format {
+            Format::Awmorp => pipeline::Format::Awmorp,
+            Format::Tmsim => pipeline::Format::Tmsim,
+            Format::Jflap => pipeline::Format::Jflap,
         }
-    };
+    }
+}
 
-    ($a:expr, $b:expr) => {
-        {
-            let mut lib = load_lib!($a);
-            lib.insert($b.to_owned(), include_str!(concat!("../", $b)).to_owned());
-            lib
+#[derive(Debug, Clone, ArgEnum)]
+enum TapeKind {
+    /// Tape which is infinite in both directions.
+    TwoWay,
+    /// Tape which is only infinite to the right, and whose leftmost cell holds a marker.
+    OneWay,
+}
+
+impl From<&TapeKind> for data::TapeKind {
+    fn from(tape: &TapeKind) -> Self {
+        match tape {
+            TapeKind::TwoWay => data::TapeKind::TwoWay,
+            TapeKind::OneWay => data::TapeKind::OneWay,
         }
-    };
+    }
+}
+
+#[derive(Debug, Clone, ArgEnum)]
+enum OptLevel {
+    /// Keeps the machine close to the source, for debugging and teaching.
+    #[clap(name = "0")]
+    O0,
+    /// Simplifies the program and the machine.
+    #[clap(name = "1")]
+    O1,
+    /// Also merges equivalent states.
+    #[clap(name = "2")]
+    O2,
+    /// Also searches for the smallest machine, which takes longer.
+    #[clap(name = "s")]
+    Os,
+}
 
-    ($a:expr, $($b:tt)*) => {
-        {
-            let mut lib = load_lib!($($b)*);
-            lib.insert($a.to_owned(), include_str!(concat!("../", $a)).to_owned());
-            lib
+impl From<&OptLevel> for pipeline::OptLevel {
+    fn from(level: &OptLevel) -> Self {
+        match level {
+            OptLevel::O0 => pipeline::OptLevel::O0,
+            OptLevel::O1 => pipeline::OptLevel::O1,
+            OptLevel::O2 => pipeline::OptLevel::O2,
+            OptLevel::Os => pipeline::OptLevel::Os,
         }
     }
 }
 
 #[derive(Debug, Clone, ArgEnum)]
-enum Format {
-    /// Turing machine format used at https://github.com/awmorp/turing
-    Awmorp,
+enum TraceFormat {
+    /// One JSON object per step.
+    Jsonl,
+    /// Comma separated values, with a header line.
+    Csv,
+}
+
+#[derive(Debug, Clone, ArgEnum)]
+enum CoverageFormat {
+    /// Source listing with the steps spent on each line.
+    Annotated,
+    /// lcov tracefile, readable by genhtml and most editors.
+    Lcov,
 }
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compiles a program and prints the resulting turing machine.
+    Build {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// The format used to print the resulting turing machine.
+        #[clap(arg_enum, default_value = "awmorp")]
+        format: Format,
+        /// Writes the machine to the given file instead of stdout.
+        #[clap(short, long, value_name = "FILE", parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Prints the warnings found while validating the exported machine: unreachable states, states which get
+        /// stuck on some symbol and wildcard transitions which overlap exact ones.
+        #[clap(long)]
+        validate: bool,
+    },
+    /// Checks a program for type, const and ownership errors, without generating a machine.
+    Check {
+        #[clap(flatten)]
+        args: CompileArgs,
+    },
+    /// Runs the machine on the given input tape and prints the result, detecting infinite loops.
+    Run {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// The input tape.
+        #[clap(long, value_name = "INPUT")]
+        input: String,
+        #[clap(flatten)]
+        sim: SimulationArgs,
+    },
+    /// Runs the test cases written in the comments of a program, as `# test INPUT -> OUTCOME [TAPE]`.
+    Test {
+        #[clap(flatten)]
+        args: CompileArgs,
+        #[clap(flatten)]
+        sim: SimulationArgs,
+    },
+    /// Normalises the whitespace of programs in place.
+    Fmt {
+        /// The files to be formatted.
+        #[clap(required = true, parse(from_os_str))]
+        paths: Vec<PathBuf>,
+        /// Lists the files which aren't formatted instead of formatting them.
+        #[clap(long)]
+        check: bool,
+    },
+    /// Prints the tokens of a program and its syntax tree after each stage of the compiler.
+    Explain {
+        #[clap(flatten)]
+        args: CompileArgs,
+        #[clap(flatten)]
+        trees: Trees,
+    },
+    /// Prints the size of the machine generated for a program.
+    Stats {
+        #[clap(flatten)]
+        args: CompileArgs,
+    },
+    /// Recompiles a program whenever it or a file it imports changes, printing its errors or the size of the machine.
+    Watch {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// Input tapes on which the machine is run after each compilation.
+        #[clap(long, value_name = "INPUT", multiple_values = true)]
+        inputs: Vec<String>,
+        /// Also runs the test cases written in the comments of the program after each compilation.
+        #[clap(long)]
+        test: bool,
+        #[clap(flatten)]
+        sim: SimulationArgs,
+    },
+    /// Starts an interactive debugger which runs the machine on the given input tape.
+    Debug {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// The input tape.
+        #[clap(long, value_name = "INPUT")]
+        input: String,
+    },
+    /// Runs the machine on the given input tape and prints every step taken to stdout.
+    Trace {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// The input tape.
+        #[clap(long, value_name = "INPUT")]
+        input: String,
+        /// The format used to print the execution trace.
+        #[clap(long, arg_enum, default_value = "jsonl")]
+        trace_format: TraceFormat,
+        #[clap(flatten)]
+        sim: SimulationArgs,
+    },
+    /// Runs the machine on each of the given input tapes and reports which parts of the source were executed.
+    Coverage {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// The input tapes.
+        #[clap(long, value_name = "INPUT", multiple_values = true, required = true)]
+        inputs: Vec<String>,
+        /// The format used to print the coverage report.
+        #[clap(long, arg_enum, default_value = "annotated")]
+        coverage_format: CoverageFormat,
+        #[clap(flatten)]
+        sim: SimulationArgs,
+    },
+    /// Measures the steps and tape cells used by the machine on inputs following the given pattern, in which `[...]`
+    /// groups stand for sequences of symbols taken from the brackets, such as `[01]+[01]`.
+    Measure {
+        #[clap(flatten)]
+        args: CompileArgs,
+        /// The pattern followed by the inputs.
+        #[clap(long)]
+        pattern: String,
+        /// The range of lengths of the pattern groups measured, as `MIN..MAX`.
+        #[clap(long, default_value = "1..8", value_name = "RANGE")]
+        sizes: String,
+        /// The maximum number of inputs measured for each size, which are sampled if there are more. Must be at
+        /// least 1.
+        #[clap(long, default_value = "256")]
+        samples: usize,
+        #[clap(flatten)]
+        sim: SimulationArgs,
+    },
+    /// Prints the alphabet inferred from a program, instead of compiling it.
+    InferAlphabet {
+        #[clap(flatten)]
+        args: CompileArgs,
+    },
+}
+
+/// Options of every command which compiles a program.
+#[derive(Args, Clone)]
 #[clap(group(
-    ArgGroup::new("input")
+    ArgGroup::new("source")
         .required(true)
         .args(&["path", "stdin"]),
 ))]
-struct Cli {
+struct CompileArgs {
     /// The path to the file to be compiled.
     #[clap(parse(from_os_str))]
-    path: Option<std::path::PathBuf>,
-    /// The format used to print the resulting turing machine.
-    #[clap(arg_enum, default_value = "awmorp")]
-    format: Format,
-
-    /// The working alphabet of the turing machine.
-    #[clap(short, long, required = true, multiple_values = true)]
-    alphabet: Vec<String>,
-
+    path: Option<PathBuf>,
     /// Should the input be read from stdin instead of a file?
     #[clap(short = 'i', long)]
     stdin: bool,
 
+    /// The working alphabet of the turing machine, if it isn't declared in the source.
+    #[clap(short, long, multiple_values = true)]
+    alphabet: Vec<String>,
+
+    /// The number of tapes taken by the program. Programs with several tapes are simulated on tracks of a single
+    /// tape, unless the format supports several tapes.
+    #[clap(long, default_value = "1")]
+    tapes: usize,
+
+    /// Custom characters used to encode symbols in the exported machine, as `SYMBOL=C`. Symbols which aren't
+    /// valid characters in the chosen format are encoded automatically otherwise.
+    #[clap(long, value_name = "SYMBOL=C", multiple_values = true)]
+    symbol_map: Vec<String>,
+    /// Reduces the machine to one which only uses blanks and ones, by encoding each symbol as a block of cells.
+    #[clap(long)]
+    binary: bool,
+    /// The symbol used for blank cells, both in the exported machine and in input tapes.
+    #[clap(long, default_value = "_")]
+    blank: String,
+    /// The kind of tape assumed by the exported machine.
+    #[clap(long, arg_enum, default_value = "two-way")]
+    tape: TapeKind,
+    /// The symbol written on the leftmost cell of one-way tapes.
+    #[clap(long, default_value = ">")]
+    left_marker: String,
+    /// The symbol written on the visited blank cells of one-way tapes.
+    #[clap(long, default_value = ".")]
+    visited_blank: String,
+
+    /// Prints every expression rewritten by the given simplifier passes, before and after the rewrite.
+    #[clap(long, value_name = "PASS", multiple_occurrences = true)]
+    dump_after: Vec<String>,
+    /// Skips the given simplifier passes.
+    #[clap(long, value_name = "PASS", multiple_occurrences = true)]
+    disable_pass: Vec<String>,
+    /// Prints the time spent on each simplifier pass.
+    #[clap(long)]
+    print_pass_timings: bool,
+    /// Prints the time spent on each stage of the compiler, as soon as it finishes.
+    #[clap(long)]
+    print_stage_timings: bool,
+    /// Checks the invariants of the program after each phase of simplifier passes, reporting the pass which broke
+    /// one. Always enabled in debug builds.
+    #[clap(long)]
+    verify_passes: bool,
+    /// The maximum number of times each phase of simplifier passes is run, while it keeps changing the program.
+    #[clap(long, default_value = "1", value_name = "N")]
+    fixpoint_iterations: usize,
+    /// The optimisation level, from readable machines to minimal ones.
+    #[clap(short = 'O', arg_enum, default_value = "1", value_name = "LEVEL")]
+    opt_level: OptLevel,
+
+    /// Trees printed while compiling, which are only chosen by `explain`.
+    #[clap(skip)]
+    trees: Trees,
+}
+
+/// Trees printed by `explain`. All of them are printed if none is chosen.
+#[derive(Args, Clone, Default)]
+struct Trees {
     /// Should the tokens be printed?
     #[clap(short, long)]
     tokens: bool,
@@ -80,176 +317,782 @@ struct Cli {
     simplified: bool,
 }
 
-fn compile(args: &Cli, lib: &HashMap<String, String>) -> Result<String, String> {
-    // Tokenize input.
-    let toks = if args.stdin {
+/// Options of every command which simulates the machine.
+#[derive(Args)]
+struct SimulationArgs {
+    /// The maximum number of steps taken when running the machine.
+    #[clap(long, default_value = "10000000")]
+    max_steps: usize,
+}
+
+fn compile(
+    args: &CompileArgs,
+    sources: &Sources,
+    format: &Format,
+    validate: bool,
+) -> Result<String, String> {
+    let (alphabet, machine) = build_multi(args, sources)?;
+    let name = match &args.path {
+        Some(path) if !args.stdin => path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        _ => "stdin".to_owned(),
+    };
+
+    let exported = timed(args, "export", || {
+        pipeline::export(machine, &alphabet, &options(args), format.into(), &name)
+    })?;
+    if validate {
+        exported.warnings.iter().for_each(|w| eprintln!("{}", w));
+    }
+    Ok(exported.output)
+}
+
+/// Returns the options passed to the stages of the compiler.
+fn options(args: &CompileArgs) -> pipeline::Options {
+    pipeline::Options {
+        alphabet: args.alphabet.clone(),
+        tapes: args.tapes,
+        binary: args.binary,
+        target: data::Target {
+            blank: args.blank.clone(),
+            tape: (&args.tape).into(),
+            left_marker: args.left_marker.clone(),
+            visited_blank: args.visited_blank.clone(),
+        },
+        symbol_map: args.symbol_map.clone(),
+        passes: pipeline::PassOptions {
+            disabled: args.disable_pass.clone(),
+            dump_after: args.dump_after.clone(),
+            max_iterations: args.fixpoint_iterations,
+            timed: args.print_pass_timings,
+            verified: args.verify_passes || cfg!(debug_assertions),
+        },
+        opt_level: (&args.opt_level).into(),
+    }
+}
+
+/// Reads the source of the program being compiled.
+fn load(args: &CompileArgs) -> Result<String, String> {
+    if args.stdin {
         let mut src = String::new();
-        std::io::stdin().read_to_string(&mut src).unwrap();
-        let dir = std::env::current_dir().ok();
-        lexer::tokenize(&src, dir.as_ref().map(|s| s.as_path()), lib, None)
+        std::io::stdin()
+            .read_to_string(&mut src)
+            .map_err(|e| format!("Couldn't read stdin: {}", e))?;
+        Ok(src)
     } else {
-        lexer::tokenize_from_file(&args.path.as_ref().unwrap(), lib, None)
+        let path = args.path.as_ref().unwrap();
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read file '{}': {}", path.display(), e))
     }
-    .map_err(|e| format!("Lexer error: {}", e))?;
-    if args.tokens {
+}
+
+/// Sources which the program can import: the standard library, and the files next to the program, whose tokens are
+/// kept between compilations.
+struct Sources {
+    lib: HashMap<String, String>,
+    files: Recorded<Directory>,
+    cache: pipeline::ImportCache,
+}
+
+impl Sources {
+    fn new(args: &CompileArgs) -> Self {
+        Self {
+            lib: pipeline::standard_library(),
+            files: Recorded::new(source_dir(args)),
+            cache: pipeline::ImportCache::default(),
+        }
+    }
+}
+
+/// Returns the directory in which imported files are searched for.
+fn source_dir(args: &CompileArgs) -> Directory {
+    Directory(if args.stdin {
+        std::env::current_dir().unwrap_or_default()
+    } else {
+        args.path
+            .as_ref()
+            .and_then(|p| p.parent())
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default()
+    })
+}
+
+/// Parses the program, printing its tokens and abstract syntax tree if requested.
+fn parse(args: &CompileArgs, sources: &Sources, src: &str) -> Result<Program, String> {
+    // Tokenize input, searching for imports next to the input file.
+    let toks = timed(args, "tokenize", || {
+        pipeline::tokenize(src, &sources.files, &sources.lib, &sources.cache)
+    })?;
+    if args.trees.tokens {
         eprintln!("----------- Tokens -----------");
         toks.iter().for_each(|(tok, _)| eprint!("{} ", tok));
-        eprintln!("");
+        eprintln!();
     }
 
     // Parse the tokens and generate the abstract syntax tree.
-    let ast = parser::parse(toks).map_err(|e| format!("Parser error: {}", e))?;
-    if args.parser {
+    let program = timed(args, "parse", || pipeline::parse(toks))?;
+    if args.trees.parser {
         eprintln!("------------ AST -------------");
-        eprintln!("{}", ast);
-        eprintln!("");
+        eprintln!("{}", program.ast);
+        eprintln!();
     }
 
-    // Get the set of symbols used by the AST (including those only used during compilation)
-    let alphabet = HashSet::from_iter(
-        args.alphabet
-            .iter()
-            .map(|s| s.to_owned())
-            .chain(std::iter::once("".to_owned())),
-    );
-    let mut const_alphabet = alphabet.clone();
-    ast.collect_symbols(&mut const_alphabet);
-
-    // Remove unused optional let bindings from the AST.
-    let ast = simplifier::optionals_remover::remove_optionals(ast);
-
-    // Apply initial simplifications on the AST and dedup ids:
-    // - replace let statements with function applications.
-    // - remove match 'any' patterns.
-    // - remove trivial applications.
-    let ast = ast.transform(&simplifier::let_remover::remove_lets);
-    let ast = ast.transform(&|e| {
-        let e = simplifier::any_remover::remove_any(e, &const_alphabet);
-        let e = simplifier::trivial_remover::remove_trivial(e);
-        e
-    });
+    Ok(program)
+}
 
-    let ast = simplifier::id_dedup::dedup_ids(ast);
-    if args.simplified {
+/// Compiles the program, returning its alphabet and the machine generated for it.
+fn build(args: &CompileArgs, sources: &Sources) -> Result<(Vec<String>, data::Machine), String> {
+    let (alphabet, machine) = build_multi(args, sources)?;
+    let machine = timed(args, "lower", || {
+        pipeline::lower(machine, &alphabet, &options(args))
+    })?;
+    Ok((alphabet, machine))
+}
+
+/// Compiles the program, returning its alphabet and the machine generated for it, with one tape per argument.
+fn build_multi(
+    args: &CompileArgs,
+    sources: &Sources,
+) -> Result<(Vec<String>, data::MultiMachine), String> {
+    if args.tapes == 0 {
+        return Err("The program must take at least one tape".to_owned());
+    }
+
+    let src = load(args)?;
+    let program = parse(args, sources, &src)?;
+    let alphabet = pipeline::alphabet(&program, &options(args))?;
+    let machine = generate(args, &alphabet, &program)?;
+    Ok((pipeline::tuple_alphabet(&alphabet, &program.ast), machine))
+}
+
+/// Runs the stages which check the program, without generating a machine.
+fn check(args: &CompileArgs, sources: &Sources) -> Result<(), String> {
+    if args.tapes == 0 {
+        return Err("The program must take at least one tape".to_owned());
+    }
+
+    let src = load(args)?;
+    let program = parse(args, sources, &src)?;
+    let alphabet = pipeline::alphabet(&program, &options(args))?;
+    let passes = pipeline::pass_manager(&options(args))?;
+    type_check(args, &alphabet, &program, &passes)?;
+    Ok(())
+}
+
+/// Runs the stages which check the program, printing the intermediate trees if requested.
+fn type_check(
+    args: &CompileArgs,
+    alphabet: &[String],
+    parsed: &Program,
+    passes: &PassManager,
+) -> Result<Typed, String> {
+    let program = timed(args, "desugar", || {
+        pipeline::desugar(parsed, alphabet, passes)
+    });
+    dump(passes);
+    let program = program?;
+    warn_unused(alphabet, &program);
+    if args.trees.simplified {
         eprintln!("-------- Simplified AST --------");
-        eprintln!("{}", ast);
-        eprintln!("");
-    }
-
-    // Annotate the AST with the type of each expression, and check if match patterns are constant.
-    let ast = annotater::type_checker::type_check(ast)
-        .map_err(|e| format!("Type checker error: {}", e))?;
-    annotater::const_checker::const_check(&ast)
-        .map_err(|e| format!("Const checker error: {}", e))?;
-
-    // Remove all non tape -> tape applications which can be removed before checking ownership rules.
-    fn ownership_transform(
-        e: data::Exp<annotater::Annot>,
-        alphabet: &HashSet<String>,
-    ) -> data::Exp<annotater::Annot> {
-        let rec =
-            |e: data::Exp<annotater::Annot>| e.transform(&|e| ownership_transform(e, alphabet));
-        let e = simplifier::get_remover::remove_gets(e, alphabet);
-        let e = simplifier::match_mover::move_matches(e);
-        let e = simplifier::applier::apply(e, rec);
-        let e = simplifier::trivial_remover::remove_trivial(e);
-        e
-    }
-    let ast = ast.transform(&|e| ownership_transform(e, &alphabet));
-
-    // Check for ownership errors and resolve the types of unions.
-    annotater::ownership_checker::ownership_check(&ast)
-        .map_err(|e| format!("Ownership checker error: {}", e))?;
-    let ast = annotater::union_resolver::resolve_unions(ast);
-    if args.annotated {
+        eprintln!("{}", program.ast);
+        eprintln!();
+    }
+
+    let program = timed(args, "type_check", || {
+        pipeline::type_check(program, args.tapes, passes)
+    });
+    dump(passes);
+    let program = program?;
+    if args.trees.annotated {
         eprintln!("-------- Annotated AST --------");
-        eprintln!("{:#}", ast);
-        eprintln!("");
-    }
-
-    // Simplify the AAST even further:
-    // - remove match captured variables.
-    // - remove non tape -> tape applications
-    // - remove trivial applications
-    // - move matches so that they are at the root of the function expressions.
-    // - match matches with symbols an input.
-    // - remove duplicate match patterns.
-    // - merge matches which are used as arguments to other matches.
-    // - remove get applications, replacing them with match expressions.
-    // - simplify get & set expressions when symbols are already known.
-    // - merge match arms which have equivalent expressions.
-    fn final_transform(
-        e: data::Exp<annotater::Annot>,
-        alphabet: &HashSet<String>,
-    ) -> data::Exp<annotater::Annot> {
-        let rec = |e: data::Exp<annotater::Annot>| e.transform(&|e| final_transform(e, alphabet));
-        let e = simplifier::capture_remover::remove_captures(e, rec);
-        let e = simplifier::applier::apply(e, rec);
-        let e = simplifier::trivial_remover::remove_trivial(e);
-        let e = simplifier::abort_spreader::spread_aborts(e);
-        let e = simplifier::matcher::match_const(e);
-        let e = simplifier::pat_dedup::dedup_patterns(e);
-        let e = simplifier::match_merger::merge_matches(e);
-        let e = simplifier::match_deduper::dedup_matches(e, rec);
-        let e = simplifier::arm_merger::merge_arms(e);
-        e
-    }
-    let ast = ast.transform(&|e| final_transform(e, &alphabet));
-    let ast = ast.transform(&simplifier::arm_merger::merge_arms);
-
-    if args.simplified {
-        eprintln!("-------- Simplified AAST --------");
-        eprintln!("{:#}", ast);
-        eprintln!("");
+        eprintln!("{:#}", program.ast);
+        eprintln!();
     }
+    Ok(program)
+}
 
-    // Generate the turing machine from the AAST:
-    let machine = generator::generate(ast);
+/// Runs the stages which generate the machine, printing the intermediate trees if requested.
+fn generate(
+    args: &CompileArgs,
+    alphabet: &[String],
+    parsed: &Program,
+) -> Result<data::MultiMachine, String> {
+    let options = options(args);
+    let passes = pipeline::pass_manager(&options)?;
+    let program = type_check(args, alphabet, parsed, &passes)?;
 
-    // Export the machine to the desired format.
-    Ok(match args.format {
-        Format::Awmorp => exporter::awmorp::export(machine)
-            .map_err(|e| format!("Awmorp exporter error: {}", e))?,
+    let program = timed(args, "simplify", || pipeline::simplify(program, &passes));
+    dump(&passes);
+    let program = program?;
+    if args.trees.simplified {
+        eprintln!("-------- Simplified AAST --------");
+        eprintln!("{:#}", program.ast);
+        eprintln!();
+    }
+    if args.print_pass_timings {
+        eprintln!("-------- Pass timings --------");
+        eprint!("{}", passes.timings());
+        eprintln!();
+    }
+
+    let machine = timed(args, "generate", || {
+        pipeline::generate(program, args.tapes, options.opt_level)
+    })?;
+    Ok(match options.opt_level {
+        pipeline::OptLevel::Os => timed(args, "search", || {
+            pipeline::search(parsed, alphabet, &options, machine)
+        })?,
+        _ => machine,
     })
 }
 
-fn main() {
-    let args = Cli::parse();
-
-    // Load the standard library files.
-    let lib = load_lib!(
-        "std/bool.tmc",
-        "std/iter.tmc",
-        "std/math.tmc",
-        "std/check.tmc"
+/// Writes the compiled machine to the given file, or to stdout if there's none.
+fn write_output(out: String, path: Option<&std::path::Path>) -> Result<(), String> {
+    match path {
+        Some(path) => std::fs::write(path, out + "\n")
+            .map_err(|e| format!("Couldn't write file '{}': {}", path.display(), e)),
+        None => {
+            println!("{}", out);
+            Ok(())
+        }
+    }
+}
+
+/// Prints the expressions rewritten by the passes whose dumps were requested.
+fn dump(passes: &PassManager) {
+    passes.take_dumps().iter().for_each(|d| eprintln!("{}", d));
+}
+
+/// Runs a stage of the compiler, printing the time it took if requested.
+fn timed<T>(args: &CompileArgs, stage: &str, f: impl FnOnce() -> T) -> T {
+    if !args.print_stage_timings {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    eprintln!("{:>12.3?}  {} stage", start.elapsed(), stage);
+    result
+}
+
+/// Warns about symbols in the alphabet which the program never uses.
+fn warn_unused(alphabet: &[String], program: &Desugared) {
+    for warning in pipeline::unused_symbols(alphabet, program) {
+        eprintln!("{}", warning);
+    }
+}
+
+/// Infers the symbols which the program may read from or write to the tape.
+fn infer_alphabet(args: &CompileArgs, sources: &Sources) -> Result<Vec<String>, String> {
+    if args.tapes != 1 {
+        return Err("Alphabet inference only supports programs with a single tape".to_owned());
+    }
+
+    let src = load(args)?;
+    Ok(pipeline::infer_alphabet(&parse(args, sources, &src)?)?)
+}
+
+/// Formats an inferred alphabet as a declaration which can be pasted into the program. An empty alphabet can't be
+/// declared, as `alphabet ''` would declare the blank symbol, so it is reported instead.
+fn alphabet_declaration(symbols: &[String]) -> String {
+    if symbols.is_empty() {
+        return "The program reads and writes no symbols".to_owned();
+    }
+    let symbols = symbols
+        .iter()
+        .map(|s| format!("'{}'", s))
+        .collect::<Vec<_>>();
+    format!("alphabet {}", symbols.join(" | "))
+}
+
+/// Parses an input tape and encodes it in the same way as the machine.
+fn tape(args: &CompileArgs, alphabet: &[String], input: &str) -> Result<simulator::Tape, String> {
+    Ok(pipeline::tape(input, alphabet, &options(args))?)
+}
+
+fn debug(args: &CompileArgs, sources: &Sources, input: &str) -> Result<(), String> {
+    let (alphabet, machine) = build(args, sources)?;
+    deterministic(&machine)?;
+    let stdin = std::io::stdin();
+    simulator::debugger::debug(
+        &machine,
+        tape(args, &alphabet, input)?,
+        stdin.lock(),
+        std::io::stdout(),
+    )
+    .map_err(|e| format!("Debugger error: {}", e))
+}
+
+fn coverage(
+    args: &CompileArgs,
+    sources: &Sources,
+    inputs: &[String],
+    format: &CoverageFormat,
+    max_steps: usize,
+) -> Result<(), String> {
+    let src = load(args)?;
+    let program = parse(args, sources, &src)?;
+    let lib = &sources.lib;
+    let alphabet = pipeline::alphabet(&program, &options(args))?;
+    let regions = simulator::coverage::regions(&program.ast);
+    let machine = generate(args, &alphabet, &program)?;
+    let machine = pipeline::lower(machine, &alphabet, &options(args))?;
+    deterministic(&machine)?;
+
+    let mut cov = simulator::coverage::Coverage::new(&machine);
+    for input in inputs {
+        let (outcome, steps) = cov.run(tape(args, &alphabet, input)?, max_steps);
+        eprintln!("{}: {} after {} steps", input, outcome, steps);
+    }
+
+    // Show the main file and every imported file which contains a let binding or match arm.
+    let mut sources = vec![simulator::coverage::Source {
+        import: None,
+        path: match &args.path {
+            Some(path) => path.display().to_string(),
+            None => "<stdin>".to_owned(),
+        },
+        text: src,
+    }];
+    let dir = source_dir(args);
+    for r in regions.iter() {
+        let import = match &r.loc.import {
+            Some(import) if !sources.iter().any(|s| s.import.as_ref() == Some(import)) => import,
+            _ => continue,
+        };
+
+        let text = match lib.get(import) {
+            Some(text) => text.clone(),
+            None => dir
+                .read(std::path::Path::new(import))
+                .ok_or(format!("Couldn't read imported file '{}'", import))?,
+        };
+        sources.push(simulator::coverage::Source {
+            import: Some(import.clone()),
+            path: import.clone(),
+            text,
+        });
+    }
+
+    print!(
+        "{}",
+        match format {
+            CoverageFormat::Annotated => cov.annotate(&regions, &sources),
+            CoverageFormat::Lcov => cov.lcov(&regions, &sources),
+        }
+    );
+    Ok(())
+}
+
+fn measure(
+    args: &CompileArgs,
+    sources: &Sources,
+    pattern: &str,
+    sizes: &str,
+    samples: usize,
+    max_steps: usize,
+) -> Result<(), String> {
+    let pattern = simulator::complexity::Pattern::parse(pattern)?;
+    let sizes = simulator::complexity::parse_sizes(sizes)?;
+    let (alphabet, machine) = build(args, sources)?;
+    deterministic(&machine)?;
+
+    let measurements =
+        simulator::complexity::measure(&machine, &pattern, sizes, samples, max_steps, |input| {
+            tape(args, &alphabet, input)
+        })?;
+    print!("{}", simulator::complexity::report(&measurements));
+    Ok(())
+}
+
+fn run(
+    args: &CompileArgs,
+    sources: &Sources,
+    input: &str,
+    max_steps: usize,
+) -> Result<bool, String> {
+    let (alphabet, machine) = build(args, sources)?;
+    simulate(args, &alphabet, &machine, input, max_steps)
+}
+
+/// Runs the machine on an input tape and prints the result, returning whether the input was accepted.
+fn simulate(
+    args: &CompileArgs,
+    alphabet: &[String],
+    machine: &data::Machine,
+    input: &str,
+    max_steps: usize,
+) -> Result<bool, String> {
+    if !machine.is_deterministic() {
+        return search(args, alphabet, machine, input, max_steps);
+    }
+
+    let mut sim = simulator::Simulator::new(machine, tape(args, alphabet, input)?);
+    let outcome = simulator::divergence::run(&mut sim, max_steps);
+
+    match &outcome {
+        simulator::divergence::Outcome::Diverges(d) => {
+            println!("Detected after {} steps: {}", d.step, outcome)
+        }
+        _ => println!("After {} steps: {}", sim.steps(), outcome),
+    }
+    println!("{}", sim.tape());
+
+    Ok(outcome == simulator::divergence::Outcome::Halted(simulator::Status::Accepted))
+}
+
+/// Runs a nondeterministic machine by searching for a branch which accepts the input.
+fn search(
+    args: &CompileArgs,
+    alphabet: &[String],
+    machine: &data::Machine,
+    input: &str,
+    max_steps: usize,
+) -> Result<bool, String> {
+    let search = simulator::search::search(machine, tape(args, alphabet, input)?, max_steps);
+    match &search.branch {
+        Some(branch) => {
+            println!(
+                "After {} steps: {}, {} configurations explored",
+                branch.steps(),
+                search.outcome,
+                search.configurations
+            );
+            println!("{}", branch.tape());
+        }
+        None => println!(
+            "After exploring {} configurations: {}",
+            search.configurations, search.outcome
+        ),
+    }
+
+    Ok(search.outcome == simulator::search::Outcome::Accepted)
+}
+
+/// Checks that a machine only has one branch to follow, as required to simulate it step by step.
+fn deterministic(machine: &data::Machine) -> Result<(), String> {
+    match machine.is_deterministic() {
+        true => Ok(()),
+        false => Err("Nondeterministic machines can only be simulated with run".to_owned()),
+    }
+}
+
+fn trace(
+    args: &CompileArgs,
+    sources: &Sources,
+    input: &str,
+    format: &TraceFormat,
+    max_steps: usize,
+) -> Result<(), String> {
+    let (alphabet, machine) = build(args, sources)?;
+    deterministic(&machine)?;
+    let mut sim = simulator::Simulator::new(&machine, tape(args, &alphabet, input)?);
+    let format = match format {
+        TraceFormat::Jsonl => simulator::tracer::Format::JsonLines,
+        TraceFormat::Csv => simulator::tracer::Format::Csv,
+    };
+
+    let stdout = std::io::stdout();
+    let out = std::io::BufWriter::new(stdout.lock());
+    let status = simulator::tracer::trace(&mut sim, max_steps, format, out)?;
+    eprintln!("Trace finished after {} steps: {:?}", sim.steps(), status);
+    Ok(())
+}
+
+/// Runs the test cases in the comments of the program on its machine, printing the result of each one. Returns
+/// whether every case passed.
+fn test_cases(
+    args: &CompileArgs,
+    alphabet: &[String],
+    machine: &data::Machine,
+    max_steps: usize,
+) -> Result<bool, String> {
+    let cases = simulator::cases::parse(&load(args)?)?;
+    if cases.is_empty() {
+        return Err(
+            "The program has no test cases, written as `# test INPUT -> OUTCOME`".to_owned(),
+        );
+    }
+
+    let mut failed = 0;
+    for case in &cases {
+        let input = tape(args, alphabet, &case.input)?;
+        match simulator::cases::check(machine, case, input, max_steps) {
+            Ok(()) => println!("test at line {}: {} ... ok", case.line, case.input),
+            Err((outcome, tape)) => {
+                failed += 1;
+                println!(
+                    "test at line {}: {} ... FAILED, {} {}",
+                    case.line, case.input, outcome, tape
+                );
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+/// Prints the number of states, transitions and symbols of the machine, and whether it is deterministic.
+fn stats(args: &CompileArgs, sources: &Sources) -> Result<(), String> {
+    let (alphabet, machine) = build(args, sources)?;
+    println!("states:        {}", machine.state_count);
+    println!("transitions:   {}", machine.transitions.len());
+    println!("symbols:       {}", alphabet.len());
+    println!(
+        "deterministic: {}",
+        if machine.is_deterministic() {
+            "yes"
+        } else {
+            "no"
+        }
     );
+    Ok(())
+}
 
-    // Compile with the input arguments and the standard library.
-    std::process::exit(match compile(&args, &lib) {
+/// Formats each file in place, or only lists the ones which aren't formatted. Returns whether every file was already
+/// formatted.
+fn fmt(paths: &[PathBuf], check: bool) -> Result<bool, String> {
+    let mut formatted = true;
+    for path in paths {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read file '{}': {}", path.display(), e))?;
+        let out = formatter::format(&src);
+        if out == src {
+            continue;
+        }
+
+        formatted = false;
+        if check {
+            println!("{}", path.display());
+        } else {
+            std::fs::write(path, out)
+                .map_err(|e| format!("Couldn't write file '{}': {}", path.display(), e))?;
+        }
+    }
+    Ok(formatted || !check)
+}
+
+/// Time between checks for changes to the watched files.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Recompiles the program whenever the main file or a file it imports is modified.
+fn watch(
+    args: &CompileArgs,
+    sources: &Sources,
+    inputs: &[String],
+    test: bool,
+    max_steps: usize,
+) -> Result<(), String> {
+    if args.stdin {
+        return Err("Programs read from stdin can't be watched".to_owned());
+    }
+
+    loop {
+        let start = Instant::now();
+        match build(args, sources) {
+            Ok((alphabet, machine)) => {
+                eprintln!(
+                    "Compiled in {:.3?}: {} states, {} transitions",
+                    start.elapsed(),
+                    machine.state_count,
+                    machine.transitions.len()
+                );
+                for input in inputs {
+                    if let Err(err) = simulate(args, &alphabet, &machine, input, max_steps) {
+                        eprintln!("Running failed: {}", err);
+                    }
+                }
+                if test {
+                    if let Err(err) = test_cases(args, &alphabet, &machine, max_steps) {
+                        eprintln!("Testing failed: {}", err);
+                    }
+                }
+            }
+            Err(err) => eprintln!("Compilation failed: {}", err),
+        }
+
+        // Imports which weren't found are watched as well, in case they are created.
+        let paths = watched_files(args, sources);
+        let modified = || {
+            paths
+                .iter()
+                .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+                .collect::<Vec<_>>()
+        };
+        let before = modified();
+        eprintln!("Watching {} files for changes...", paths.len());
+        while modified() == before {
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+        eprintln!();
+    }
+}
+
+/// Returns the main file and the files it imported since the last call.
+fn watched_files(args: &CompileArgs, sources: &Sources) -> Vec<PathBuf> {
+    let dir = &sources.files.files.0;
+    args.path
+        .iter()
+        .cloned()
+        .chain(sources.files.take().into_iter().map(|p| dir.join(p)))
+        .collect()
+}
+
+/// Prints the error of a command, prefixed by what failed, and returns the exit code of the command.
+fn exit_code(failed: &str, result: Result<i32, String>) -> i32 {
+    match result {
+        Ok(code) => code,
         Err(err) => {
-            eprintln!("Compilation failed: {}", err);
+            eprintln!("{}: {}", failed, err);
             1
         }
-        Ok(output) => {
-            println!("{}", output);
-            0
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    // Every stage of the compiler recurses on the syntax tree, so deeply nested programs need a large stack.
+    let code = pipeline::with_stack(|| execute(&cli.command));
+    std::process::exit(code)
+}
+
+fn execute(command: &Command) -> i32 {
+    match command {
+        Command::Build {
+            args,
+            format,
+            output,
+            validate,
+        } => {
+            let result = compile(args, &Sources::new(args), format, *validate)
+                .and_then(|out| write_output(out, output.as_deref()));
+            exit_code("Compilation failed", result.map(|()| 0))
         }
-    })
+        Command::Check { args } => {
+            exit_code("Check failed", check(args, &Sources::new(args)).map(|()| 0))
+        }
+        Command::Run { args, input, sim } => exit_code(
+            "Running failed",
+            run(args, &Sources::new(args), input, sim.max_steps).map(|accepted| !accepted as i32),
+        ),
+        Command::Test { args, sim } => {
+            let result = build(args, &Sources::new(args)).and_then(|(alphabet, machine)| {
+                test_cases(args, &alphabet, &machine, sim.max_steps)
+            });
+            exit_code("Testing failed", result.map(|passed| !passed as i32))
+        }
+        Command::Fmt { paths, check } => {
+            exit_code("Formatting failed", fmt(paths, *check).map(|ok| !ok as i32))
+        }
+        Command::Explain { args, trees } => {
+            let mut args = args.clone();
+            args.trees = match trees {
+                Trees {
+                    tokens: false,
+                    parser: false,
+                    annotated: false,
+                    simplified: false,
+                } => Trees {
+                    tokens: true,
+                    parser: true,
+                    annotated: true,
+                    simplified: true,
+                },
+                trees => trees.clone(),
+            };
+            exit_code(
+                "Compilation failed",
+                build_multi(&args, &Sources::new(&args)).map(|_| 0),
+            )
+        }
+        Command::Stats { args } => exit_code(
+            "Compilation failed",
+            stats(args, &Sources::new(args)).map(|()| 0),
+        ),
+        Command::Watch {
+            args,
+            inputs,
+            test,
+            sim,
+        } => exit_code(
+            "Watching failed",
+            watch(args, &Sources::new(args), inputs, *test, sim.max_steps).map(|()| 0),
+        ),
+        Command::Debug { args, input } => exit_code(
+            "Debugging failed",
+            debug(args, &Sources::new(args), input).map(|()| 0),
+        ),
+        Command::Trace {
+            args,
+            input,
+            trace_format,
+            sim,
+        } => {
+            let result = trace(
+                args,
+                &Sources::new(args),
+                input,
+                trace_format,
+                sim.max_steps,
+            );
+            exit_code("Tracing failed", result.map(|()| 0))
+        }
+        Command::Coverage {
+            args,
+            inputs,
+            coverage_format,
+            sim,
+        } => {
+            let sources = Sources::new(args);
+            let result = coverage(args, &sources, inputs, coverage_format, sim.max_steps);
+            exit_code("Coverage failed", result.map(|()| 0))
+        }
+        Command::Measure {
+            args,
+            pattern,
+            sizes,
+            samples,
+            sim,
+        } => {
+            let sources = Sources::new(args);
+            let result = measure(args, &sources, pattern, sizes, *samples, sim.max_steps);
+            exit_code("Measuring failed", result.map(|()| 0))
+        }
+        Command::InferAlphabet { args } => {
+            let result = infer_alphabet(args, &Sources::new(args)).map(|symbols| {
+                println!("{}", alphabet_declaration(&symbols));
+                0
+            });
+            exit_code("Alphabet inference failed", result)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Parses the options of `tmc build` followed by the given arguments.
+    fn build_args(args: &[&str]) -> CompileArgs {
+        let args = ["tmc", "build"].iter().chain(args);
+        match Cli::parse_from(args).command {
+            Command::Build { args, .. } => args,
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
-    fn test_compiler_tests() {
-        let lib = load_lib!(
-            "std/bool.tmc",
-            "std/iter.tmc",
-            "std/math.tmc",
-            "std/check.tmc"
-        );
+    fn test_cli() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
 
+    #[test]
+    fn test_compiler_tests() {
         // Compile every program in the tests directory.
         for entry in std::fs::read_dir("tests").unwrap() {
             let entry = entry.unwrap();
@@ -258,26 +1101,25 @@ mod tests {
                 let name = path.file_name().unwrap().to_str().unwrap();
 
                 if name.ends_with(".tmc") {
-                    let args = Cli {
-                        alphabet: ["0", "1", "i", "z", "a", "b"]
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect(),
-                        path: Some(path.clone()),
-                        format: Format::Awmorp,
-                        stdin: false,
-                        tokens: false,
-                        parser: false,
-                        annotated: false,
-                        simplified: false,
-                    };
+                    let args = build_args(&[
+                        path.to_str().unwrap(),
+                        "--alphabet",
+                        "0",
+                        "1",
+                        "i",
+                        "z",
+                        "a",
+                        "b",
+                    ]);
 
+                    let sources = Sources::new(&args);
+                    let result = compile(&args, &sources, &Format::Awmorp, false);
                     if name.contains("fail") {
-                        if compile(&args, &lib).is_ok() {
+                        if result.is_ok() {
                             panic!("Test program {} should have failed!", name);
                         }
                     } else {
-                        if let Err(err) = compile(&args, &lib) {
+                        if let Err(err) = result {
                             panic!(
                                 "Test program {} should have compiled! Instead, got error: {}",
                                 name, err
@@ -288,4 +1130,167 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_panic_corpus() {
+        // Programs which used to make the compiler panic, and which must now either compile or fail with an error
+        // pointing at the source. Single tape programs already panicked before multi-tape programs were added.
+        let expected = [
+            (
+                "captured_pattern_fail_1.tmc",
+                1,
+                Some("Const checker error: Match pattern at line 3, column 35 uses p, which was captured by an outer match arm"),
+            ),
+            (
+                "infinite_type_fail_1.tmc",
+                2,
+                Some("Type checker error: Cannot cast u6 to (u6 -> u7) at line 2, column 11, as its type would be infinite"),
+            ),
+            (
+                "infinite_type_fail_2.tmc",
+                2,
+                Some("Type checker error: Cannot cast u1 to (u1 -> u2) at line 2, column 21, as its type would be infinite"),
+            ),
+            (
+                "mixed_match_fail_1.tmc",
+                2,
+                Some("Generator error: The tape matched at line 2, column 18 depends on the path taken"),
+            ),
+            ("nested_match.tmc", 2, None),
+            (
+                "non_const_pattern_fail_1.tmc",
+                2,
+                Some("Const checker error: Match pattern at line 3, column 5 is not constant"),
+            ),
+            (
+                "non_const_pattern_fail_2.tmc",
+                1,
+                Some("Const checker error: Match pattern at line 2, column 36 is not constant"),
+            ),
+            (
+                "non_const_pattern_fail_3.tmc",
+                1,
+                Some("Const checker error: Match pattern at line 2, column 22 is not constant"),
+            ),
+            (
+                "non_const_pattern_fail_4.tmc",
+                1,
+                Some("Const checker error: Match pattern at line 2, column 68 is not constant"),
+            ),
+            (
+                "non_tape_match_fail_1.tmc",
+                2,
+                Some("Type checker error: Cannot cast (u8 -> u8) to tape at line 2, column 13"),
+            ),
+            (
+                "recursion_order_fail_1.tmc",
+                2,
+                Some("Generator error: Recursive function at line 3, column 11 must receive its tapes in the same order"),
+            ),
+            (
+                "recursion_order_fail_2.tmc",
+                2,
+                Some("Generator error: Recursive function at line 6, column 15 must receive its tapes in the same order"),
+            ),
+        ];
+
+        let mut names = std::fs::read_dir("tests/panics")
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, expected.map(|(name, _, _)| name));
+
+        for (name, tapes, error) in expected {
+            let path = format!("tests/panics/{}", name);
+            let tapes = tapes.to_string();
+            let args = build_args(&[&path, "--tapes", &tapes, "--alphabet", "0", "1"]);
+            let result = compile(&args, &Sources::new(&args), &Format::Awmorp, false);
+            assert_eq!(result.err().as_deref(), error, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        // Type errors are found without generating the machine, which is where write errors are found.
+        let check = |path: &str| {
+            let args = build_args(&[path, "--alphabet", "0", "1", "i", "z", "a", "b"]);
+            check(&args, &Sources::new(&args))
+        };
+        assert!(check("tests/identity.tmc").is_ok());
+        assert!(check("tests/union_fail_1.tmc").is_err());
+        assert!(check("tests/write_fail_1.tmc").is_ok());
+    }
+
+    #[test]
+    fn test_sample_cases() {
+        // Every sample with test cases in its comments passes them.
+        for entry in std::fs::read_dir("samples").unwrap() {
+            let path = entry.unwrap().path();
+            let src = std::fs::read_to_string(&path).unwrap();
+            if simulator::cases::parse(&src).unwrap().is_empty() {
+                continue;
+            }
+
+            let args = build_args(&[path.to_str().unwrap()]);
+            let (alphabet, machine) = build(&args, &Sources::new(&args)).unwrap();
+            let passed = test_cases(&args, &alphabet, &machine, 100000).unwrap();
+            assert!(passed, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_without_match_deduper() {
+        // Matches of a tape inside the arms of a match of the same tape are generated as they are, reading the tape
+        // again, when the match deduper is disabled.
+        let args = build_args(&[
+            "tests/recursive_matches_2.tmc",
+            "--alphabet",
+            "0",
+            "1",
+            "i",
+            "--disable-pass",
+            "match_deduper",
+        ]);
+        let (alphabet, machine) = build(&args, &Sources::new(&args)).unwrap();
+        assert!(test_cases(&args, &alphabet, &machine, 1000).unwrap());
+    }
+
+    #[test]
+    fn test_watched_files() {
+        // Every imported file is watched, except those in the standard library.
+        let args = build_args(&["samples/flip.tmc"]);
+        let sources = Sources::new(&args);
+        build(&args, &sources).unwrap();
+        let paths = watched_files(&args, &sources);
+        assert_eq!(paths[0], std::path::Path::new("samples/flip.tmc"));
+        assert!(paths.len() > 1);
+        assert!(paths.iter().all(|p| p.starts_with("samples")));
+
+        // The tokens of imported files are reused when compiling again.
+        let cached = sources.cache.len();
+        build(&args, &sources).unwrap();
+        assert_eq!(sources.cache.len(), cached);
+        assert_eq!(watched_files(&args, &sources), paths);
+    }
+
+    #[test]
+    fn test_infer_alphabet() {
+        // Symbols which are only used during compilation, such as 'true' and 'false', must be left out.
+        let infer = |path: &str| {
+            let args = build_args(&[path]);
+            infer_alphabet(&args, &Sources::new(&args)).unwrap()
+        };
+        assert_eq!(infer("tests/recursive_matches_2.tmc"), vec!["0", "1"]);
+        assert_eq!(infer("samples/inc.tmc"), vec!["#", "0", "1"]);
+        assert_eq!(
+            alphabet_declaration(&infer("samples/inc.tmc")),
+            "alphabet '#' | '0' | '1'"
+        );
+        assert!(infer("tests/identity.tmc").is_empty());
+        assert_eq!(
+            alphabet_declaration(&infer("tests/identity.tmc")),
+            "The program reads and writes no symbols"
+        );
+    }
 }
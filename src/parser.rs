@@ -1,4 +1,4 @@
-use crate::data::{Arm, Exp, Node, Pat, Token, TokenLoc};
+use crate::data::{Arm, Exp, ExpRef, Node, Pat, Token, TokenLoc, Track};
 
 /// Input accepted by parse_x functions.
 type Stream<'a> = &'a [(Token, TokenLoc)];
@@ -6,17 +6,23 @@ type Stream<'a> = &'a [(Token, TokenLoc)];
 type Result<'a> = std::result::Result<Option<(Stream<'a>, Exp<TokenLoc>)>, String>;
 
 /// Converts a stream of tokens into an abstract syntax tree.
-pub fn parse(toks: Vec<(Token, TokenLoc)>) -> std::result::Result<Exp<TokenLoc>, String> {
-    let (toks, exp) = parse_exp(&toks)?.ok_or(format!(
+/// The program may start with an alphabet declaration, whose symbols are returned along with the tree.
+pub fn parse(
+    toks: Vec<(Token, TokenLoc)>,
+) -> std::result::Result<(Option<Vec<String>>, Exp<TokenLoc>), String> {
+    let (toks, alphabet) = parse_alphabet(&toks)?;
+
+    let (toks, exp) = parse_exp(toks)?.ok_or(format!(
         "Expected expression but found {} (while parsing root expression)",
-        match toks.get(0) {
+        match toks.first() {
             Some(t) => format!("{}", t.0),
             None => "EOF".to_owned(),
         },
     ))?;
 
+    check_tuples(&exp, false)?;
     if toks.is_empty() {
-        Ok(exp)
+        Ok((alphabet, exp))
     } else {
         Err(format!(
             "Expected EOF but found token {} at {}",
@@ -25,6 +31,51 @@ pub fn parse(toks: Vec<(Token, TokenLoc)>) -> std::result::Result<Exp<TokenLoc>,
     }
 }
 
+/// Parses an alphabet declaration, which is a union of symbols. `alphabet` is only a keyword at the start of the main
+/// file, when followed by a symbol, so that it can still be used as an identifier anywhere else.
+fn parse_alphabet(toks: Stream) -> std::result::Result<(Stream, Option<Vec<String>>), String> {
+    let (mut toks, mut loc) = match toks {
+        [(Token::Identifier(id), loc), rem @ ..]
+            if id == "alphabet"
+                && loc.import.is_none()
+                && matches!(rem.first(), Some((Token::Symbol(_), _))) =>
+        {
+            (rem, loc.clone())
+        }
+        _ => return Ok((toks, None)),
+    };
+
+    let mut symbols = Vec::new();
+    loop {
+        match toks.split_first() {
+            Some(((Token::Symbol(sym), _), rem)) => {
+                if !symbols.contains(sym) {
+                    symbols.push(sym.clone());
+                }
+                toks = rem;
+            }
+            first => {
+                return Err(format!(
+                    "Expected symbol but found {} after {} (while parsing alphabet declaration)",
+                    match first {
+                        Some(t) => format!("{}", t.0 .0),
+                        None => "EOF".to_owned(),
+                    },
+                    loc,
+                ))
+            }
+        }
+
+        match accept_token(toks, Token::Pipe) {
+            Some((t, l)) => {
+                toks = t;
+                loc = l;
+            }
+            None => return Ok((toks, Some(symbols))),
+        }
+    }
+}
+
 fn parse_exp(toks: Stream) -> Result {
     let (mut toks, mut exp) = if let Some((toks, exp)) = parse_apply(toks)? {
         (toks, exp)
@@ -35,7 +86,7 @@ fn parse_exp(toks: Stream) -> Result {
     while let Some((t, loc)) = accept_token(toks, Token::Pipe) {
         let (t, exp_rhs) = parse_exp(t)?.ok_or(format!(
             "Expected expression but found {} after {} (while parsing union)",
-            match toks.get(0) {
+            match toks.first() {
                 Some(t) => format!("{}", t.0),
                 None => "EOF".to_owned(),
             },
@@ -44,8 +95,8 @@ fn parse_exp(toks: Stream) -> Result {
 
         exp = Exp(
             Node::Union {
-                lhs: Box::new(exp),
-                rhs: Box::new(exp_rhs),
+                lhs: ExpRef::new(exp),
+                rhs: ExpRef::new(exp_rhs),
             },
             loc,
         );
@@ -66,8 +117,8 @@ fn parse_apply(toks: Stream) -> Result {
     while let Some((t, arg)) = parse_term(toks)? {
         exp = Exp(
             Node::Application {
-                func: Box::new(exp.clone()),
-                arg: Box::new(arg),
+                func: ExpRef::new(exp.clone()),
+                arg: ExpRef::new(arg),
             },
             exp.1,
         );
@@ -79,28 +130,46 @@ fn parse_apply(toks: Stream) -> Result {
 
 /// Parses a term.
 fn parse_term(toks: Stream) -> Result {
-    if let Some((toks, loc)) = accept_token(toks, Token::LParenthesis) {
-        let (toks, exp) = match parse_exp(toks)? {
-            Some(r) => r,
-            None => {
-                return Err(format!(
-                "Expected expression but found {} after {} (while parsing parenthesis expression)",
-                match toks.get(0) {
-                    Some(t) => format!("{}", t.0),
-                    None => "EOF".to_owned(),
-                },
-                loc,
-            ))
+    if let Some((mut toks, loc)) = accept_token(toks, Token::LParenthesis) {
+        // Parenthesis expressions with more than one element are tuples.
+        let mut tracks = Vec::new();
+        let mut last_loc = loc.clone();
+        let toks = loop {
+            let (t, track) = parse_track(toks, &last_loc)?;
+            tracks.push(track);
+            match accept_token(t, Token::Comma) {
+                Some((t, l)) => {
+                    toks = t;
+                    last_loc = l;
+                }
+                None => break t,
             }
         };
 
         let (toks, _) = expect_token(
             toks,
             Token::RParenthesis,
-            "while parsing parenthesis expression",
+            if tracks.len() > 1 {
+                "while parsing tuple"
+            } else {
+                "while parsing parenthesis expression"
+            },
         )?;
 
-        Ok(Some((toks, exp)))
+        match tracks.pop() {
+            Some(Track {
+                catch_id: None,
+                pat: Pat::Union(exp),
+            }) if tracks.is_empty() => Ok(Some((toks, ExpRef::unwrap_or_clone(exp)))),
+            Some(track) if !tracks.is_empty() => {
+                tracks.push(track);
+                Ok(Some((toks, Exp(Node::Tuple(tracks), loc))))
+            }
+            _ => Err(format!(
+                "Expected tuple with at least two elements at {} (while parsing parenthesis expression)",
+                loc
+            )),
+        }
     } else if let Some((toks, exp)) = parse_match(toks)? {
         Ok(Some((toks, exp)))
     } else if let Some((toks, exp)) = parse_let(toks)? {
@@ -116,6 +185,112 @@ fn parse_term(toks: Stream) -> Result {
     }
 }
 
+/// Parses an element of a tuple, which may be `any` or capture the symbol matched, as in match arms.
+fn parse_track<'a>(
+    toks: Stream<'a>,
+    last_loc: &TokenLoc,
+) -> std::result::Result<(Stream<'a>, Track<TokenLoc>), String> {
+    let (toks, catch_id) = match accept_identifier(toks) {
+        Some((t, id, _)) => match accept_token(t, Token::Catch) {
+            Some((t, _)) => (t, Some(id)),
+            None => (toks, None),
+        },
+        None => (toks, None),
+    };
+
+    if let Some((toks, _)) = accept_token(toks, Token::Any) {
+        return Ok((
+            toks,
+            Track {
+                catch_id,
+                pat: Pat::Any,
+            },
+        ));
+    }
+
+    let (toks, exp) = parse_exp(toks)?.ok_or(format!(
+        "Expected expression but found {} after {} (while parsing parenthesis expression)",
+        match toks.first() {
+            Some(t) => format!("{}", t.0),
+            None => "EOF".to_owned(),
+        },
+        last_loc,
+    ))?;
+    Ok((
+        toks,
+        Track {
+            catch_id,
+            pat: Pat::Union(ExpRef::new(exp)),
+        },
+    ))
+}
+
+/// Checks that tuples only use `any` in match patterns, and only capture the symbols of their elements when they
+/// are the whole pattern of a match arm.
+fn check_tuples(exp: &Exp<TokenLoc>, in_pattern: bool) -> std::result::Result<(), String> {
+    match &exp.0 {
+        Node::Tuple(tracks) => check_tracks(tracks, &exp.1, in_pattern, false),
+        Node::Union { lhs, rhs } => {
+            check_tuples(lhs, in_pattern)?;
+            check_tuples(rhs, in_pattern)
+        }
+        Node::Match { exp, arms } => {
+            check_tuples(exp, false)?;
+            for arm in arms {
+                match &arm.pat {
+                    Pat::Union(exp) => match &exp.0 {
+                        Node::Tuple(tracks) => check_tracks(tracks, &exp.1, true, true)?,
+                        _ => check_tuples(exp, true)?,
+                    },
+                    Pat::Any => {}
+                }
+                check_tuples(&arm.exp, false)?;
+            }
+            Ok(())
+        }
+        Node::Let { exp, binds } => {
+            check_tuples(exp, false)?;
+            binds
+                .iter()
+                .try_for_each(|(_, _, exp)| check_tuples(exp, false))
+        }
+        Node::Function { exp, .. } => check_tuples(exp, false),
+        Node::Application { func, arg } => {
+            check_tuples(func, false)?;
+            check_tuples(arg, false)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks the elements of a tuple, see `check_tuples`.
+fn check_tracks(
+    tracks: &[Track<TokenLoc>],
+    loc: &TokenLoc,
+    in_pattern: bool,
+    captures: bool,
+) -> std::result::Result<(), String> {
+    for track in tracks {
+        if track.catch_id.is_some() && !captures {
+            return Err(format!(
+                "Tuple at {} captures an element, which is only allowed when the tuple is a whole match pattern",
+                loc
+            ));
+        }
+        match &track.pat {
+            Pat::Any if !in_pattern => {
+                return Err(format!(
+                    "Tuple at {} uses 'any' outside of a match pattern",
+                    loc
+                ))
+            }
+            Pat::Any => {}
+            Pat::Union(exp) => check_tuples(exp, in_pattern)?,
+        }
+    }
+    Ok(())
+}
+
 /// Parses a match.
 fn parse_match(toks: Stream) -> Result {
     let (toks, loc) = match accept_token(toks, Token::Match) {
@@ -125,7 +300,7 @@ fn parse_match(toks: Stream) -> Result {
 
     let (toks, exp) = parse_exp(toks)?.ok_or(format!(
         "Expected expression but found {} after {} (while parsing match expression)",
-        match toks.get(0) {
+        match toks.first() {
             Some(t) => format!("{}", t.0),
             None => "EOF".to_owned(),
         },
@@ -158,19 +333,19 @@ fn parse_match(toks: Stream) -> Result {
         } else {
             let (t, exp) = parse_exp(toks)?.ok_or(format!(
                 "Expected expression but found {} after {} (while parsing match pattern)",
-                match toks.get(0) {
+                match toks.first() {
                     Some(t) => format!("{}", t.0),
                     None => "EOF".to_owned(),
                 },
                 last_loc,
             ))?;
-            (t, Pat::Union(exp))
+            (t, Pat::Union(ExpRef::new(exp)))
         };
 
         let (t, loc) = expect_token(t, Token::Arrow, "while parsing match arm")?;
         let (t, exp) = parse_exp(t)?.ok_or(format!(
             "Expected expression but found {} after {} (while parsing match arm)",
-            match toks.get(0) {
+            match toks.first() {
                 Some(t) => format!("{}", t.0),
                 None => "EOF".to_owned(),
             },
@@ -181,10 +356,11 @@ fn parse_match(toks: Stream) -> Result {
         toks = t;
         last_loc = loc;
 
+        let exp = ExpRef::new(exp);
         arms.push(Arm { catch_id, pat, exp });
     };
 
-    let exp = Box::new(exp);
+    let exp = ExpRef::new(exp);
     Ok(Some((toks, Exp(Node::Match { exp, arms }, loc))))
 }
 
@@ -212,7 +388,7 @@ fn parse_let(toks: Stream) -> Result {
 
         let (t, exp) = parse_exp(t)?.ok_or(format!(
             "Expected expression but found {} after {} (while parsing let binding)",
-            match t.get(0) {
+            match t.first() {
                 Some(t) => format!("{}", t.0),
                 None => "EOF".to_owned(),
             },
@@ -221,14 +397,14 @@ fn parse_let(toks: Stream) -> Result {
 
         let (t, _) = expect_token(t, Token::Comma, "while parsing let expression")?;
         toks = t;
-        binds.push((id, optional, exp))
+        binds.push((id, optional, ExpRef::new(exp)))
     };
 
     let (toks, exp) = parse_exp(toks)?.ok_or(format!(
         "Expected expression after {} (while parsing let expression)",
         last_loc
     ))?;
-    let exp = Box::new(exp);
+    let exp = ExpRef::new(exp);
 
     Ok(Some((toks, Exp(Node::Let { exp, binds }, loc))))
 }
@@ -251,7 +427,7 @@ fn parse_function(toks: Stream) -> Result {
             Exp(
                 Node::Function {
                     arg,
-                    exp: Box::new(exp),
+                    exp: ExpRef::new(exp),
                 },
                 loc,
             ),
@@ -365,33 +541,33 @@ mod tests {
             Token::Identifier("c".to_owned()),
         ]);
 
-        let ast = parse(tokens).unwrap();
+        let (_, ast) = parse(tokens).unwrap();
         assert_eq!(
             ast,
             Exp(
                 Node::Application {
-                    func: Box::new(Exp(
+                    func: ExpRef::new(Exp(
                         Node::Union {
-                            lhs: Box::new(Exp(
+                            lhs: ExpRef::new(Exp(
                                 Node::Application {
-                                    func: Box::new(Exp(
+                                    func: ExpRef::new(Exp(
                                         Node::Identifier("x".to_owned()),
                                         dummy.clone()
                                     )),
-                                    arg: Box::new(Exp(
+                                    arg: ExpRef::new(Exp(
                                         Node::Identifier("y".to_owned()),
                                         dummy.clone()
                                     )),
                                 },
                                 dummy.clone(),
                             )),
-                            rhs: Box::new(Exp(
+                            rhs: ExpRef::new(Exp(
                                 Node::Application {
-                                    func: Box::new(Exp(
+                                    func: ExpRef::new(Exp(
                                         Node::Identifier("a".to_owned()),
                                         dummy.clone()
                                     )),
-                                    arg: Box::new(Exp(
+                                    arg: ExpRef::new(Exp(
                                         Node::Identifier("b".to_owned()),
                                         dummy.clone()
                                     )),
@@ -401,13 +577,155 @@ mod tests {
                         },
                         dummy.clone(),
                     )),
-                    arg: Box::new(Exp(Node::Identifier("c".to_owned()), dummy.clone())),
+                    arg: ExpRef::new(Exp(Node::Identifier("c".to_owned()), dummy.clone())),
                 },
                 dummy,
             )
         );
     }
 
+    #[test]
+    fn test_parse_alphabet() {
+        let sym = |s: &str| Token::Symbol(s.to_owned());
+        let (tokens, _) = insert_dummy_locs(vec![
+            Token::Identifier("alphabet".to_owned()),
+            sym("0"),
+            Token::Pipe,
+            sym("1"),
+            Token::Pipe,
+            sym("0"),
+            Token::Identifier("x".to_owned()),
+        ]);
+        let (alphabet, _) = parse(tokens).unwrap();
+        assert_eq!(alphabet, Some(vec!["0".to_owned(), "1".to_owned()]));
+
+        let (tokens, _) = insert_dummy_locs(vec![
+            Token::Identifier("alphabet".to_owned()),
+            sym("0"),
+            Token::Pipe,
+            Token::Identifier("x".to_owned()),
+        ]);
+        parse(tokens).expect_err("Expected union of symbols");
+
+        // Anywhere else, `alphabet` is an identifier.
+        let (tokens, _) = insert_dummy_locs(vec![
+            Token::Identifier("x".to_owned()),
+            Token::Identifier("alphabet".to_owned()),
+            sym("0"),
+        ]);
+        assert_eq!(parse(tokens).unwrap().0, None);
+
+        let (mut tokens, _) = insert_dummy_locs(vec![
+            Token::Identifier("alphabet".to_owned()),
+            sym("0"),
+            Token::Identifier("x".to_owned()),
+        ]);
+        tokens[0].1.import = Some("lib.tmc".to_owned());
+        assert_eq!(parse(tokens).unwrap().0, None);
+
+        let (tokens, dummy) = insert_dummy_locs(vec![
+            Token::Identifier("alphabet".to_owned()),
+            Token::Colon,
+            Token::Identifier("alphabet".to_owned()),
+        ]);
+        let (alphabet, ast) = parse(tokens).unwrap();
+        assert_eq!(alphabet, None);
+        assert_eq!(
+            ast,
+            Exp(
+                Node::Function {
+                    arg: "alphabet".to_owned(),
+                    exp: ExpRef::new(Exp(Node::Identifier("alphabet".to_owned()), dummy.clone())),
+                },
+                dummy,
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_tuple() {
+        let sym = |s: &str| Token::Symbol(s.to_owned());
+        let id = |s: &str| Token::Identifier(s.to_owned());
+
+        // t: match t { (d @ any, '1' | '0') > (d, 'x'), }
+        let (tokens, dummy) = insert_dummy_locs(vec![
+            id("t"),
+            Token::Colon,
+            Token::Match,
+            id("t"),
+            Token::LBraces,
+            Token::LParenthesis,
+            id("d"),
+            Token::Catch,
+            Token::Any,
+            Token::Comma,
+            sym("1"),
+            Token::Pipe,
+            sym("0"),
+            Token::RParenthesis,
+            Token::Arrow,
+            Token::LParenthesis,
+            id("d"),
+            Token::Comma,
+            sym("x"),
+            Token::RParenthesis,
+            Token::Comma,
+            Token::RBraces,
+        ]);
+        let (_, ast) = parse(tokens).unwrap();
+        let arms = match ast.0 {
+            Node::Function { exp, .. } => match &exp.0 {
+                Node::Match { arms, .. } => arms.clone(),
+                _ => panic!("Expected match"),
+            },
+            _ => panic!("Expected function"),
+        };
+        let union = Exp(
+            Node::Union {
+                lhs: ExpRef::new(Exp(Node::Symbol("1".to_owned()), dummy.clone())),
+                rhs: ExpRef::new(Exp(Node::Symbol("0".to_owned()), dummy.clone())),
+            },
+            dummy.clone(),
+        );
+        assert_eq!(
+            arms[0].pat,
+            Pat::Union(ExpRef::new(Exp(
+                Node::Tuple(vec![
+                    Track {
+                        catch_id: Some("d".to_owned()),
+                        pat: Pat::Any,
+                    },
+                    Track {
+                        catch_id: None,
+                        pat: Pat::Union(ExpRef::new(union)),
+                    },
+                ]),
+                dummy.clone(),
+            )))
+        );
+        assert!(matches!(&arms[0].exp.0, Node::Tuple(tracks) if tracks.len() == 2));
+
+        // Captures and 'any' are only allowed in patterns.
+        let (tokens, _) = insert_dummy_locs(vec![
+            Token::LParenthesis,
+            id("d"),
+            Token::Catch,
+            sym("0"),
+            Token::Comma,
+            sym("1"),
+            Token::RParenthesis,
+        ]);
+        parse(tokens).expect_err("Captures are only allowed in patterns");
+        let (tokens, _) = insert_dummy_locs(vec![
+            Token::LParenthesis,
+            Token::Any,
+            Token::Comma,
+            sym("1"),
+            Token::RParenthesis,
+        ]);
+        parse(tokens).expect_err("'any' is only allowed in patterns");
+    }
+
     fn insert_dummy_locs(toks: Vec<Token>) -> (Vec<(Token, TokenLoc)>, TokenLoc) {
         let dummy = TokenLoc {
             line: 0,
@@ -0,0 +1,1141 @@
+//! Stages of the compiler, from the source of a program to the exported turing machine.
+//!
+//! Each stage takes the result of the previous one, so that tools can inspect or stop at any of them:
+//! [`tokenize`], [`parse`], [`desugar`], [`type_check`], [`simplify`], [`generate`] and [`export`]. The
+//! [`compile`] function runs all of them at once.
+
+use crate::annotater::Annot;
+use crate::data::{Exp, Machine, MultiMachine, TapeKind, Target, Token, TokenLoc, Transition};
+use crate::files::FileSystem;
+pub use crate::lexer::ImportCache;
+use crate::simplifier::pass_manager::{Pass, Phase};
+pub use crate::simplifier::pass_manager::{PassManager, PassOptions};
+use crate::simplifier::verifier::Invariant;
+use crate::simulator::Tape;
+use crate::transformer::binary::Reduction;
+use crate::transformer::encoding::Encoding;
+use crate::{annotater, exporter, generator, lexer, parser, simplifier, transformer, validator};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+macro_rules! load_lib {
+    ($a:expr) => {
+        {
+            let mut lib = HashMap::new();
+            lib.insert($a.to_owned(), include_str!(concat!("../", $a)).to_owned());
+            lib
+        }
+    };
+
+    ($a:expr, $b:expr) => {
+        {
+            let mut lib = load_lib!($a);
+            lib.insert($b.to_owned(), include_str!(concat!("../", $b)).to_owned());
+            lib
+        }
+    };
+
+    ($a:expr, $($b:tt)*) => {
+        {
+            let mut lib = load_lib!($($b)*);
+            lib.insert($a.to_owned(), include_str!(concat!("../", $a)).to_owned());
+            lib
+        }
+    }
+}
+
+/// Returns the files of the standard library, by the path with which programs import them.
+pub fn standard_library() -> HashMap<String, String> {
+    load_lib!(
+        "std/bool.tmc",
+        "std/iter.tmc",
+        "std/math.tmc",
+        "std/check.tmc"
+    )
+}
+
+/// Format in which a machine is exported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Turing machine format used at https://github.com/awmorp/turing
+    Awmorp,
+    /// Turing machine format used at https://turingmachinesimulator.com, which supports several tapes
+    Tmsim,
+    /// JFLAP file format (.jff), which supports nondeterministic machines
+    Jflap,
+}
+
+/// How much effort is spent making the machine smaller, at the expense of how closely it follows the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptLevel {
+    /// Skips the optional simplifier passes and keeps the states generated for each expression, so that the machine
+    /// stays close to the source.
+    O0,
+    /// Runs every simplifier pass and joins the states linked by transitions which don't touch the tape.
+    O1,
+    /// Also merges equivalent states, before and after the machine is lowered.
+    O2,
+    /// Also searches for the passes which are better left out, keeping the machine with the fewest transitions.
+    Os,
+}
+
+/// Passes which the generator doesn't depend on, and which are skipped at `O0`.
+const OPTIONAL_PASSES: &[&str] = &["arm_merger", "match_deduper"];
+
+/// Passes which may make the machine larger for some programs, and which the search of `Os` tries to leave out.
+const SEARCHED_PASSES: &[&str] = &["arm_merger", "match_merger", "match_deduper"];
+
+/// Stack size of the thread which runs the compiler. Every stage recurses on the syntax tree, so programs nested tens
+/// of thousands of levels deep, such as long `let` chains, overflow the default stack of the main thread.
+pub const STACK_SIZE: usize = 1 << 30;
+
+/// Runs a function on a thread with a stack of `STACK_SIZE` bytes, and returns its result. Panics are propagated.
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("compiler".to_owned())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("failed to spawn the compiler thread")
+            .join()
+            .unwrap_or_else(|err| std::panic::resume_unwind(err))
+    })
+}
+
+/// Stage of the compiler at which an error was found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Invalid options, such as a malformed symbol mapping.
+    Options,
+    Lexer,
+    Parser,
+    /// Picking the alphabet, or checking that the machine doesn't write symbols outside of it.
+    Alphabet,
+    TypeChecker,
+    ConstChecker,
+    OwnershipChecker,
+    Generator,
+    /// Checking the invariants of the program between simplifier passes.
+    Verifier,
+    /// Adapting the machine to the number of tapes and the alphabet of the target.
+    Lowering,
+    OneWay,
+    Validation,
+    Encoding,
+    Exporter(Format),
+    /// Encoding an input tape for the machine.
+    Input,
+}
+
+/// Error returned by a stage of the compiler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub stage: Stage,
+    pub message: String,
+}
+
+/// Options shared by the stages of the compiler.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The working alphabet of the machine, if it isn't declared in the source.
+    pub alphabet: Vec<String>,
+    /// The number of tapes taken by the program.
+    pub tapes: usize,
+    /// Should the machine be reduced to one which only uses blanks and ones?
+    pub binary: bool,
+    /// The conventions followed by the simulator for which the machine is exported.
+    pub target: Target,
+    /// Custom characters used to encode symbols in the exported machine, as `SYMBOL=C`.
+    pub symbol_map: Vec<String>,
+    /// Passes which are skipped or dumped while simplifying the program.
+    pub passes: PassOptions,
+    pub opt_level: OptLevel,
+}
+
+/// Program returned by the parser.
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// The alphabet declared at the start of the source, if any.
+    pub alphabet: Option<Vec<String>>,
+    pub ast: Exp<TokenLoc>,
+}
+
+/// Program whose syntactic sugar was removed, ready to be type checked.
+#[derive(Debug, Clone)]
+pub struct Desugared {
+    pub ast: Exp<TokenLoc>,
+    /// Symbols which may be on the tape, including the blank and the tuples formed with the alphabet.
+    pub alphabet: HashSet<String>,
+    /// Symbols used by the program, once its unused optional let bindings are removed.
+    pub used: HashSet<String>,
+}
+
+/// Program annotated with the type of each expression.
+#[derive(Debug, Clone)]
+pub struct Typed {
+    pub ast: Exp<Annot>,
+    /// Symbols which may be on the tape, including the blank and the tuples formed with the alphabet.
+    pub alphabet: HashSet<String>,
+}
+
+/// Machine exported in some format, along with the warnings found while validating it.
+#[derive(Debug, Clone)]
+pub struct Exported {
+    pub output: String,
+    pub warnings: Vec<String>,
+}
+
+/// Converts the source of the main file into tokens. Imports are searched for in the standard library first, and
+/// then in the file system, relative to the main file. Imported files whose contents are in the cache aren't tokenized
+/// again.
+pub fn tokenize(
+    src: &str,
+    files: &dyn FileSystem,
+    lib: &HashMap<String, String>,
+    cache: &ImportCache,
+) -> Result<Vec<(Token, TokenLoc)>, Error> {
+    lexer::tokenize_with(src, Some((files, Path::new(""))), lib, None, cache)
+        .map_err(Error::at(Stage::Lexer))
+}
+
+/// Parses the tokens of a program.
+pub fn parse(toks: Vec<(Token, TokenLoc)>) -> Result<Program, Error> {
+    let (alphabet, ast) = parser::parse(toks).map_err(Error::at(Stage::Parser))?;
+    Ok(Program { alphabet, ast })
+}
+
+/// Picks the working alphabet from the declaration in the program or from the options.
+pub fn alphabet(program: &Program, options: &Options) -> Result<Vec<String>, Error> {
+    let fmt = |symbols: &[String]| {
+        symbols
+            .iter()
+            .map(|s| format!("'{}'", s))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    };
+
+    match &program.alphabet {
+        Some(declared) if !options.alphabet.is_empty() => {
+            let set = |symbols: &[String]| {
+                symbols
+                    .iter()
+                    .filter(|s| !s.is_empty())
+                    .cloned()
+                    .collect::<HashSet<_>>()
+            };
+            if set(declared) != set(&options.alphabet) {
+                return Err(Error::new(
+                    Stage::Alphabet,
+                    format!(
+                        "The alphabet declared in the source ({}) conflicts with the one passed with --alphabet ({})",
+                        fmt(declared),
+                        fmt(&options.alphabet)
+                    ),
+                ));
+            }
+            Ok(declared.clone())
+        }
+        Some(declared) => Ok(declared.clone()),
+        None if !options.alphabet.is_empty() => Ok(options.alphabet.clone()),
+        None => Err(Error::new(
+            Stage::Alphabet,
+            "No alphabet given, either declare it at the start of the source (e.g. alphabet '0' | '1') or pass it with --alphabet"
+                .to_owned(),
+        )),
+    }
+}
+
+/// Returns a warning for each symbol in the alphabet which the program never uses.
+pub fn unused_symbols(alphabet: &[String], program: &Desugared) -> Vec<String> {
+    alphabet
+        .iter()
+        .filter(|s| !s.is_empty() && !program.used.contains(*s))
+        .map(|sym| {
+            format!(
+                "Warning: symbol '{}' is in the alphabet but is never used by the program",
+                sym
+            )
+        })
+        .collect()
+}
+
+/// Adds the tuple symbols which can be formed with the alphabet to it, for each size of tuple used by the program.
+pub fn tuple_alphabet(alphabet: &[String], ast: &Exp<TokenLoc>) -> Vec<String> {
+    let mut sizes = BTreeSet::new();
+    ast.collect_tuple_sizes(&mut sizes);
+
+    let tracks = std::iter::once(String::new())
+        .chain(alphabet.iter().filter(|s| !s.is_empty()).cloned())
+        .collect::<Vec<_>>();
+    let mut alphabet = alphabet.to_vec();
+    for size in sizes {
+        alphabet.extend(crate::data::tuple_symbols(&tracks, size));
+    }
+    alphabet
+}
+
+/// Removes the syntactic sugar of a program with the given alphabet.
+pub fn desugar(
+    program: &Program,
+    alphabet: &[String],
+    passes: &PassManager,
+) -> Result<Desugared, Error> {
+    // Remove unused optional let bindings from the AST.
+    let phase = Phase {
+        name: "optionals",
+        bottom_up: false,
+        passes: vec![Pass::new(
+            "optionals_remover",
+            simplifier::optionals_remover::remove_optionals,
+        )],
+    };
+    let ast = passes
+        .run(&phase, program.ast.clone())
+        .map_err(Error::at(Stage::Verifier))?;
+
+    // Get the set of symbols used by the AST (including those only used during compilation), along with the tuples
+    // which can be formed with them.
+    let mut used = HashSet::new();
+    ast.collect_symbols(&mut used);
+    let track_alphabet = alphabet
+        .iter()
+        .cloned()
+        .chain([String::new()])
+        .chain(used.iter().cloned())
+        .collect();
+    let alphabet = HashSet::from_iter(
+        tuple_alphabet(alphabet, &ast)
+            .into_iter()
+            .chain(std::iter::once("".to_owned())),
+    );
+    let const_alphabet = alphabet.union(&used).cloned().collect();
+
+    // Apply initial simplifications on the AST and dedup ids:
+    // - replace let statements with function applications.
+    // - remove match 'any' patterns.
+    // - remove trivial applications.
+    let phases = [
+        Phase {
+            name: "lets",
+            bottom_up: true,
+            passes: vec![Pass::new(
+                "let_remover",
+                simplifier::let_remover::remove_lets,
+            )],
+        },
+        Phase {
+            name: "patterns",
+            bottom_up: true,
+            passes: vec![
+                Pass::new("any_remover", |e| {
+                    simplifier::any_remover::remove_any(e, &const_alphabet, &track_alphabet)
+                }),
+                Pass::new(
+                    "trivial_remover",
+                    simplifier::trivial_remover::remove_trivial,
+                ),
+            ],
+        },
+        Phase {
+            name: "ids",
+            bottom_up: false,
+            passes: vec![Pass::new("id_dedup", simplifier::id_dedup::dedup_ids)],
+        },
+    ];
+    let ast = phases
+        .iter()
+        .try_fold(ast, |ast, phase| passes.run(phase, ast))
+        .map_err(Error::at(Stage::Verifier))?;
+    Ok(Desugared {
+        ast,
+        alphabet,
+        used,
+    })
+}
+
+/// Annotates the program with the type of each expression, and checks if match patterns are constant and if
+/// ownership rules are followed.
+pub fn type_check(program: Desugared, tapes: usize, passes: &PassManager) -> Result<Typed, Error> {
+    let ast = annotater::type_checker::type_check(program.ast, tapes)
+        .map_err(Error::at(Stage::TypeChecker))?;
+    annotater::const_checker::const_check(&ast).map_err(Error::at(Stage::ConstChecker))?;
+
+    // Remove all non tape -> tape applications which can be removed before checking ownership rules.
+    let alphabet = program.alphabet;
+    let phase = Phase {
+        name: "ownership",
+        bottom_up: true,
+        passes: vec![
+            Pass::new("tuple_folder", simplifier::tuple_folder::fold_tuples),
+            Pass::new("get_remover", |e| {
+                simplifier::get_remover::remove_gets(e, &alphabet)
+            }),
+            Pass::new("match_mover", simplifier::match_mover::move_matches),
+            Pass::recursive("applier", |e, rec| simplifier::applier::apply(e, rec)),
+            Pass::new(
+                "trivial_remover",
+                simplifier::trivial_remover::remove_trivial,
+            ),
+        ],
+    };
+    let ast = passes
+        .run(&phase, ast)
+        .map_err(Error::at(Stage::Verifier))?;
+    drop(phase);
+
+    // Check for ownership errors and resolve the types of unions.
+    annotater::ownership_checker::ownership_check(&ast)
+        .map_err(Error::at(Stage::OwnershipChecker))?;
+    let ast = annotater::union_resolver::resolve_unions(ast);
+    Ok(Typed { ast, alphabet })
+}
+
+/// Simplifies the annotated program until it is ready for generation.
+pub fn simplify(program: Typed, passes: &PassManager) -> Result<Typed, Error> {
+    // Simplify the AAST even further:
+    // - remove match captured variables.
+    // - remove non tape -> tape applications
+    // - remove trivial applications
+    // - move matches so that they are at the root of the function expressions.
+    // - match matches with symbols an input.
+    // - remove duplicate match patterns.
+    // - merge matches which are used as arguments to other matches.
+    // - remove get applications, replacing them with match expressions.
+    // - simplify get & set expressions when symbols are already known.
+    // - merge match arms which have equivalent expressions.
+    let phases = [
+        Phase {
+            name: "final",
+            bottom_up: true,
+            passes: vec![
+                Pass::recursive("capture_remover", |e, rec| {
+                    simplifier::capture_remover::remove_captures(e, rec)
+                }),
+                Pass::new("tuple_folder", simplifier::tuple_folder::fold_tuples),
+                Pass::recursive("applier", |e, rec| simplifier::applier::apply(e, rec)),
+                Pass::new(
+                    "trivial_remover",
+                    simplifier::trivial_remover::remove_trivial,
+                ),
+                Pass::new("abort_spreader", simplifier::abort_spreader::spread_aborts),
+                Pass::new("matcher", simplifier::matcher::match_const),
+                Pass::new("pat_dedup", simplifier::pat_dedup::dedup_patterns),
+                Pass::recursive("match_merger", |e, rec| {
+                    simplifier::match_merger::merge_matches(e, rec)
+                }),
+                Pass::recursive("match_deduper", |e, rec| {
+                    simplifier::match_deduper::dedup_matches(e, rec)
+                }),
+                Pass::new("arm_merger", simplifier::arm_merger::merge_arms),
+            ],
+        },
+        Phase {
+            name: "arms",
+            bottom_up: true,
+            passes: vec![Pass::new("arm_merger", simplifier::arm_merger::merge_arms)],
+        },
+    ];
+    let ast = phases
+        .iter()
+        .try_fold(program.ast, |ast, phase| passes.run(phase, ast))
+        .map_err(Error::at(Stage::Verifier))?;
+    passes
+        .verify(&ast, &[Invariant::TapeScrutinees])
+        .map_err(|v| Error::new(Stage::Verifier, format!("{}, before the generator", v)))?;
+    Ok(Typed { ast, ..program })
+}
+
+/// Generates the turing machine for a simplified program, with one tape per argument, and checks that it never
+/// writes symbols outside of the alphabet.
+pub fn generate(program: Typed, tapes: usize, level: OptLevel) -> Result<MultiMachine, Error> {
+    let mut machine = generator::generate_multi(program.ast, tapes, level != OptLevel::O0)
+        .map_err(Error::at(Stage::Generator))?;
+    if let OptLevel::O2 | OptLevel::Os = level {
+        machine.minimize();
+    }
+    check_writes(&machine, &program.alphabet)?;
+    Ok(machine)
+}
+
+/// Checks if the machine may write symbols which aren't part of the alphabet.
+fn check_writes(machine: &MultiMachine, alphabet: &HashSet<String>) -> Result<(), Error> {
+    let mut written = BTreeMap::<&String, Vec<&TokenLoc>>::new();
+    for t in machine.reachable_transitions(&alphabet.iter().cloned().collect()) {
+        for sym in t.to.1.iter().flatten() {
+            if !alphabet.contains(sym) {
+                written.entry(sym).or_default().extend(t.locs.iter())
+            }
+        }
+    }
+
+    match written.into_iter().next() {
+        Some((sym, mut locs)) => {
+            locs.sort_by(|a, b| (&a.import, a.line, a.col).cmp(&(&b.import, b.line, b.col)));
+            locs.dedup();
+            Err(Error::new(
+                Stage::Alphabet,
+                format!(
+                    "The machine writes symbol '{}', which isn't in the alphabet, at {}",
+                    sym,
+                    locs.iter()
+                        .map(|l| l.to_string())
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Runs every stage from the desugaring to the generation of the machine, for a parsed program.
+pub fn build(
+    program: &Program,
+    alphabet: &[String],
+    options: &Options,
+    passes: &PassManager,
+) -> Result<MultiMachine, Error> {
+    let machine = build_with(program, alphabet, options, passes)?;
+    search(program, alphabet, options, machine)
+}
+
+fn build_with(
+    program: &Program,
+    alphabet: &[String],
+    options: &Options,
+    passes: &PassManager,
+) -> Result<MultiMachine, Error> {
+    let program = type_check(desugar(program, alphabet, passes)?, options.tapes, passes)?;
+    generate(simplify(program, passes)?, options.tapes, options.opt_level)
+}
+
+/// At `Os`, builds the program again with each combination of the searched passes left out, and returns the machine
+/// with the fewest transitions, starting with the given one. Combinations which fail to compile are skipped.
+pub fn search(
+    program: &Program,
+    alphabet: &[String],
+    options: &Options,
+    machine: MultiMachine,
+) -> Result<MultiMachine, Error> {
+    if options.opt_level != OptLevel::Os {
+        return Ok(machine);
+    }
+
+    let size = |m: &MultiMachine| (m.transitions.len(), m.state_count);
+    let mut best = machine;
+    for mask in 1..1 << SEARCHED_PASSES.len() {
+        let mut passes = options.passes.clone();
+        passes.dump_after.clear();
+        passes.timed = false;
+        passes.disabled.extend(
+            SEARCHED_PASSES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask >> i & 1 == 1)
+                .map(|(_, p)| p.to_string()),
+        );
+
+        let passes = PassManager::new(passes).map_err(Error::at(Stage::Options))?;
+        if let Ok(machine) = build_with(program, alphabet, options, &passes) {
+            if size(&machine) < size(&best) {
+                best = machine;
+            }
+        }
+    }
+    Ok(best)
+}
+
+/// Creates the pass manager used to simplify the program, which skips the optional passes at `O0`.
+pub fn pass_manager(options: &Options) -> Result<PassManager, Error> {
+    let mut passes = options.passes.clone();
+    if options.opt_level == OptLevel::O0 {
+        passes
+            .disabled
+            .extend(OPTIONAL_PASSES.iter().map(|p| p.to_string()));
+    }
+    PassManager::new(passes).map_err(Error::at(Stage::Options))
+}
+
+/// Adapts a generated machine to the kind of tape it will run on, and reduces its alphabet if requested.
+/// Machines with several tapes are first reduced to a single tape divided into tracks.
+pub fn lower(
+    machine: MultiMachine,
+    alphabet: &[String],
+    options: &Options,
+) -> Result<Machine, Error> {
+    if !machine.is_deterministic() && (machine.tapes > 1 || options.binary) {
+        return Err(Error::new(
+            Stage::Lowering,
+            "Nondeterministic machines can't be reduced to a single tape or a binary alphabet"
+                .to_owned(),
+        ));
+    }
+
+    let machine = match machine.tapes {
+        1 => machine.into_single(),
+        _ => transformer::tracks::reduce(&machine, &alphabet.iter().cloned().collect()),
+    };
+
+    let machine = match options.target.tape {
+        TapeKind::TwoWay => machine,
+        TapeKind::OneWay => transformer::one_way::transform(
+            machine,
+            &tape_alphabet(alphabet, options).into_iter().collect(),
+            &options.target,
+        )
+        .map_err(Error::at(Stage::OneWay))?,
+    };
+
+    let machine = match reduction(alphabet, options) {
+        Some(reduction) => reduction.apply(&machine),
+        None => machine,
+    };
+
+    // The transformations generate similar states for similar symbols, many of which can be merged.
+    Ok(match options.opt_level {
+        OptLevel::O2 | OptLevel::Os => {
+            let mut machine = MultiMachine::from(machine);
+            machine.minimize();
+            machine.into_single()
+        }
+        _ => machine,
+    })
+}
+
+/// Returns the binary encoding of the symbols which may be on the tape, if the machine is reduced to a binary
+/// alphabet.
+pub fn reduction(alphabet: &[String], options: &Options) -> Option<Reduction> {
+    if !options.binary {
+        return None;
+    }
+
+    let mut symbols = tape_alphabet(alphabet, options);
+    if let TapeKind::OneWay = options.target.tape {
+        symbols.insert(options.target.left_marker.clone());
+        symbols.insert(options.target.visited_blank.clone());
+    }
+    Some(Reduction::new(&symbols))
+}
+
+/// Returns the symbols which may be on the single tape of the lowered machine, before the one-way and binary
+/// transformations. With several tapes, these are the tuples of symbols stored on the tracks.
+fn tape_alphabet(alphabet: &[String], options: &Options) -> BTreeSet<String> {
+    let symbols = alphabet.iter().cloned().collect::<BTreeSet<_>>();
+    match options.tapes {
+        1 => symbols,
+        tapes => transformer::tracks::alphabet(&symbols, tapes),
+    }
+}
+
+/// Parses an input tape, which is the first tape of programs with several tapes, and encodes it in the same way as
+/// the machine.
+pub fn tape(input: &str, alphabet: &[String], options: &Options) -> Result<Tape, Error> {
+    let tape = Tape::parse_with_blank(input, &options.target.blank);
+    if let TapeKind::OneWay = options.target.tape {
+        // The prologue of one-way machines shifts the input up to its first blank.
+        let (min, max) = tape.bounds();
+        if let Some(p) = (min..max).find(|&p| tape.get(p).is_empty() && !tape.get(p + 1).is_empty())
+        {
+            return Err(Error::at(Stage::Input)(format!(
+                "Input tapes of one-way machines can't have blanks before their last symbol, found one at cell {}",
+                p - min
+            )));
+        }
+    }
+    if options.tapes == 1 && !options.binary {
+        return Ok(tape);
+    }
+
+    let (min, max) = tape.bounds();
+    let mut cells = (min..=max)
+        .map(|p| tape.get(p).to_owned())
+        .collect::<Vec<_>>();
+    if options.tapes > 1 {
+        cells = transformer::tracks::encode(&cells, options.tapes);
+    }
+    if let Some(reduction) = reduction(alphabet, options) {
+        cells = reduction.encode(&cells).map_err(Error::at(Stage::Input))?;
+    }
+    Ok(Tape::new(cells))
+}
+
+/// Lowers the machine if the format requires it, validates it and exports it, along with the tables needed to
+/// decode its symbols. The name is only used by formats which name the machine.
+pub fn export(
+    machine: MultiMachine,
+    alphabet: &[String],
+    options: &Options,
+    format: Format,
+    name: &str,
+) -> Result<Exported, Error> {
+    let mappings = options
+        .symbol_map
+        .iter()
+        .map(|m| transformer::encoding::parse_mapping(m))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::at(Stage::Options))?;
+    let exporter_error = Error::at(Stage::Exporter(format));
+
+    // Encode the symbols of the machine as characters supported by the format, and export it, along with the
+    // encoding table if any symbol was changed.
+    let mut warnings = Vec::new();
+    let mut tables = String::new();
+    if let Some(reduction) = reduction(alphabet, options) {
+        tables += &format!("Binary encoding:\n{}", reduction);
+    }
+    let output = match format {
+        Format::Awmorp => {
+            let machine = lower(machine, alphabet, options)?;
+            warnings = validate(&machine, alphabet, options, false)?;
+            let reserved = exporter::awmorp::reserved(&options.target);
+            let encoding = Encoding::for_machine(&machine, &reserved, &mappings)
+                .map_err(Error::at(Stage::Encoding))?;
+            if !encoding.is_identity() {
+                tables += &format!("Symbol encoding:\n{}", encoding);
+            }
+            exporter::awmorp::comment(&tables)
+                + &exporter::awmorp::export(encoding.apply(machine), &options.target)
+                    .map_err(exporter_error)?
+        }
+        Format::Tmsim => {
+            // Machines with several tapes are exported as they are, unless they have to be lowered anyway.
+            let machine = match options.target.tape {
+                TapeKind::TwoWay if !options.binary => machine,
+                _ => lower(machine, alphabet, options)?.into(),
+            };
+            if machine.tapes == 1 {
+                warnings = validate(&machine.clone().into_single(), alphabet, options, false)?;
+            }
+            let encoding =
+                Encoding::new(&machine.symbols(), &exporter::tmsim::reserved(), &mappings)
+                    .map_err(Error::at(Stage::Encoding))?;
+            if !encoding.is_identity() {
+                tables += &format!("Symbol encoding:\n{}", encoding);
+            }
+            tables
+                .lines()
+                .map(|l| format!("// {}\n", l))
+                .collect::<String>()
+                + &exporter::tmsim::export(&encoding.apply_multi(machine), name)
+                    .map_err(exporter_error)?
+        }
+        Format::Jflap => {
+            let machine = lower(machine, alphabet, options)?;
+            warnings = validate(&machine, alphabet, options, true)?;
+            let encoding = Encoding::for_machine(&machine, &exporter::jflap::reserved(), &mappings)
+                .map_err(Error::at(Stage::Encoding))?;
+            if !encoding.is_identity() {
+                tables += &format!("Symbol encoding:\n{}", encoding);
+            }
+            exporter::jflap::comment(&tables)
+                + &exporter::jflap::export(encoding.apply(machine)).map_err(exporter_error)?
+        }
+    };
+
+    Ok(Exported { output, warnings })
+}
+
+/// Checks the machine before it is exported, failing on errors and returning the warnings.
+fn validate(
+    machine: &Machine,
+    alphabet: &[String],
+    options: &Options,
+    nondeterministic: bool,
+) -> Result<Vec<String>, Error> {
+    // Symbols which may be on the tape of the lowered machine.
+    let symbols = match reduction(alphabet, options) {
+        Some(_) => machine
+            .transitions
+            .iter()
+            .flat_map(|t| t.from.1.iter().chain(t.to.1.iter()))
+            .cloned()
+            .collect(),
+        None => {
+            let mut symbols = tape_alphabet(alphabet, options);
+            if let TapeKind::OneWay = options.target.tape {
+                symbols.insert(options.target.left_marker.clone());
+                symbols.insert(options.target.visited_blank.clone());
+            }
+            symbols
+        }
+    };
+
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        validator::validate(machine, &symbols, nondeterministic)
+            .into_iter()
+            .partition(|i| i.severity == validator::Severity::Error);
+
+    match errors.is_empty() {
+        true => Ok(warnings.into_iter().map(|i| i.to_string()).collect()),
+        false => Err(Error::new(
+            Stage::Validation,
+            errors
+                .into_iter()
+                .map(|i| i.message)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )),
+    }
+}
+
+/// Compiles the source of a program and exports the resulting machine, with imports read from the given file
+/// system and the standard library. The warnings of every stage are returned along with the machine. The compiler runs
+/// on a thread with a large stack, see `with_stack`.
+pub fn compile(
+    src: &str,
+    files: &dyn FileSystem,
+    options: &Options,
+    format: Format,
+    name: &str,
+) -> Result<Exported, Error> {
+    with_stack(|| compile_program(src, files, options, format, name))
+}
+
+fn compile_program(
+    src: &str,
+    files: &dyn FileSystem,
+    options: &Options,
+    format: Format,
+    name: &str,
+) -> Result<Exported, Error> {
+    if options.tapes == 0 {
+        return Err(Error::new(
+            Stage::Options,
+            "The program must take at least one tape".to_owned(),
+        ));
+    }
+
+    let passes = pass_manager(options)?;
+    let cache = ImportCache::default();
+    let program = parse(tokenize(src, files, &standard_library(), &cache)?)?;
+    let alphabet = alphabet(&program, options)?;
+    let desugared = desugar(&program, &alphabet, &passes)?;
+    let mut warnings = unused_symbols(&alphabet, &desugared);
+    let typed = type_check(desugared, options.tapes, &passes)?;
+    let machine = generate(simplify(typed, &passes)?, options.tapes, options.opt_level)?;
+    let machine = search(&program, &alphabet, options, machine)?;
+    let exported = export(
+        machine,
+        &tuple_alphabet(&alphabet, &program.ast),
+        options,
+        format,
+        name,
+    )?;
+    warnings.extend(exported.warnings);
+    Ok(Exported {
+        output: exported.output,
+        warnings,
+    })
+}
+
+/// Infers the symbols which a program with a single tape may read from or write to the tape.
+pub fn infer_alphabet(program: &Program) -> Result<Vec<String>, Error> {
+    // Compile the program with every symbol it uses, plus a symbol which stands for every other symbol.
+    let mut symbols = HashSet::new();
+    simplifier::optionals_remover::remove_optionals(program.ast.clone())
+        .collect_symbols(&mut symbols);
+    symbols.remove("");
+    let mut symbols = symbols.into_iter().collect::<Vec<_>>();
+    symbols.sort();
+
+    let other = "<other>".to_owned();
+    let alphabet = symbols
+        .iter()
+        .cloned()
+        .chain([other.clone()])
+        .collect::<Vec<_>>();
+    let machine = build(
+        program,
+        &alphabet,
+        &Options::default(),
+        &PassManager::default(),
+    )?
+    .into_single();
+    let reachable =
+        machine.reachable_transitions(&alphabet.iter().cloned().chain([String::new()]).collect());
+
+    // Symbols which are written are always needed. Symbols which are only read are needed if the machine handles
+    // them differently from the other symbols.
+    let written = reachable
+        .iter()
+        .filter(|t| t.to.1.is_some() && t.to.1 != t.from.1)
+        .filter_map(|t| t.to.1.as_ref())
+        .collect::<HashSet<_>>();
+    let same = |t: &Transition, u: &Transition| {
+        t.to.0 == u.to.0
+            && t.dir == u.dir
+            && (t.to.1 == u.to.1 || (t.to.1 == t.from.1 && u.to.1 == u.from.1))
+    };
+    let distinguished = |sym: &String| {
+        reachable
+            .iter()
+            .filter(|t| t.from.1.as_ref() == Some(sym))
+            .any(|t| {
+                let state = reachable.iter().filter(|u| u.from.0 == t.from.0);
+                let on_other = state
+                    .clone()
+                    .find(|u| u.from.1.as_ref() == Some(&other))
+                    .or_else(|| state.clone().find(|u| u.from.1.is_none()));
+                !matches!(on_other, Some(u) if same(t, u))
+            })
+    };
+
+    // Symbols stored on the tracks of needed tuples are needed as well.
+    let mut sizes = BTreeSet::new();
+    program.ast.collect_tuple_sizes(&mut sizes);
+    let tracks = tuple_alphabet(&alphabet, &program.ast)
+        .into_iter()
+        .filter(|s| written.contains(s) || distinguished(s))
+        .flat_map(|s| {
+            sizes
+                .iter()
+                .find_map(|&size| crate::data::tuple_parts(&s, size))
+                .unwrap_or_default()
+        })
+        .collect::<HashSet<_>>();
+
+    Ok(symbols
+        .into_iter()
+        .filter(|s| written.contains(s) || distinguished(s) || tracks.contains(s))
+        .collect())
+}
+
+impl Error {
+    pub fn new(stage: Stage, message: String) -> Self {
+        Self { stage, message }
+    }
+
+    /// Returns a function which wraps an error message from the given stage, for use with `map_err`.
+    fn at(stage: Stage) -> impl Fn(String) -> Self {
+        move |message| Self::new(stage, message)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            alphabet: Vec::new(),
+            tapes: 1,
+            binary: false,
+            target: Target::default(),
+            symbol_map: Vec::new(),
+            passes: PassOptions::default(),
+            opt_level: OptLevel::O1,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Awmorp => write!(f, "Awmorp"),
+            Format::Tmsim => write!(f, "Tmsim"),
+            Format::Jflap => write!(f, "Jflap"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.stage {
+            Stage::Lexer => write!(f, "Lexer error: ")?,
+            Stage::Parser => write!(f, "Parser error: ")?,
+            Stage::TypeChecker => write!(f, "Type checker error: ")?,
+            Stage::ConstChecker => write!(f, "Const checker error: ")?,
+            Stage::OwnershipChecker => write!(f, "Ownership checker error: ")?,
+            Stage::Generator => write!(f, "Generator error: ")?,
+            Stage::Verifier => write!(f, "Verifier error: ")?,
+            Stage::OneWay => write!(f, "One-way tape error: ")?,
+            Stage::Validation => write!(f, "Machine validation error: ")?,
+            Stage::Encoding => write!(f, "Symbol encoding error: ")?,
+            Stage::Exporter(format) => write!(f, "{} exporter error: ", format)?,
+            Stage::Options | Stage::Alphabet | Stage::Lowering | Stage::Input => {}
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for String {
+    fn from(e: Error) -> Self {
+        e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_in_memory() {
+        // Nested imports are resolved relative to the importing file.
+        let files = HashMap::from([
+            (
+                "lib/flip.tmc".to_owned(),
+                "import 'bits.tmc'\nlet flip = x: match x { '0' > one, '1' > zero, }, in"
+                    .to_owned(),
+            ),
+            (
+                "lib/bits.tmc".to_owned(),
+                "let one = '1', zero = '0', in".to_owned(),
+            ),
+        ]);
+        let options = Options {
+            alphabet: ["0", "1", "#"].iter().map(|s| s.to_string()).collect(),
+            ..Options::default()
+        };
+
+        let src = "import 'lib/flip.tmc'\nt: set (flip (get t)) t";
+        let exported = compile(src, &files, &options, Format::Awmorp, "flip").unwrap();
+        assert!(!exported.output.is_empty());
+        assert_eq!(
+            exported.warnings[0],
+            "Warning: symbol '#' is in the alphabet but is never used by the program"
+        );
+
+        let err = compile(
+            "import 'lib/missing.tmc'\nt: t",
+            &files,
+            &options,
+            Format::Awmorp,
+            "",
+        )
+        .unwrap_err();
+        assert_eq!(err.stage, Stage::Lexer);
+        assert!(err
+            .to_string()
+            .starts_with("Lexer error: Couldn't import file lib/missing.tmc"));
+    }
+
+    #[test]
+    fn test_alphabet_identifier() {
+        // `alphabet` only declares the alphabet at the start of the main file, and is an identifier anywhere else.
+        let src = "alphabet '0' | '1'\nlet alphabet = t: set '1' t, in alphabet";
+        let options = Options::default();
+        let exported = compile(src, &HashMap::new(), &options, Format::Awmorp, "").unwrap();
+        assert!(!exported.output.is_empty());
+
+        let files = HashMap::from([("lib.tmc".to_owned(), "let alphabet = '0', in".to_owned())]);
+        let src = "import 'lib.tmc'\nt: set alphabet t";
+        let options = Options {
+            alphabet: vec!["0".to_owned()],
+            ..Options::default()
+        };
+        assert!(compile(src, &files, &options, Format::Awmorp, "").is_ok());
+    }
+
+    #[test]
+    fn test_opt_levels() {
+        // Higher levels never make the machine larger, and O0 keeps the states generated for each expression.
+        let src = std::fs::read_to_string("samples/inc.tmc").unwrap();
+        let size = |opt_level| {
+            let options = Options {
+                binary: true,
+                opt_level,
+                ..Options::default()
+            };
+            let exported = compile(&src, &HashMap::new(), &options, Format::Awmorp, "").unwrap();
+            exported
+                .output
+                .lines()
+                .filter(|l| !l.starts_with(';'))
+                .count()
+        };
+
+        let sizes = [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os].map(size);
+        assert!(sizes[0] > sizes[1]);
+        assert!(sizes.windows(2).all(|w| w[0] >= w[1]));
+        assert!(sizes[1] > sizes[2]);
+    }
+
+    #[test]
+    fn test_used_symbols() {
+        // Symbols of optional bindings which are already bound aren't used, while those of pattern unions are.
+        let src =
+            "let z = 'c', in let z ? 'z', in t: match get t { 'a' | 'b' > set z t, any > t, }";
+        let program = parse(
+            tokenize(
+                src,
+                &crate::files::NoFiles,
+                &HashMap::new(),
+                &ImportCache::default(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let alphabet = ["a", "b", "c", "z"].map(|s| s.to_owned());
+        let passes = pass_manager(&Options::default()).unwrap();
+        let desugared = desugar(&program, &alphabet, &passes).unwrap();
+        assert!(["a", "b", "c"].iter().all(|s| desugared.used.contains(*s)));
+        assert!(!desugared.used.contains("z"));
+        assert_eq!(
+            unused_symbols(&alphabet, &desugared),
+            ["Warning: symbol 'z' is in the alphabet but is never used by the program"]
+        );
+    }
+
+    #[test]
+    fn test_blank_symbols() {
+        // The awmorp emulator always reads '_' as blank, so the symbol can't be exported as it is.
+        let src =
+            "alphabet '0' | '_'\nt: match get t { '0' > accept (set '_' t), any > reject t, }";
+        let options = Options {
+            target: Target {
+                blank: "x".to_owned(),
+                ..Target::default()
+            },
+            ..Options::default()
+        };
+        let exported = compile(src, &HashMap::new(), &options, Format::Awmorp, "").unwrap();
+        assert!(exported.output.contains("; '_' -> 'a'\n"));
+        assert!(exported.output.contains("0 0 a * halt-accept"));
+
+        // Nor as the character of another symbol.
+        let options = Options {
+            symbol_map: vec!["0=_".to_owned()],
+            ..options
+        };
+        let err = compile(src, &HashMap::new(), &options, Format::Awmorp, "").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Symbol encoding error: Symbol '0' can't be encoded as '_', reserved character"
+        );
+
+        // One-way machines only shift the input up to its first blank.
+        let options = Options {
+            target: Target {
+                tape: TapeKind::OneWay,
+                ..Target::default()
+            },
+            ..Options::default()
+        };
+        let alphabet = ["0".to_owned(), "1".to_owned()];
+        assert!(tape("0110", &alphabet, &options).is_ok());
+        assert!(tape("01_10", &alphabet, &options).is_err());
+        assert!(tape("_0", &alphabet, &options).is_err());
+        assert!(tape("01__", &alphabet, &options).is_ok());
+    }
+
+    #[test]
+    fn test_deep_programs() {
+        // Every stage recurses on the syntax tree, which is as deep as the program is nested.
+        const DEPTH: usize = 20000;
+        let options = Options {
+            alphabet: vec!["0".to_owned(), "1".to_owned()],
+            ..Options::default()
+        };
+
+        let parens = format!("t: {}next t{}", "(".repeat(DEPTH), ")".repeat(DEPTH));
+        compile(&parens, &HashMap::new(), &options, Format::Awmorp, "").unwrap();
+
+        // Chains of moves and bindings are type checked and their states joined in time linear in their length.
+        let applications = format!("t: {}t{}", "next (".repeat(DEPTH), ")".repeat(DEPTH));
+        compile(&applications, &HashMap::new(), &options, Format::Awmorp, "").unwrap();
+
+        let lets = format!("t:\n{}t", "let t = next t, in\n".repeat(DEPTH));
+        compile(&lets, &HashMap::new(), &options, Format::Awmorp, "").unwrap();
+    }
+}
@@ -1,10 +1,15 @@
-use crate::data::{Arm, Exp, Node, Pat};
+use crate::data::{Arm, Exp, ExpRef, Internable, Node, Pat, Track};
 use std::collections::HashSet;
 
 /// If the given expression is a match expression with 'any' patterns, they are simplified.
-pub fn remove_any<Annot>(ast: Exp<Annot>, alphabet: &HashSet<String>) -> Exp<Annot>
+/// 'any' elements of tuples are replaced by the symbols which can be stored on a track.
+pub fn remove_any<Annot>(
+    ast: Exp<Annot>,
+    alphabet: &HashSet<String>,
+    tracks: &HashSet<String>,
+) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match ast.0 {
@@ -14,7 +19,9 @@ where
                     .into_iter()
                     .map(|arm| Arm {
                         pat: match arm.pat {
-                            Pat::Any => Pat::Union(Exp::union_from_set(alphabet, &arm.exp.1)),
+                            Pat::Any => {
+                                Pat::Union(ExpRef::new(Exp::union_from_set(alphabet, &arm.exp.1)))
+                            }
                             pat => pat,
                         },
                         catch_id: arm.catch_id,
@@ -23,6 +30,21 @@ where
                     .collect(),
             },
 
+            Node::Tuple(elems) => Node::Tuple(
+                elems
+                    .into_iter()
+                    .map(|track| Track {
+                        catch_id: track.catch_id,
+                        pat: match track.pat {
+                            Pat::Any => {
+                                Pat::Union(ExpRef::new(Exp::union_from_set(tracks, &ast.1)))
+                            }
+                            pat => pat,
+                        },
+                    })
+                    .collect(),
+            ),
+
             n => n,
         },
         ast.1,
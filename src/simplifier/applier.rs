@@ -1,10 +1,12 @@
 use super::id_replacer::replace_id;
 use crate::annotater::Annot;
-use crate::data::{Exp, Node, Type};
+use crate::data::{Exp, ExpRef, Node, Type};
 
 /// Applies the given expression if it is either:
 /// - a non tape -> tape function application
 /// - a tape -> tape function application with an identifier as argument
+///
+/// Functions which take several tapes are treated as tape -> tape functions.
 pub fn apply<F>(ast: Exp<Annot>, rec: F) -> Exp<Annot>
 where
     F: Fn(Exp<Annot>) -> Exp<Annot>,
@@ -18,13 +20,15 @@ where
                     unreachable!()
                 };
 
-                if &**arg_t != &Type::Tape
-                    || &**ret_t != &Type::Tape
-                    || matches!(arg.0, Node::Identifier(_))
+                if (**arg_t != Type::Tape
+                    || (**ret_t != Type::Tape && ret_t.tape_function_arity().is_none())
+                    || matches!(arg.0, Node::Identifier(_)))
+                    && matches!(func.0, Node::Function { .. })
                 {
-                    if let Node::Function { arg: arg_id, exp } = func.0 {
-                        return rec(replace_id(*exp, &arg_id, &arg));
+                    if let Node::Function { arg: arg_id, exp } = ExpRef::unwrap_or_clone(func).0 {
+                        return rec(replace_id(ExpRef::unwrap_or_clone(exp), &arg_id, &arg));
                     }
+                    unreachable!()
                 }
 
                 Node::Application { func, arg }
@@ -1,5 +1,5 @@
 use crate::annotater::Annot;
-use crate::data::{Exp, Node, Pat};
+use crate::data::{Exp, ExpRef, Node, Pat};
 use std::collections::HashSet;
 
 /// Merges all arms in the given match expression which have equivalent expressions.
@@ -21,7 +21,7 @@ pub fn merge_arms(ast: Exp<Annot>) -> Exp<Annot> {
                             let mut set = HashSet::new();
                             assert!(pati.union_to_set(&mut set));
                             assert!(patj.union_to_set(&mut set));
-                            *patj = Exp::union_from_set(&set, &patj.1);
+                            *patj = ExpRef::new(Exp::union_from_set(&set, &patj.1));
                             found = true;
                         }
                     }
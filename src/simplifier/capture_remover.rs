@@ -1,10 +1,11 @@
 use super::id_replacer::replace_id;
 use crate::annotater::Annot;
-use crate::data::{Arm, Exp, Node, Pat, Type};
+use crate::data::{tuple_parts, Arm, Exp, ExpRef, Node, Pat, Type};
 use std::collections::HashSet;
 
 /// Removes match capture variables, replacing every reference to them with the corresponding symbol.
 /// If the given arm pattern is a union, the union is split into multiple arms, one for each symbol in the union.
+/// Elements captured by tuple patterns are replaced by the corresponding element of each symbol.
 pub fn remove_captures<F>(ast: Exp<Annot>, rec: F) -> Exp<Annot>
 where
     F: Fn(Exp<Annot>) -> Exp<Annot>,
@@ -15,7 +16,18 @@ where
                 let mut new_arms = Vec::new();
 
                 for arm in arms {
-                    if let Some(id) = arm.catch_id {
+                    let track_ids = match &arm.pat {
+                        Pat::Union(pat) => match &pat.0 {
+                            Node::Tuple(tracks) => tracks
+                                .iter()
+                                .map(|t| t.catch_id.clone())
+                                .collect::<Vec<_>>(),
+                            _ => Vec::new(),
+                        },
+                        Pat::Any => Vec::new(),
+                    };
+
+                    if arm.catch_id.is_some() || track_ids.iter().any(|id| id.is_some()) {
                         // Flatten the union into a set of symbols.
                         // It is guaranteed that at this point the pattern is a union of symbols since it has already
                         // been as simplified as possible.
@@ -23,21 +35,34 @@ where
                             Pat::Union(union) => {
                                 let mut symbols = HashSet::new();
                                 assert!(union.union_to_set(&mut symbols));
-                                (symbols, union.1 .1)
+                                (symbols, union.1 .1.clone())
                             }
                             _ => unreachable!(),
                         };
 
                         for sym in symbols {
-                            let sym = Exp(
-                                Node::Symbol(sym.clone()),
-                                Annot(Type::Symbol, pat_loc.clone()),
-                            );
+                            let symbol = |s: String| {
+                                Exp(Node::Symbol(s), Annot(Type::Symbol, pat_loc.clone()))
+                            };
+
+                            let mut exp = ExpRef::unwrap_or_clone(arm.exp.clone());
+                            if !track_ids.is_empty() {
+                                let parts = tuple_parts(&sym, track_ids.len()).unwrap();
+                                for (id, part) in track_ids.iter().zip(parts) {
+                                    if let Some(id) = id {
+                                        exp = replace_id(exp, id, &symbol(part));
+                                    }
+                                }
+                            }
 
-                            let exp = rec(replace_id(arm.exp.clone(), &id, &sym));
+                            let sym = symbol(sym);
+                            if let Some(id) = &arm.catch_id {
+                                exp = replace_id(exp, id, &sym);
+                            }
+                            let exp = ExpRef::new(rec(exp));
 
                             new_arms.push(Arm {
-                                pat: Pat::Union(sym),
+                                pat: Pat::Union(ExpRef::new(sym)),
                                 catch_id: None,
                                 exp,
                             });
@@ -1,5 +1,5 @@
 use crate::annotater::Annot;
-use crate::data::{Arm, Exp, Node, Pat, Type};
+use crate::data::{Arm, Exp, ExpRef, Node, Pat, Type};
 use std::collections::HashSet;
 
 /// If the current expression is a get application, it is replaced by a match expression with the tape as its
@@ -7,16 +7,16 @@ use std::collections::HashSet;
 pub fn remove_gets(ast: Exp<Annot>, alphabet: &HashSet<String>) -> Exp<Annot> {
     Exp(
         match ast.0 {
-            Node::Application { func, arg } => match func.0 {
+            Node::Application { func, arg } => match &func.0 {
                 Node::Identifier(id) if id == "get" => Node::Match {
                     exp: arg,
                     arms: alphabet
                         .iter()
                         .map(|sym| {
-                            let sym = Exp(
+                            let sym = ExpRef::new(Exp(
                                 Node::Symbol(sym.clone()),
                                 Annot(Type::Symbol, ast.1 .1.clone()),
-                            );
+                            ));
 
                             Arm {
                                 pat: Pat::Union(sym.clone()),
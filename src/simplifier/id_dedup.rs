@@ -1,92 +1,136 @@
-use crate::data::{Arm, Exp, Node, Pat};
+use crate::data::{Arm, Exp, ExpRef, Internable, Node, Pat, Track};
 
 use std::collections::HashMap;
 
-/// Changes all duplicate identifiers to unique identifiers. This is done by prepending duplicate identifiers with a _.
+/// Changes all duplicate identifiers to unique identifiers. This is done by prepending duplicate identifiers with a _
+/// and the number of bindings of the same identifier they are nested in, which can't be the start of an identifier in
+/// the source. Counting keeps the identifiers short in long chains of bindings, such as `let t = next t, in ...`.
 pub fn dedup_ids<Annot>(ast: Exp<Annot>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
-    traverse(ast, &HashMap::new())
+    traverse(ast, &mut HashMap::new())
 }
 
-fn traverse<Annot>(ast: Exp<Annot>, renames: &HashMap<String, String>) -> Exp<Annot>
+/// Identifiers are renamed through a stack of the names of the bindings in scope for each of them, which are pushed
+/// and popped in place, so that bindings don't copy the names in scope.
+fn traverse<Annot>(ast: Exp<Annot>, renames: &mut HashMap<String, Vec<String>>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match ast.0 {
             Node::Identifier(id) => Node::Identifier(get_id(renames, id)),
 
             Node::Union { lhs, rhs } => Node::Union {
-                lhs: Box::new(traverse(*lhs, renames)),
-                rhs: Box::new(traverse(*rhs, renames)),
+                lhs: lhs.map(|e| traverse(e, renames)),
+                rhs: rhs.map(|e| traverse(e, renames)),
             },
 
             Node::Match { exp, arms } => Node::Match {
-                exp: Box::new(traverse(*exp, renames)),
+                exp: exp.map(|e| traverse(e, renames)),
                 arms: arms
                     .into_iter()
                     .map(|arm| {
                         let pat = match arm.pat {
-                            Pat::Union(exp) => Pat::Union(traverse(exp, renames)),
+                            Pat::Union(exp) => Pat::Union(exp.map(|e| traverse(e, renames))),
                             Pat::Any => Pat::Any,
                         };
 
-                        let mut renames = renames.clone();
-                        let catch_id = if let Some(id) = arm.catch_id {
-                            Some(push_id(&mut renames, id))
-                        } else {
-                            None
+                        let mut bound = vec![];
+                        let catch_id = arm.catch_id.map(|id| push_id(renames, &mut bound, id));
+
+                        // The elements captured by a tuple pattern are bound in the arm as well.
+                        let pat = match pat {
+                            Pat::Union(exp) => {
+                                Pat::Union(ExpRef::new(match ExpRef::unwrap_or_clone(exp) {
+                                    Exp(Node::Tuple(tracks), annot) => Exp(
+                                        Node::Tuple(
+                                            tracks
+                                                .into_iter()
+                                                .map(|track| Track {
+                                                    catch_id: track
+                                                        .catch_id
+                                                        .map(|id| push_id(renames, &mut bound, id)),
+                                                    pat: track.pat,
+                                                })
+                                                .collect(),
+                                        ),
+                                        annot,
+                                    ),
+                                    exp => exp,
+                                }))
+                            }
+                            Pat::Any => Pat::Any,
                         };
 
-                        Arm {
-                            catch_id,
-                            pat,
-                            exp: traverse(arm.exp, &renames),
-                        }
+                        let exp = arm.exp.map(|e| traverse(e, renames));
+                        pop_ids(renames, bound);
+                        Arm { catch_id, pat, exp }
                     })
                     .collect(),
             },
 
             Node::Function { arg, exp } => {
-                let mut renames = renames.clone();
-                let arg = push_id(&mut renames, arg);
-                Node::Function {
-                    arg,
-                    exp: Box::new(traverse(*exp, &renames)),
-                }
+                let mut bound = vec![];
+                let arg = push_id(renames, &mut bound, arg);
+                let exp = exp.map(|e| traverse(e, renames));
+                pop_ids(renames, bound);
+                Node::Function { arg, exp }
             }
 
             Node::Application { func, arg } => Node::Application {
-                func: Box::new(traverse(*func, renames)),
-                arg: Box::new(traverse(*arg, renames)),
+                func: func.map(|e| traverse(e, renames)),
+                arg: arg.map(|e| traverse(e, renames)),
             },
 
+            Node::Tuple(tracks) => Node::Tuple(
+                tracks
+                    .into_iter()
+                    .map(|track| Track {
+                        catch_id: track.catch_id,
+                        pat: match track.pat {
+                            Pat::Union(exp) => Pat::Union(exp.map(|e| traverse(e, renames))),
+                            Pat::Any => Pat::Any,
+                        },
+                    })
+                    .collect(),
+            ),
+
             n => n,
         },
         ast.1,
     )
 }
 
-/// Pushes a new identifier to the renaming map.
-fn push_id(renames: &mut HashMap<String, String>, id: String) -> String {
-    match renames.get(&id) {
-        Some(renamed) => {
-            let renamed = format!("_{}", renamed);
-            renames.insert(id.clone(), renamed.clone());
-            renamed
-        }
-        None => {
-            renames.insert(id.clone(), id.clone());
-            id
+/// Pushes a new identifier to the renaming map, recording it in the identifiers bound by the current expression.
+fn push_id(
+    renames: &mut HashMap<String, Vec<String>>,
+    bound: &mut Vec<String>,
+    id: String,
+) -> String {
+    let names = renames.entry(id.clone()).or_default();
+    let renamed = match names.len() {
+        0 => id.clone(),
+        count => format!("_{}_{}", count, id),
+    };
+    names.push(renamed.clone());
+    bound.push(id);
+    renamed
+}
+
+/// Pops the identifiers bound by an expression from the renaming map, once it has been traversed.
+fn pop_ids(renames: &mut HashMap<String, Vec<String>>, bound: Vec<String>) {
+    for id in bound {
+        if let Some(names) = renames.get_mut(&id) {
+            names.pop();
         }
     }
 }
 
 /// Gets the renamed identifier from the renaming map.
-fn get_id(renames: &HashMap<String, String>, id: String) -> String {
-    match renames.get(&id) {
+fn get_id(renames: &HashMap<String, Vec<String>>, id: String) -> String {
+    match renames.get(&id).and_then(|names| names.last()) {
         Some(renamed) => renamed.clone(),
         None => id,
     }
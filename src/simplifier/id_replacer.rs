@@ -1,36 +1,36 @@
-use crate::data::{Arm, Exp, Node, Pat};
+use crate::data::{Arm, Exp, ExpRef, Internable, Node, Pat, Track};
 
 /// Replaces all occurences of the given identifier with the given expression.
 pub fn replace_id<Annot>(ast: Exp<Annot>, id: &str, exp: &Exp<Annot>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match ast.0 {
             Node::Identifier(id2) if id == id2 => return exp.clone(),
 
             Node::Union { lhs, rhs } => Node::Union {
-                lhs: Box::new(replace_id(*lhs, id, exp)),
-                rhs: Box::new(replace_id(*rhs, id, exp)),
+                lhs: replace_in(lhs, id, exp),
+                rhs: replace_in(rhs, id, exp),
             },
 
             Node::Match {
                 exp: match_exp,
                 arms,
             } => Node::Match {
-                exp: Box::new(replace_id(*match_exp, id, exp)),
+                exp: replace_in(match_exp, id, exp),
                 arms: arms
                     .into_iter()
                     .map(|arm| {
-                        let arm_exp = if arm.catch_id == Some(id.to_owned()) {
+                        let arm_exp = if arm.binds(id) {
                             arm.exp
                         } else {
-                            replace_id(arm.exp, id, exp)
+                            replace_in(arm.exp, id, exp)
                         };
 
                         Arm {
                             pat: match arm.pat {
-                                Pat::Union(u) => Pat::Union(replace_id(u, id, exp)),
+                                Pat::Union(u) => Pat::Union(replace_in(u, id, exp)),
                                 _ => unreachable!(),
                             },
                             catch_id: arm.catch_id,
@@ -42,16 +42,40 @@ where
 
             Node::Function { arg, exp: func_exp } if arg != id => Node::Function {
                 arg,
-                exp: Box::new(replace_id(*func_exp, id, exp)),
+                exp: replace_in(func_exp, id, exp),
             },
 
             Node::Application { func, arg } => Node::Application {
-                func: Box::new(replace_id(*func, id, exp)),
-                arg: Box::new(replace_id(*arg, id, exp)),
+                func: replace_in(func, id, exp),
+                arg: replace_in(arg, id, exp),
             },
 
+            Node::Tuple(tracks) => Node::Tuple(
+                tracks
+                    .into_iter()
+                    .map(|track| Track {
+                        catch_id: track.catch_id,
+                        pat: match track.pat {
+                            Pat::Union(u) => Pat::Union(replace_in(u, id, exp)),
+                            Pat::Any => Pat::Any,
+                        },
+                    })
+                    .collect(),
+            ),
+
             n => n,
         },
         ast.1,
     )
 }
+
+/// Replaces the identifier in a subexpression, leaving it as it is if the identifier isn't free in it.
+fn replace_in<Annot>(ast: ExpRef<Annot>, id: &str, exp: &Exp<Annot>) -> ExpRef<Annot>
+where
+    Annot: Internable,
+{
+    match ast.uses_id(id) {
+        true => ast.map(|e| replace_id(e, id, exp)),
+        false => ast,
+    }
+}
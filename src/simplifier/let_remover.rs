@@ -1,24 +1,24 @@
-use crate::data::{Exp, Node};
+use crate::data::{Exp, ExpRef, Internable, Node};
 
 /// If the given expression is a let expression, it is simplified into function applications.
 pub fn remove_lets<Annot>(ast: Exp<Annot>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match ast.0 {
             Node::Let { mut exp, binds } => {
                 for (id, _, bind) in binds.into_iter().rev() {
                     let annot = bind.1.clone();
-                    exp = Box::new(Exp(
+                    exp = ExpRef::new(Exp(
                         Node::Application {
-                            func: Box::new(Exp(Node::Function { arg: id, exp }, annot.clone())),
-                            arg: Box::new(bind),
+                            func: ExpRef::new(Exp(Node::Function { arg: id, exp }, annot.clone())),
+                            arg: bind,
                         },
                         annot,
                     ));
                 }
-                exp.0
+                ExpRef::unwrap_or_clone(exp).0
             }
 
             n => n,
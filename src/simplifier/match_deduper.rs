@@ -1,5 +1,5 @@
 use crate::annotater::Annot;
-use crate::data::{Arm, Exp, Node, Pat, Type};
+use crate::data::{Arm, Exp, ExpRef, Node, Pat, Type};
 
 use super::capture_remover::remove_captures;
 
@@ -12,30 +12,31 @@ where
 {
     Exp(
         match ast.0 {
-            Node::Match { exp, arms } => match *exp {
+            Node::Match { exp, arms } => match &*exp {
                 Exp(Node::Identifier(id), Annot(Type::Tape, loc)) => {
                     return remove_captures(
                         Exp(
                             Node::Match {
-                                exp: Box::new(Exp(
+                                exp: ExpRef::new(Exp(
                                     Node::Identifier(id.clone()),
-                                    Annot(Type::Tape, loc),
+                                    Annot(Type::Tape, loc.clone()),
                                 )),
                                 arms: arms
                                     .into_iter()
                                     .map(|arm| Arm {
                                         pat: arm.pat.clone(),
                                         catch_id: Some("get".to_owned()),
-                                        exp: traverse(
-                                            arm.exp,
-                                            &id,
-                                            &match arm.pat {
-                                                Pat::Union(Exp(Node::Symbol(s), _)) => {
-                                                    Some(s.clone())
-                                                }
-                                                _ => None,
+                                        exp: ExpRef::new(traverse(
+                                            ExpRef::unwrap_or_clone(arm.exp),
+                                            id,
+                                            &match &arm.pat {
+                                                Pat::Union(pat) => match &pat.0 {
+                                                    Node::Symbol(s) => Some(s.clone()),
+                                                    _ => None,
+                                                },
+                                                Pat::Any => None,
                                             },
-                                        ),
+                                        )),
                                     })
                                     .collect(),
                             },
@@ -57,9 +58,9 @@ fn traverse(ast: Exp<Annot>, id: &str, sym: &Option<String>) -> Exp<Annot> {
     Exp(
         match ast.0 {
             Node::Match { exp, arms } => {
-                let (exp, arms) = match exp.0 {
+                let (exp, arms) = match &exp.0 {
                     Node::Identifier(i) if i == id => (
-                        Box::new(Exp(Node::Identifier("get".to_owned()), exp.1)),
+                        ExpRef::new(Exp(Node::Identifier("get".to_owned()), exp.1.clone())),
                         arms,
                     ),
                     _ => (exp, arms),
@@ -73,11 +74,13 @@ fn traverse(ast: Exp<Annot>, id: &str, sym: &Option<String>) -> Exp<Annot> {
                             if arm.catch_id != Some(id.to_owned()) {
                                 Some(Arm {
                                     pat: match arm.pat {
-                                        Pat::Union(pat) => Pat::Union(traverse(pat, id, sym)),
+                                        Pat::Union(pat) => {
+                                            Pat::Union(pat.map(|e| traverse(e, id, sym)))
+                                        }
                                         Pat::Any => Pat::Any,
                                     },
                                     catch_id: arm.catch_id,
-                                    exp: traverse(arm.exp, id, sym),
+                                    exp: arm.exp.map(|e| traverse(e, id, sym)),
                                 })
                             } else {
                                 None
@@ -89,18 +92,20 @@ fn traverse(ast: Exp<Annot>, id: &str, sym: &Option<String>) -> Exp<Annot> {
 
             Node::Function { arg, exp } if arg != id => Node::Function {
                 arg,
-                exp: Box::new(traverse(*exp, id, sym)),
+                exp: exp.map(|e| traverse(e, id, sym)),
             },
 
-            Node::Application { func, arg } => match (*func, *arg) {
-                (func, Exp(Node::Identifier(i), _)) if i == id && is_set(&func, sym) => {
-                    return Exp(Node::Identifier(id.to_owned()), ast.1);
+            Node::Application { func, arg } => {
+                match (ExpRef::unwrap_or_clone(func), ExpRef::unwrap_or_clone(arg)) {
+                    (func, Exp(Node::Identifier(i), _)) if i == id && is_set(&func, sym) => {
+                        return Exp(Node::Identifier(id.to_owned()), ast.1);
+                    }
+                    (func, arg) => Node::Application {
+                        func: ExpRef::new(traverse(func, id, sym)),
+                        arg: ExpRef::new(traverse(arg, id, sym)),
+                    },
                 }
-                (func, arg) => Node::Application {
-                    func: Box::new(traverse(func, id, sym)),
-                    arg: Box::new(traverse(arg, id, sym)),
-                },
-            },
+            }
 
             n => n,
         },
@@ -115,10 +120,10 @@ fn is_set(func: &Exp<Annot>, sym: &Option<String>) -> bool {
     };
 
     match &func.0 {
-        Node::Application { func, arg } => match (&func.0, &arg.0) {
-            (Node::Identifier(id), Node::Symbol(s)) if s == sym && id == "set" => true,
-            _ => false,
-        },
+        Node::Application { func, arg } => matches!(
+            (&func.0, &arg.0),
+            (Node::Identifier(id), Node::Symbol(s)) if s == sym && id == "set"
+        ),
         _ => false,
     }
 }
@@ -1,17 +1,67 @@
 use crate::annotater::Annot;
-use crate::data::{Arm, Exp, Node, Pat};
+use crate::data::{Arm, Exp, ExpRef, Node, Pat, Type};
 use std::collections::HashSet;
 
-/// Merges all matches which contain matches in their expressions.
-pub fn merge_matches(ast: Exp<Annot>) -> Exp<Annot> {
+/// Merges all matches which contain matches of symbols in their expressions. If the arms of the inner match aren't
+/// all symbols, the outer match is moved into each of them instead, so that it matches the symbol they return.
+/// Matches whose inner match returns tapes are left for the generator, which only accepts them if every arm returns
+/// the same tape.
+pub fn merge_matches<F>(ast: Exp<Annot>, rec: F) -> Exp<Annot>
+where
+    F: Fn(Exp<Annot>) -> Exp<Annot>,
+{
     Exp(
         match ast.0 {
-            Node::Match { exp, arms } => {
-                if let Node::Match {
-                    exp: inner_exp,
-                    arms: inner_arms,
-                } = exp.0
+            Node::Match { exp, arms } if matches!(exp.0, Node::Match { .. }) => {
+                if let Exp(
+                    Node::Match {
+                        exp: inner_exp,
+                        arms: inner_arms,
+                    },
+                    inner_annot,
+                ) = ExpRef::unwrap_or_clone(exp)
                 {
+                    if !inner_arms
+                        .iter()
+                        .all(|arm| matches!(arm.exp.0, Node::Symbol(_) | Node::Abort))
+                    {
+                        if inner_annot.0 == Type::Symbol {
+                            return Exp(
+                                Node::Match {
+                                    exp: inner_exp,
+                                    arms: inner_arms
+                                        .into_iter()
+                                        .map(|arm| Arm {
+                                            exp: ExpRef::new(rec(Exp(
+                                                Node::Match {
+                                                    exp: arm.exp,
+                                                    arms: arms.clone(),
+                                                },
+                                                ast.1.clone(),
+                                            ))),
+                                            ..arm
+                                        })
+                                        .collect(),
+                                },
+                                ast.1,
+                            );
+                        }
+
+                        return Exp(
+                            Node::Match {
+                                exp: ExpRef::new(Exp(
+                                    Node::Match {
+                                        exp: inner_exp,
+                                        arms: inner_arms,
+                                    },
+                                    inner_annot,
+                                )),
+                                arms,
+                            },
+                            ast.1,
+                        );
+                    }
+
                     let mut new_arms = Vec::new();
 
                     for arm in arms {
@@ -19,7 +69,7 @@ pub fn merge_matches(ast: Exp<Annot>) -> Exp<Annot> {
                             Pat::Union(u) => {
                                 let mut set = HashSet::new();
                                 assert!(u.union_to_set(&mut set));
-                                (set, u.1)
+                                (set, u.1.clone())
                             }
                             _ => unreachable!(),
                         };
@@ -31,17 +81,14 @@ pub fn merge_matches(ast: Exp<Annot>) -> Exp<Annot> {
                                     Pat::Union(pat) => assert!(pat.union_to_set(&mut set)),
                                     _ => unreachable!(),
                                 },
-                                Node::Symbol(_) => {}
-                                _ => unreachable!(
-                                    "\n{}\n{:?}\n{}",
-                                    inner_exp, inner_arm.pat, inner_arm.exp
-                                ),
+                                Node::Symbol(_) | Node::Abort => {}
+                                _ => unreachable!(),
                             }
                         }
 
                         if !set.is_empty() {
                             new_arms.push(Arm {
-                                pat: Pat::Union(Exp::union_from_set(&set, &annot)),
+                                pat: Pat::Union(ExpRef::new(Exp::union_from_set(&set, &annot))),
                                 exp: arm.exp.clone(),
                                 catch_id: None,
                             });
@@ -57,7 +104,7 @@ pub fn merge_matches(ast: Exp<Annot>) -> Exp<Annot> {
                         }
                     }
                 } else {
-                    Node::Match { exp, arms }
+                    unreachable!()
                 }
             }
 
@@ -1,15 +1,16 @@
-use crate::data::{Arm, Exp, Node};
+use crate::data::{Arm, Exp, ExpRef, Internable, Node, Pat};
 
 /// If the current expression is an application in which either the function or the argument is a match, the match is
-/// moved up in the AST so that the expression becomes a match of applications.
+/// moved up in the AST so that the expression becomes a match of applications. Matches in the elements of tuples are
+/// moved up in the same way.
 pub fn move_matches<Annot>(ast: Exp<Annot>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match ast.0 {
-            Node::Application { func, arg } => {
-                if let Node::Match { exp, arms } = func.0 {
+            Node::Application { func, arg } if matches!(func.0, Node::Match { .. }) => {
+                if let Node::Match { exp, arms } = ExpRef::unwrap_or_clone(func).0 {
                     Node::Match {
                         exp,
                         arms: arms
@@ -17,17 +18,23 @@ where
                             .map(|arm| Arm {
                                 pat: arm.pat,
                                 catch_id: arm.catch_id,
-                                exp: move_matches(Exp(
+                                exp: ExpRef::new(move_matches(Exp(
                                     Node::Application {
-                                        func: Box::new(arm.exp),
+                                        func: arm.exp,
                                         arg: arg.clone(),
                                     },
                                     ast.1.clone(),
-                                )),
+                                ))),
                             })
                             .collect(),
                     }
-                } else if let Node::Match { exp, arms } = arg.0 {
+                } else {
+                    unreachable!()
+                }
+            }
+
+            Node::Application { func, arg } if matches!(arg.0, Node::Match { .. }) => {
+                if let Node::Match { exp, arms } = ExpRef::unwrap_or_clone(arg).0 {
                     Node::Match {
                         exp,
                         arms: arms
@@ -35,18 +42,50 @@ where
                             .map(|arm| Arm {
                                 pat: arm.pat,
                                 catch_id: arm.catch_id,
-                                exp: Exp(
+                                exp: ExpRef::new(Exp(
                                     Node::Application {
                                         func: func.clone(),
-                                        arg: Box::new(arm.exp),
+                                        arg: arm.exp,
                                     },
                                     ast.1.clone(),
-                                ),
+                                )),
                             })
                             .collect(),
                     }
                 } else {
-                    Node::Application { func, arg }
+                    unreachable!()
+                }
+            }
+
+            Node::Tuple(tracks) => {
+                let index = tracks.iter().position(
+                    |t| matches!(&t.pat, Pat::Union(pat) if matches!(pat.0, Node::Match { .. })),
+                );
+                let (exp, arms) = match index.map(|i| &tracks[i].pat) {
+                    Some(Pat::Union(pat)) => match &pat.0 {
+                        Node::Match { exp, arms } => (exp.clone(), arms.clone()),
+                        _ => unreachable!(),
+                    },
+                    _ => return Exp(Node::Tuple(tracks), ast.1),
+                };
+
+                Node::Match {
+                    exp,
+                    arms: arms
+                        .into_iter()
+                        .map(|arm| {
+                            let mut tracks = tracks.clone();
+                            tracks[index.unwrap()].pat = Pat::Union(arm.exp);
+                            Arm {
+                                pat: arm.pat,
+                                catch_id: arm.catch_id,
+                                exp: ExpRef::new(move_matches(Exp(
+                                    Node::Tuple(tracks),
+                                    ast.1.clone(),
+                                ))),
+                            }
+                        })
+                        .collect(),
                 }
             }
 
@@ -1,5 +1,5 @@
 use crate::annotater::Annot;
-use crate::data::{Exp, Node, Pat};
+use crate::data::{Exp, ExpRef, Node, Pat};
 
 use std::collections::HashSet;
 
@@ -7,7 +7,7 @@ use std::collections::HashSet;
 pub fn match_const(ast: Exp<Annot>) -> Exp<Annot> {
     Exp(
         match ast.0 {
-            Node::Match { exp, arms } => match exp.0 {
+            Node::Match { exp, arms } => match &exp.0 {
                 Node::Symbol(sym) => {
                     let arm = arms.into_iter().find(|arm| {
                         assert!(arm.catch_id.is_none());
@@ -19,19 +19,16 @@ pub fn match_const(ast: Exp<Annot>) -> Exp<Annot> {
                         let mut set = HashSet::new();
                         assert!(pat.union_to_set(&mut set));
 
-                        set.contains(&sym)
+                        set.contains(sym)
                     });
 
                     match arm {
-                        Some(arm) => return arm.exp,
+                        Some(arm) => return ExpRef::unwrap_or_clone(arm.exp),
                         None => Node::Abort,
                     }
                 }
 
-                n => Node::Match {
-                    exp: Box::new(Exp(n, exp.1)),
-                    arms,
-                },
+                _ => Node::Match { exp, arms },
             },
 
             n => n,
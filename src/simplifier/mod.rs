@@ -11,7 +11,10 @@ pub mod match_merger;
 pub mod match_mover;
 pub mod matcher;
 pub mod optionals_remover;
+pub mod pass_manager;
 pub mod pat_dedup;
 pub mod trivial_remover;
+pub mod tuple_folder;
+pub mod verifier;
 
 mod id_replacer;
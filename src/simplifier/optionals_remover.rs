@@ -1,44 +1,40 @@
-use crate::data::{Arm, Exp, Node, Pat};
+use crate::data::{Arm, Exp, Internable, Node, Pat, Track};
 use std::collections::HashSet;
 
 /// If the given expression is a let expression, it is simplified into function applications.
 pub fn remove_optionals<Annot>(ast: Exp<Annot>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     traverse(ast, &HashSet::new())
 }
 
 fn traverse<Annot>(ast: Exp<Annot>, env: &HashSet<String>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match ast.0 {
             Node::Union { lhs, rhs } => Node::Union {
-                lhs: Box::new(traverse(*lhs, env)),
-                rhs: Box::new(traverse(*rhs, env)),
+                lhs: lhs.map(|e| traverse(e, env)),
+                rhs: rhs.map(|e| traverse(e, env)),
             },
 
             Node::Match {
                 exp: match_exp,
                 arms,
             } => Node::Match {
-                exp: Box::new(traverse(*match_exp, env)),
+                exp: match_exp.map(|e| traverse(e, env)),
                 arms: arms
                     .into_iter()
                     .map(|arm| {
-                        let arm_exp = if let Some(id) = &arm.catch_id {
-                            let mut env = env.clone();
-                            env.insert(id.clone());
-                            traverse(arm.exp, &env)
-                        } else {
-                            traverse(arm.exp, env)
-                        };
+                        let mut arm_env = env.clone();
+                        arm_env.extend(arm.catch_ids().into_iter().cloned());
+                        let arm_exp = arm.exp.map(|e| traverse(e, &arm_env));
 
                         Arm {
                             pat: match arm.pat {
-                                Pat::Union(u) => Pat::Union(traverse(u, env)),
+                                Pat::Union(u) => Pat::Union(u.map(|e| traverse(e, env))),
                                 Pat::Any => Pat::Any,
                             },
                             catch_id: arm.catch_id,
@@ -60,12 +56,13 @@ where
                         continue;
                     }
 
-                    new_binds.push((bind.0.clone(), false, traverse(bind.2, &env)));
+                    let exp = bind.2.map(|e| traverse(e, &env));
+                    new_binds.push((bind.0.clone(), false, exp));
                     env.insert(bind.0.clone());
                 }
 
                 Node::Let {
-                    exp: Box::new(traverse(*let_exp, &env)),
+                    exp: let_exp.map(|e| traverse(e, &env)),
                     binds: new_binds,
                 }
             }
@@ -75,15 +72,28 @@ where
                 env.insert(arg.clone());
                 Node::Function {
                     arg,
-                    exp: Box::new(traverse(*func_exp, &env)),
+                    exp: func_exp.map(|e| traverse(e, &env)),
                 }
             }
 
             Node::Application { func, arg } => Node::Application {
-                func: Box::new(traverse(*func, env)),
-                arg: Box::new(traverse(*arg, env)),
+                func: func.map(|e| traverse(e, env)),
+                arg: arg.map(|e| traverse(e, env)),
             },
 
+            Node::Tuple(tracks) => Node::Tuple(
+                tracks
+                    .into_iter()
+                    .map(|track| Track {
+                        catch_id: track.catch_id,
+                        pat: match track.pat {
+                            Pat::Union(u) => Pat::Union(u.map(|e| traverse(e, env))),
+                            Pat::Any => Pat::Any,
+                        },
+                    })
+                    .collect(),
+            ),
+
             n => n,
         },
         ast.1,
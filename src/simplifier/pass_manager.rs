@@ -0,0 +1,515 @@
+use super::verifier::{self, Annotation, Invariant};
+use crate::data::{Exp, ExpRef, Internable};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Names of every pass which can be run by the pass manager.
+pub const PASSES: &[&str] = &[
+    "optionals_remover",
+    "let_remover",
+    "any_remover",
+    "id_dedup",
+    "tuple_folder",
+    "get_remover",
+    "match_mover",
+    "applier",
+    "trivial_remover",
+    "capture_remover",
+    "abort_spreader",
+    "matcher",
+    "pat_dedup",
+    "match_merger",
+    "match_deduper",
+    "arm_merger",
+];
+
+/// Passes whose results later passes rely on without checking them, which can't be disabled.
+pub const REQUIRED_PASSES: &[&str] = &[
+    "let_remover",
+    "any_remover",
+    "applier",
+    "capture_remover",
+    "abort_spreader",
+    "matcher",
+];
+
+/// Function which simplifies the expressions built by a pass, by running its phase on them.
+pub type Rec<'r, Annot> = &'r dyn Fn(Exp<Annot>) -> Exp<Annot>;
+
+/// Named rewrite of the AST, such as one of the `simplifier::*` modules.
+pub struct Pass<'a, Annot> {
+    pub name: &'static str,
+    #[allow(clippy::type_complexity)]
+    rewrite: Box<dyn Fn(Exp<Annot>, Rec<Annot>) -> Exp<Annot> + 'a>,
+    /// Time spent on the pass since it was last reported to the pass manager.
+    timing: Cell<Timing>,
+}
+
+/// Sequence of passes which are run together.
+pub struct Phase<'a, Annot> {
+    pub name: &'static str,
+    /// Should the passes be applied to every expression, from the leaves up to the root, instead of only once to the
+    /// whole tree?
+    pub bottom_up: bool,
+    pub passes: Vec<Pass<'a, Annot>>,
+}
+
+/// Options which control how the passes are run.
+#[derive(Debug, Clone)]
+pub struct PassOptions {
+    /// Passes which are skipped.
+    pub disabled: Vec<String>,
+    /// Passes whose rewrites are recorded.
+    pub dump_after: Vec<String>,
+    /// Maximum number of times each phase is run, while it keeps changing the tree.
+    pub max_iterations: usize,
+    /// Should the time spent on each pass be measured? Reading the clock for every rewrite is slow enough to make
+    /// compilation noticeably slower.
+    pub timed: bool,
+    /// Should the invariants of the tree be checked after each phase? The passes of a phase which breaks one are
+    /// run again, checking each of their rewrites, to find the one which broke it. The invariants which a pass relies
+    /// on are also checked on every expression before it is rewritten by the pass.
+    pub verified: bool,
+}
+
+/// Time spent on a pass and number of times it was run.
+#[derive(Debug, Clone, Copy, Default)]
+struct Timing {
+    time: Duration,
+    runs: usize,
+}
+
+/// Pass enabled in a phase, along with whether it is dumped and the invariants which hold after it runs.
+struct Enabled<'p, 'a, Annot> {
+    pass: &'p Pass<'a, Annot>,
+    dumped: bool,
+    invariants: Vec<Invariant>,
+}
+
+/// Runs phases of passes, recording the time spent on each pass and the rewrites of the dumped passes.
+pub struct PassManager {
+    options: PassOptions,
+    /// Invariants established by the passes run so far.
+    established: RefCell<Vec<Invariant>>,
+    /// First invariant found not to hold while running a phase, along with the pass which broke or needed it. No
+    /// more rewrites are done once it is found, as the passes may panic on the broken tree.
+    violation: RefCell<Option<(verifier::Violation, String)>>,
+    timings: RefCell<BTreeMap<(&'static str, &'static str), Timing>>,
+    iterations: RefCell<Vec<(&'static str, usize)>>,
+    dumps: RefCell<Vec<String>>,
+    /// Time spent on passes run by the pass currently running, which is left out of its own time.
+    nested: Cell<Duration>,
+}
+
+impl<'a, Annot> Pass<'a, Annot> {
+    /// Creates a pass which doesn't build expressions needing further simplification.
+    pub fn new<F>(name: &'static str, rewrite: F) -> Self
+    where
+        F: Fn(Exp<Annot>) -> Exp<Annot> + 'a,
+    {
+        Self::recursive(name, move |e, _| rewrite(e))
+    }
+
+    /// Creates a pass which simplifies the expressions it builds with the given function.
+    pub fn recursive<F>(name: &'static str, rewrite: F) -> Self
+    where
+        F: Fn(Exp<Annot>, Rec<Annot>) -> Exp<Annot> + 'a,
+    {
+        debug_assert!(PASSES.contains(&name), "Unknown pass {}", name);
+        Self {
+            name,
+            rewrite: Box::new(rewrite),
+            timing: Cell::new(Timing::default()),
+        }
+    }
+}
+
+impl PassManager {
+    /// Creates a pass manager, checking that every pass named in the options exists and that no required pass is
+    /// disabled.
+    pub fn new(options: PassOptions) -> Result<Self, String> {
+        for name in options.disabled.iter().chain(options.dump_after.iter()) {
+            if !PASSES.contains(&name.as_str()) {
+                return Err(format!(
+                    "Unknown pass '{}', expected one of {}",
+                    name,
+                    PASSES.join(", ")
+                ));
+            }
+        }
+        if let Some(name) = options
+            .disabled
+            .iter()
+            .find(|d| REQUIRED_PASSES.contains(&d.as_str()))
+        {
+            return Err(format!(
+                "Pass '{}' can't be disabled, as later passes rely on it",
+                name
+            ));
+        }
+        if options.max_iterations == 0 {
+            return Err("Each phase must run at least once".to_owned());
+        }
+
+        Ok(Self {
+            options,
+            established: RefCell::new(vec![
+                Invariant::BoundIdentifiers,
+                Invariant::ConsistentTypes,
+            ]),
+            violation: RefCell::new(None),
+            timings: RefCell::new(BTreeMap::new()),
+            iterations: RefCell::new(Vec::new()),
+            dumps: RefCell::new(Vec::new()),
+            nested: Cell::new(Duration::ZERO),
+        })
+    }
+
+    /// Runs a phase on the tree, repeating it while it changes the tree, up to the maximum number of iterations.
+    /// Fails if the tree breaks an invariant, when verification is enabled.
+    pub fn run<Annot>(&self, phase: &Phase<Annot>, ast: Exp<Annot>) -> Result<Exp<Annot>, String>
+    where
+        Annot: Internable + fmt::Display + Annotation,
+    {
+        self.verify(&ast, &[])
+            .map_err(|v| format!("{}, before the {} phase", v, phase.name))?;
+
+        // Invariants are expected from the position of their pass, so disabling it is reported as the breakage.
+        let mut invariants = self.established.borrow().clone();
+        let mut passes = vec![];
+        let mut disabled = vec![];
+        for pass in phase.passes.iter() {
+            invariants.extend(verifier::established_by(pass.name));
+            match self.options.disabled.iter().any(|d| d == pass.name) {
+                true => disabled.push(pass.name),
+                false => passes.push(Enabled {
+                    pass,
+                    dumped: self.options.dump_after.iter().any(|d| d == pass.name),
+                    invariants: invariants.clone(),
+                }),
+            }
+        }
+
+        // Subexpressions occurring several times in the tree are only rewritten once per traversal.
+        let traverse = |ast: Exp<Annot>, locate| {
+            let done = RefCell::new(HashMap::new());
+            match phase.bottom_up {
+                true => {
+                    ast.transform_shared(&|e| self.rewrite(phase, &passes, locate, &done, e), &done)
+                }
+                false => self.rewrite(phase, &passes, locate, &done, ast),
+            }
+        };
+
+        let mut ast = ast;
+        let mut iterations = 0;
+        while iterations < self.options.max_iterations {
+            iterations += 1;
+            let before = match iterations < self.options.max_iterations || self.options.verified {
+                true => Some(ast.clone()),
+                false => None,
+            };
+            ast = traverse(ast, false);
+            if let Some((v, pass)) = self.violation.take() {
+                return Err(format!("{}, {}", v, pass));
+            }
+
+            // Find the rewrite which broke an invariant by running the phase again, checking every rewrite.
+            if let (Some(before), Err(v)) = (&before, self.verify_with(&ast, &invariants)) {
+                if let Some(pass) = disabled
+                    .iter()
+                    .find(|&&p| verifier::established_by(p) == Some(v.invariant))
+                {
+                    return Err(format!(
+                        "{}, after the {} pass of the {} phase, which is disabled",
+                        v, pass, phase.name
+                    ));
+                }
+                traverse(before.clone(), true);
+                return Err(match self.violation.take() {
+                    Some((v, pass)) => format!("{}, {}", v, pass),
+                    None => format!("{}, after the {} phase", v, phase.name),
+                });
+            }
+            if before.as_ref() == Some(&ast) {
+                break;
+            }
+        }
+        self.iterations.borrow_mut().push((phase.name, iterations));
+        *self.established.borrow_mut() = invariants;
+
+        let mut timings = self.timings.borrow_mut();
+        for pass in phase.passes.iter() {
+            let timing = pass.timing.take();
+            let total = timings.entry((phase.name, pass.name)).or_default();
+            total.time += timing.time;
+            total.runs += timing.runs;
+        }
+        Ok(ast)
+    }
+
+    /// Checks that the tree satisfies the invariants established so far, along with the given ones, when
+    /// verification is enabled.
+    pub fn verify<Annot: Annotation>(
+        &self,
+        ast: &Exp<Annot>,
+        invariants: &[Invariant],
+    ) -> Result<(), verifier::Violation> {
+        let mut all = self.established.borrow().clone();
+        all.extend_from_slice(invariants);
+        self.verify_with(ast, &all)
+    }
+
+    /// Checks that the tree satisfies the given invariants, when verification is enabled.
+    fn verify_with<Annot: Annotation>(
+        &self,
+        ast: &Exp<Annot>,
+        invariants: &[Invariant],
+    ) -> Result<(), verifier::Violation> {
+        match self.options.verified {
+            true => verifier::verify(ast, invariants),
+            false => Ok(()),
+        }
+    }
+
+    /// Applies the enabled passes of the phase to an expression. The invariants needed by each pass are checked
+    /// before it runs, and while locating a broken invariant, each rewrite is checked too. The first invariant found
+    /// not to hold is recorded, and the expression is left as it is from then on.
+    fn rewrite<Annot>(
+        &self,
+        phase: &Phase<Annot>,
+        passes: &[Enabled<Annot>],
+        locate: bool,
+        done: &RefCell<HashMap<ExpRef<Annot>, ExpRef<Annot>>>,
+        e: Exp<Annot>,
+    ) -> Exp<Annot>
+    where
+        Annot: Internable + fmt::Display + Annotation,
+    {
+        let rec = |e: Exp<Annot>| match phase.bottom_up {
+            true => e.transform_shared(&|e| self.rewrite(phase, passes, locate, done, e), done),
+            false => self.rewrite(phase, passes, locate, done, e),
+        };
+
+        passes.iter().fold(e, |e, enabled| {
+            let pass = enabled.pass;
+            if self.violation.borrow().is_some() {
+                return e;
+            }
+            if self.options.verified {
+                if let Err(v) = verifier::verify_node(&e, verifier::required_by(pass.name)) {
+                    let place =
+                        format!("before the {} pass of the {} phase", pass.name, phase.name);
+                    *self.violation.borrow_mut() = Some((v, place));
+                    return e;
+                }
+            }
+
+            let before = match enabled.dumped || locate {
+                true => Some(e.clone()),
+                false => None,
+            };
+
+            // The pass may run itself again on the expressions it builds, so its timing is only read afterwards.
+            let (e, time) = match self.options.timed {
+                true => {
+                    let outer = self.nested.replace(Duration::ZERO);
+                    let start = Instant::now();
+                    let e = (pass.rewrite)(e, &rec);
+                    let elapsed = start.elapsed();
+                    let inner = self.nested.replace(outer + elapsed);
+                    (e, elapsed.saturating_sub(inner))
+                }
+                false => ((pass.rewrite)(e, &rec), Duration::ZERO),
+            };
+            let mut timing = pass.timing.get();
+            timing.time += time;
+            timing.runs += 1;
+            pass.timing.set(timing);
+
+            match before {
+                Some(before) if locate => {
+                    if let Err(v) = verifier::verify_rewrite(&before, &e, &enabled.invariants) {
+                        let place =
+                            format!("after the {} pass of the {} phase", pass.name, phase.name);
+                        *self.violation.borrow_mut() = Some((v, place));
+                    }
+                }
+                Some(before) if before != e => self.dumps.borrow_mut().push(format!(
+                    "-------- After {} ({}) --------\n{:#}=>\n{:#}",
+                    pass.name, phase.name, before, e
+                )),
+                _ => {}
+            }
+            e
+        })
+    }
+
+    /// Returns the rewrites recorded for the dumped passes since the last call.
+    pub fn take_dumps(&self) -> Vec<String> {
+        std::mem::take(&mut self.dumps.borrow_mut())
+    }
+
+    /// Returns a report of the time spent on each pass, if it was measured, and the number of iterations of each
+    /// phase.
+    pub fn timings(&self) -> String {
+        let mut report = String::new();
+        for ((phase, pass), timing) in self.timings.borrow().iter() {
+            report += &format!(
+                "{:>12.3?}  {:>8} runs  {} ({})\n",
+                timing.time, timing.runs, pass, phase
+            );
+        }
+        for (phase, iterations) in self.iterations.borrow().iter() {
+            report += &format!(
+                "{} ran {} time{}\n",
+                phase,
+                iterations,
+                if *iterations == 1 { "" } else { "s" }
+            );
+        }
+        report
+    }
+}
+
+impl Default for PassOptions {
+    fn default() -> Self {
+        Self {
+            disabled: Vec::new(),
+            dump_after: Vec::new(),
+            max_iterations: 1,
+            timed: false,
+            verified: cfg!(debug_assertions),
+        }
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new(PassOptions::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Node, Pat, TokenLoc, Track};
+
+    fn symbol(s: &str) -> Exp<TokenLoc> {
+        let loc = TokenLoc {
+            line: 1,
+            col: 1,
+            import: None,
+        };
+        Exp(Node::Symbol(s.to_owned()), loc)
+    }
+
+    #[test]
+    fn test_pass_manager() {
+        // Rewrites 'a' into 'b' and 'b' into 'c', one step per iteration.
+        let phase = Phase {
+            name: "test",
+            bottom_up: true,
+            passes: vec![Pass::new("pat_dedup", |e: Exp<TokenLoc>| match &e.0 {
+                Node::Symbol(s) if s == "a" => symbol("b"),
+                Node::Symbol(s) if s == "b" => symbol("c"),
+                _ => e,
+            })],
+        };
+
+        let manager = PassManager::new(PassOptions {
+            dump_after: vec!["pat_dedup".to_owned()],
+            max_iterations: 4,
+            ..PassOptions::default()
+        })
+        .unwrap();
+        assert_eq!(manager.run(&phase, symbol("a")), Ok(symbol("c")));
+        assert_eq!(manager.take_dumps().len(), 2);
+        assert!(manager.take_dumps().is_empty());
+        assert!(manager.timings().contains("test ran 3 times"));
+
+        let manager = PassManager::new(PassOptions {
+            disabled: vec!["pat_dedup".to_owned()],
+            ..PassOptions::default()
+        })
+        .unwrap();
+        assert_eq!(manager.run(&phase, symbol("a")), Ok(symbol("a")));
+
+        let error = PassManager::new(PassOptions {
+            disabled: vec!["applier".to_owned()],
+            ..PassOptions::default()
+        })
+        .err();
+        assert_eq!(
+            error.as_deref(),
+            Some("Pass 'applier' can't be disabled, as later passes rely on it")
+        );
+
+        assert!(PassManager::new(PassOptions {
+            disabled: vec!["unknown".to_owned()],
+            ..PassOptions::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_verified_pass() {
+        // Rewrites 'a' into an identifier which isn't bound anywhere.
+        let phase = Phase {
+            name: "test",
+            bottom_up: true,
+            passes: vec![Pass::new("applier", |e: Exp<TokenLoc>| match &e.0 {
+                Node::Symbol(s) if s == "a" => Exp(Node::Identifier("x".to_owned()), e.1),
+                _ => e,
+            })],
+        };
+
+        let manager = PassManager::new(PassOptions {
+            verified: true,
+            ..PassOptions::default()
+        })
+        .unwrap();
+        assert_eq!(manager.run(&phase, symbol("b")), Ok(symbol("b")));
+        let error = manager.run(&phase, symbol("a")).unwrap_err();
+        assert!(error.contains("every identifier is bound"));
+        assert!(error.ends_with("after the applier pass of the test phase"));
+    }
+
+    #[test]
+    fn test_required_invariants() {
+        // Rewrites 'a' into a tuple with an 'any' track, which the matcher can't handle.
+        let phase = Phase {
+            name: "test",
+            bottom_up: true,
+            passes: vec![
+                Pass::new("abort_spreader", |e: Exp<TokenLoc>| match &e.0 {
+                    Node::Symbol(s) if s == "a" => {
+                        let track = Track {
+                            catch_id: None,
+                            pat: Pat::Any,
+                        };
+                        Exp(Node::Tuple(vec![track]), e.1)
+                    }
+                    _ => e,
+                }),
+                Pass::new("matcher", |e: Exp<TokenLoc>| match &e.0 {
+                    Node::Tuple(_) => panic!("the matcher ran on an 'any' track"),
+                    _ => e,
+                }),
+            ],
+        };
+
+        let manager = PassManager::new(PassOptions {
+            verified: true,
+            ..PassOptions::default()
+        })
+        .unwrap();
+        assert_eq!(manager.run(&phase, symbol("b")), Ok(symbol("b")));
+        let error = manager.run(&phase, symbol("a")).unwrap_err();
+        assert!(error.contains("the tuple has an 'any' track"));
+        assert!(error.ends_with("before the matcher pass of the test phase"));
+    }
+}
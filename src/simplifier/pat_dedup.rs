@@ -1,5 +1,5 @@
 use crate::annotater::Annot;
-use crate::data::{Arm, Exp, Node, Pat};
+use crate::data::{Arm, Exp, ExpRef, Node, Pat};
 use std::collections::HashSet;
 
 /// If the given expression is a match expression, any duplicate patterns are removed.
@@ -16,7 +16,7 @@ pub fn dedup_patterns(ast: Exp<Annot>) -> Exp<Annot> {
                     let annot = match arm.pat {
                         Pat::Union(pat) => {
                             assert!(pat.union_to_set(&mut set));
-                            pat.1
+                            pat.1.clone()
                         }
                         _ => unreachable!(),
                     };
@@ -27,7 +27,7 @@ pub fn dedup_patterns(ast: Exp<Annot>) -> Exp<Annot> {
                     }
 
                     new_arms.push(Arm {
-                        pat: Pat::Union(Exp::union_from_set(&set, &annot)),
+                        pat: Pat::Union(ExpRef::new(Exp::union_from_set(&set, &annot))),
                         catch_id: None,
                         exp: arm.exp,
                     });
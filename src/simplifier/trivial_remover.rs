@@ -1,4 +1,4 @@
-use crate::data::{Exp, Node, Pat};
+use crate::data::{Exp, ExpRef, Internable, Node};
 
 /// If the given expression is a trivial application of a function, simplifies it.
 /// - identity functions: `(x: x) y` -> `y`
@@ -6,25 +6,19 @@ use crate::data::{Exp, Node, Pat};
 /// - unused functions: `(x: a) y` -> `a`
 pub fn remove_trivial<Annot>(exp: Exp<Annot>) -> Exp<Annot>
 where
-    Annot: Clone,
+    Annot: Internable,
 {
     Exp(
         match exp.0 {
             Node::Application { func, arg } => {
                 if is_identity(&func) {
-                    arg.0
+                    ExpRef::unwrap_or_clone(arg).0
                 } else if let Some(func) = is_application(&func) {
-                    Node::Application {
-                        func: Box::new(func),
-                        arg: Box::new(*arg),
-                    }
+                    Node::Application { func, arg }
                 } else if let Some(exp) = is_unused(&func) {
-                    exp.0
+                    ExpRef::unwrap_or_clone(exp).0
                 } else {
-                    Node::Application {
-                        func: Box::new(*func),
-                        arg: Box::new(*arg),
-                    }
+                    Node::Application { func, arg }
                 }
             }
 
@@ -37,23 +31,17 @@ where
 /// Checks if a function expression is an identity function.
 fn is_identity<Annot>(exp: &Exp<Annot>) -> bool {
     match &exp.0 {
-        Node::Function { arg, exp } => match &exp.0 {
-            Node::Identifier(id) if id == arg => true,
-            _ => false,
-        },
+        Node::Function { arg, exp } => matches!(&exp.0, Node::Identifier(id) if id == arg),
         _ => false,
     }
 }
 
 /// Checks if a function expression is an application function, and if so returns the function being applied.
-fn is_application<Annot>(exp: &Exp<Annot>) -> Option<Exp<Annot>>
-where
-    Annot: Clone,
-{
+fn is_application<Annot>(exp: &Exp<Annot>) -> Option<ExpRef<Annot>> {
     match &exp.0 {
         Node::Function { arg: arg_id, exp } => match &exp.0 {
             Node::Application { func, arg } => match &arg.0 {
-                Node::Identifier(id) if id == arg_id => Some(*func.clone()),
+                Node::Identifier(id) if id == arg_id && !func.uses_id(arg_id) => Some(func.clone()),
                 _ => None,
             },
             _ => None,
@@ -63,36 +51,43 @@ where
 }
 
 /// Checks if a function expression is an unused function, and if so returns the function expression.
-fn is_unused<Annot>(exp: &Exp<Annot>) -> Option<Exp<Annot>>
-where
-    Annot: Clone,
-{
+fn is_unused<Annot>(exp: &Exp<Annot>) -> Option<ExpRef<Annot>> {
     match &exp.0 {
-        Node::Function { arg, exp } if !uses_id(exp, arg) => Some(*exp.clone()),
+        Node::Function { arg, exp } if !exp.uses_id(arg) => Some(exp.clone()),
         _ => None,
     }
 }
 
-/// Checks if the given identifier is used in the given expression.
-fn uses_id<Annot>(ast: &Exp<Annot>, id: &str) -> bool {
-    match &ast.0 {
-        Node::Identifier(id2) if id == id2 => true,
-        Node::Union { lhs, rhs } => uses_id(lhs, id) || uses_id(rhs, id),
-        Node::Match {
-            exp: match_exp,
-            arms,
-        } => {
-            uses_id(match_exp, id)
-                || arms.iter().any(|arm| {
-                    (arm.catch_id != Some(id.to_owned()) && uses_id(&arm.exp, id))
-                        || match &arm.pat {
-                            Pat::Union(exp) => uses_id(exp, id),
-                            Pat::Any => unreachable!(),
-                        }
-                })
-        }
-        Node::Function { arg, exp } if arg != id => uses_id(exp, id),
-        Node::Application { func, arg } => uses_id(func, id) || uses_id(arg, id),
-        _ => false,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(s: &str) -> ExpRef<()> {
+        ExpRef::new(Exp(Node::Identifier(s.to_owned()), ()))
+    }
+
+    fn function(arg: &str, exp: ExpRef<()>) -> ExpRef<()> {
+        let arg = arg.to_owned();
+        ExpRef::new(Exp(Node::Function { arg, exp }, ()))
+    }
+
+    fn apply(func: ExpRef<()>, arg: ExpRef<()>) -> ExpRef<()> {
+        ExpRef::new(Exp(Node::Application { func, arg }, ()))
+    }
+
+    #[test]
+    fn test_application_functions() {
+        // (x: f x) y -> f y
+        let exp = apply(function("x", apply(id("f"), id("x"))), id("y"));
+        let expected = apply(id("f"), id("y"));
+        assert!(remove_trivial(ExpRef::unwrap_or_clone(exp)).eq_ignore_annot(&expected));
+
+        // (x: (g x) x) y isn't (g y) y, as the function applied uses x too.
+        let exp = apply(
+            function("x", apply(apply(id("g"), id("x")), id("x"))),
+            id("y"),
+        );
+        let kept = exp.clone();
+        assert!(remove_trivial(ExpRef::unwrap_or_clone(exp)).eq_ignore_annot(&kept));
     }
 }
@@ -0,0 +1,28 @@
+use crate::annotater::Annot;
+use crate::data::{Exp, Node, Pat, Type};
+use std::collections::HashSet;
+
+/// If the current expression is a tuple whose elements are constant, replaces it with the symbol it stands for, or
+/// with the union of the symbols it matches. Tuples which capture their elements are kept, as the captures are only
+/// removed with the match arm they belong to.
+pub fn fold_tuples(ast: Exp<Annot>) -> Exp<Annot> {
+    match &ast.0 {
+        Node::Tuple(tracks) if tracks.iter().all(|t| t.catch_id.is_none()) => {
+            let mut symbols = HashSet::new();
+            if !ast.union_to_set(&mut symbols) {
+                return ast;
+            }
+
+            let single = tracks
+                .iter()
+                .all(|t| matches!(&t.pat, Pat::Union(pat) if matches!(pat.0, Node::Symbol(_))));
+            if single {
+                let sym = symbols.into_iter().next().unwrap();
+                Exp(Node::Symbol(sym), Annot(Type::Symbol, ast.1 .1))
+            } else {
+                Exp::union_from_set(&symbols, &Annot(Type::Union, ast.1 .1))
+            }
+        }
+        _ => ast,
+    }
+}
@@ -0,0 +1,493 @@
+use crate::annotater::type_checker::{define_builtin_functions, Annot};
+use crate::data::{Exp, Node, Pat, TokenLoc, Type};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+/// Property of the AST which must hold once some pass has run, or for the whole pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Invariant {
+    /// Every identifier is bound by a function, let binding or match capture, or is a builtin.
+    BoundIdentifiers,
+    /// The types of each expression are consistent with the types of its subexpressions.
+    ConsistentTypes,
+    /// There are no let expressions, once `let_remover` has run.
+    NoLets,
+    /// There are no `any` patterns, once `any_remover` has run.
+    NoAnyPatterns,
+    /// Match arms don't capture symbols, once `capture_remover` has run.
+    NoCaptures,
+    /// Every match matches a tape, as expected by the generator.
+    TapeScrutinees,
+    /// Match patterns are unions of symbols, as expected by the passes which merge and dedup arms.
+    SymbolPatterns,
+}
+
+/// Invariant which doesn't hold for some expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub invariant: Invariant,
+    pub loc: TokenLoc,
+    pub message: String,
+}
+
+/// Annotation of the AST which the verifier can read.
+pub trait Annotation {
+    /// Returns the type of the expression, if the AST was type checked.
+    fn ty(&self) -> Option<&Type>;
+    fn loc(&self) -> &TokenLoc;
+}
+
+impl Annotation for TokenLoc {
+    fn ty(&self) -> Option<&Type> {
+        None
+    }
+
+    fn loc(&self) -> &TokenLoc {
+        self
+    }
+}
+
+impl Annotation for Annot {
+    fn ty(&self) -> Option<&Type> {
+        Some(&self.0)
+    }
+
+    fn loc(&self) -> &TokenLoc {
+        &self.1
+    }
+}
+
+/// Returns the invariant established once the given pass has run, if any.
+pub fn established_by(pass: &str) -> Option<Invariant> {
+    match pass {
+        "let_remover" => Some(Invariant::NoLets),
+        "any_remover" => Some(Invariant::NoAnyPatterns),
+        "capture_remover" => Some(Invariant::NoCaptures),
+        _ => None,
+    }
+}
+
+/// Returns the invariants which must hold on an expression before the given pass rewrites it, as the pass would
+/// panic otherwise.
+pub fn required_by(pass: &str) -> &'static [Invariant] {
+    match pass {
+        "applier" | "trivial_remover" | "capture_remover" => &[Invariant::NoAnyPatterns],
+        "matcher" => &[
+            Invariant::NoAnyPatterns,
+            Invariant::NoCaptures,
+            Invariant::SymbolPatterns,
+        ],
+        "pat_dedup" | "match_merger" | "arm_merger" => {
+            &[Invariant::NoAnyPatterns, Invariant::SymbolPatterns]
+        }
+        _ => &[],
+    }
+}
+
+/// Checks that the given invariants hold for a whole tree. Identifiers bound outside of the tree must be builtins.
+/// Types are only checked if the tree was type checked.
+pub fn verify<A: Annotation>(ast: &Exp<A>, invariants: &[Invariant]) -> Result<(), Violation> {
+    let builtins = define_builtin_functions();
+    check(ast, invariants, &builtins, Some(&mut HashMap::new()))
+}
+
+/// Checks that the given invariants hold for the result of rewriting an expression, which may be a subexpression of
+/// the tree. As its enclosing bindings are unknown, the rewrite must not use identifiers which weren't free before
+/// it, other than builtins.
+pub fn verify_rewrite<A: Annotation>(
+    before: &Exp<A>,
+    after: &Exp<A>,
+    invariants: &[Invariant],
+) -> Result<(), Violation> {
+    let builtins = define_builtin_functions();
+    check(after, invariants, &builtins, None)?;
+
+    if invariants.contains(&Invariant::BoundIdentifiers) {
+        let mut allowed = BTreeSet::new();
+        free_identifiers(before, &mut Vec::new(), &mut allowed);
+        let mut free = BTreeSet::new();
+        free_identifiers(after, &mut Vec::new(), &mut free);
+        if let Some(id) = free
+            .iter()
+            .find(|id| !allowed.contains(*id) && !builtins.contains_key(*id))
+        {
+            return Err(Violation {
+                invariant: Invariant::BoundIdentifiers,
+                loc: after.1.loc().clone(),
+                message: format!("identifier {} became unbound", id),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the given invariants hold for an expression, without visiting its subexpressions. In bottom-up
+/// phases, these were already checked before being rewritten themselves.
+pub fn verify_node<A: Annotation>(exp: &Exp<A>, invariants: &[Invariant]) -> Result<(), Violation> {
+    let violation = |invariant, message: &str| {
+        Err(Violation {
+            invariant,
+            loc: exp.1.loc().clone(),
+            message: message.to_owned(),
+        })
+    };
+    let required = |invariant| invariants.contains(&invariant);
+
+    match &exp.0 {
+        Node::Let { .. } if required(Invariant::NoLets) => {
+            violation(Invariant::NoLets, "let expression")
+        }
+        Node::Match { arms, .. } => {
+            for arm in arms.iter() {
+                if required(Invariant::NoCaptures) && !arm.catch_ids().is_empty() {
+                    return violation(
+                        Invariant::NoCaptures,
+                        &format!("the match captures {}", arm.catch_ids()[0]),
+                    );
+                }
+                match &arm.pat {
+                    Pat::Any if required(Invariant::NoAnyPatterns) => {
+                        return violation(
+                            Invariant::NoAnyPatterns,
+                            "the match has an 'any' pattern",
+                        )
+                    }
+                    Pat::Union(pat)
+                        if required(Invariant::SymbolPatterns)
+                            && !pat.union_to_set(&mut HashSet::new()) =>
+                    {
+                        return violation(
+                            Invariant::SymbolPatterns,
+                            "the match has a pattern which isn't a union of symbols",
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+        Node::Tuple(tracks)
+            if required(Invariant::NoAnyPatterns)
+                && tracks.iter().any(|t| matches!(t.pat, Pat::Any)) =>
+        {
+            violation(Invariant::NoAnyPatterns, "the tuple has an 'any' track")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Types of the identifiers bound by the enclosing expressions, if known, innermost last.
+type Scope = HashMap<String, Vec<Option<Type>>>;
+
+fn bind_id(scope: &mut Scope, id: &str, t: Option<Type>) {
+    scope.entry(id.to_owned()).or_default().push(t);
+}
+
+fn unbind_id(scope: &mut Scope, id: &str) {
+    if let Some(types) = scope.get_mut(id) {
+        types.pop();
+    }
+}
+
+/// Checks the invariants on an expression and its subexpressions. Identifiers are only checked if the bindings of
+/// the enclosing expressions are known.
+fn check<A: Annotation>(
+    exp: &Exp<A>,
+    invariants: &[Invariant],
+    builtins: &HashMap<String, Type>,
+    mut scope: Option<&mut Scope>,
+) -> Result<(), Violation> {
+    let violation = |invariant, message: String| {
+        Err(Violation {
+            invariant,
+            loc: exp.1.loc().clone(),
+            message,
+        })
+    };
+    let types = invariants.contains(&Invariant::ConsistentTypes);
+    let ty = exp.1.ty().filter(|_| types);
+
+    match &exp.0 {
+        Node::Identifier(id) => {
+            let scope = match scope {
+                Some(scope) => scope,
+                None => return Ok(()),
+            };
+            match scope.get(id).and_then(|types| types.last()) {
+                Some(Some(bound)) => match ty {
+                    Some(t) if !t.simple_cast(bound) && !bound.simple_cast(t) => violation(
+                        Invariant::ConsistentTypes,
+                        format!(
+                            "identifier {} has type {}, but is bound as {}",
+                            id, t, bound
+                        ),
+                    ),
+                    _ => Ok(()),
+                },
+                Some(None) => Ok(()),
+                None if builtins.contains_key(id) => Ok(()),
+                None if invariants.contains(&Invariant::BoundIdentifiers) => violation(
+                    Invariant::BoundIdentifiers,
+                    format!("identifier {} is unbound", id),
+                ),
+                None => Ok(()),
+            }
+        }
+
+        Node::Symbol(_) | Node::Abort => Ok(()),
+
+        Node::Union { lhs, rhs } => {
+            for side in [lhs, rhs] {
+                match side.1.ty().filter(|_| types) {
+                    Some(t) if !t.simple_cast(&Type::Union) => {
+                        return violation(
+                            Invariant::ConsistentTypes,
+                            format!("union of an expression of type {}", t),
+                        )
+                    }
+                    _ => check(side, invariants, builtins, scope.as_deref_mut())?,
+                }
+            }
+            Ok(())
+        }
+
+        Node::Match {
+            exp: scrutinee,
+            arms,
+        } => {
+            let tape = scrutinee.1.ty().is_none_or(|t| *t == Type::Tape);
+            if invariants.contains(&Invariant::TapeScrutinees) && !tape {
+                return violation(
+                    Invariant::TapeScrutinees,
+                    "the match doesn't match a tape".to_owned(),
+                );
+            }
+            check(scrutinee, invariants, builtins, scope.as_deref_mut())?;
+
+            for arm in arms.iter() {
+                if invariants.contains(&Invariant::NoCaptures) && !arm.catch_ids().is_empty() {
+                    return violation(
+                        Invariant::NoCaptures,
+                        format!("the match captures {}", arm.catch_ids()[0]),
+                    );
+                }
+                match &arm.pat {
+                    Pat::Union(pat)
+                        if invariants.contains(&Invariant::SymbolPatterns)
+                            && !pat.union_to_set(&mut HashSet::new()) =>
+                    {
+                        return violation(
+                            Invariant::SymbolPatterns,
+                            "the match has a pattern which isn't a union of symbols".to_owned(),
+                        )
+                    }
+                    Pat::Union(pat) => check(pat, invariants, builtins, scope.as_deref_mut())?,
+                    Pat::Any if invariants.contains(&Invariant::NoAnyPatterns) => {
+                        return violation(
+                            Invariant::NoAnyPatterns,
+                            "the match has an 'any' pattern".to_owned(),
+                        )
+                    }
+                    Pat::Any => {}
+                }
+                if let (Some(t), Some(arm_t)) = (ty, arm.exp.1.ty()) {
+                    if !arm_t.simple_cast(t) {
+                        return violation(
+                            Invariant::ConsistentTypes,
+                            format!("match of type {} has an arm of type {}", t, arm_t),
+                        );
+                    }
+                }
+
+                if let Some(scope) = scope.as_deref_mut() {
+                    for id in arm.catch_ids() {
+                        bind_id(scope, id, Some(Type::Symbol));
+                    }
+                }
+                check(&arm.exp, invariants, builtins, scope.as_deref_mut())?;
+                if let Some(scope) = scope.as_deref_mut() {
+                    for id in arm.catch_ids() {
+                        unbind_id(scope, id);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        Node::Let { exp: body, binds } => {
+            if invariants.contains(&Invariant::NoLets) {
+                return violation(Invariant::NoLets, "let expression".to_owned());
+            }
+            for (id, _, bind) in binds.iter() {
+                check(bind, invariants, builtins, scope.as_deref_mut())?;
+                if let Some(scope) = scope.as_deref_mut() {
+                    bind_id(scope, id, bind.1.ty().filter(|_| types).cloned());
+                }
+            }
+            check(body, invariants, builtins, scope.as_deref_mut())?;
+            if let Some(scope) = scope {
+                for (id, _, _) in binds.iter() {
+                    unbind_id(scope, id);
+                }
+            }
+            Ok(())
+        }
+
+        Node::Function { arg, exp: body } => {
+            let arg_t = match ty {
+                Some(Type::Function { arg: arg_t, ret }) => {
+                    if let Some(body_t) = body.1.ty() {
+                        if !body_t.simple_cast(ret) {
+                            return violation(
+                                Invariant::ConsistentTypes,
+                                format!("function of type {} returns type {}", ty.unwrap(), body_t),
+                            );
+                        }
+                    }
+                    Some((**arg_t).clone())
+                }
+                Some(Type::Halt) | Some(Type::Unresolved(_)) | None => None,
+                Some(t) => {
+                    return violation(
+                        Invariant::ConsistentTypes,
+                        format!("function has type {}", t),
+                    )
+                }
+            };
+
+            match scope {
+                Some(scope) => {
+                    bind_id(scope, arg, arg_t);
+                    check(body, invariants, builtins, Some(scope))?;
+                    unbind_id(scope, arg);
+                    Ok(())
+                }
+                None => check(body, invariants, builtins, None),
+            }
+        }
+
+        Node::Application { func, arg } => {
+            if let (Some(t), Some(func_t), Some(arg_t)) = (ty, func.1.ty(), arg.1.ty()) {
+                match func_t {
+                    Type::Function {
+                        arg: param_t,
+                        ret: ret_t,
+                    } => {
+                        if !arg_t.simple_cast(param_t) || !ret_t.simple_cast(t) {
+                            return violation(
+                                Invariant::ConsistentTypes,
+                                format!(
+                                    "application of type {} applies a function of type {} to type {}",
+                                    t, func_t, arg_t
+                                ),
+                            );
+                        }
+                    }
+                    Type::Halt | Type::Unresolved(_) => {}
+                    _ => {
+                        return violation(
+                            Invariant::ConsistentTypes,
+                            format!("application of an expression of type {}", func_t),
+                        )
+                    }
+                }
+            }
+            check(func, invariants, builtins, scope.as_deref_mut())?;
+            check(arg, invariants, builtins, scope)
+        }
+
+        Node::Tuple(tracks) => {
+            for track in tracks.iter() {
+                match &track.pat {
+                    Pat::Union(pat) => check(pat, invariants, builtins, scope.as_deref_mut())?,
+                    Pat::Any if invariants.contains(&Invariant::NoAnyPatterns) => {
+                        return violation(
+                            Invariant::NoAnyPatterns,
+                            "the tuple has an 'any' track".to_owned(),
+                        )
+                    }
+                    Pat::Any => {}
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Collects the identifiers used by an expression which aren't bound by it.
+fn free_identifiers<A>(exp: &Exp<A>, bound: &mut Vec<String>, free: &mut BTreeSet<String>) {
+    match &exp.0 {
+        Node::Identifier(id) => {
+            if !bound.contains(id) {
+                free.insert(id.clone());
+            }
+        }
+        Node::Symbol(_) | Node::Abort => {}
+        Node::Union { lhs, rhs } => {
+            free_identifiers(lhs, bound, free);
+            free_identifiers(rhs, bound, free);
+        }
+        Node::Match { exp, arms } => {
+            free_identifiers(exp, bound, free);
+            for arm in arms.iter() {
+                if let Pat::Union(pat) = &arm.pat {
+                    free_identifiers(pat, bound, free);
+                }
+                let ids = arm.catch_ids();
+                bound.extend(ids.iter().map(|id| id.to_string()));
+                free_identifiers(&arm.exp, bound, free);
+                bound.truncate(bound.len() - ids.len());
+            }
+        }
+        Node::Let { exp, binds } => {
+            for (id, _, bind) in binds.iter() {
+                free_identifiers(bind, bound, free);
+                bound.push(id.clone());
+            }
+            free_identifiers(exp, bound, free);
+            bound.truncate(bound.len() - binds.len());
+        }
+        Node::Function { arg, exp } => {
+            bound.push(arg.clone());
+            free_identifiers(exp, bound, free);
+            bound.pop();
+        }
+        Node::Application { func, arg } => {
+            free_identifiers(func, bound, free);
+            free_identifiers(arg, bound, free);
+        }
+        Node::Tuple(tracks) => {
+            for track in tracks.iter() {
+                if let Pat::Union(pat) = &track.pat {
+                    free_identifiers(pat, bound, free);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Invariant::BoundIdentifiers => write!(f, "every identifier is bound"),
+            Invariant::ConsistentTypes => write!(f, "types are consistent"),
+            Invariant::NoLets => write!(f, "let expressions are removed"),
+            Invariant::NoAnyPatterns => write!(f, "any patterns are removed"),
+            Invariant::NoCaptures => write!(f, "match captures are removed"),
+            Invariant::TapeScrutinees => write!(f, "matches only match tapes"),
+            Invariant::SymbolPatterns => write!(f, "match patterns are unions of symbols"),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invariant '{}' doesn't hold at {}: {}",
+            self.invariant, self.loc, self.message
+        )
+    }
+}
@@ -0,0 +1,135 @@
+use super::divergence::{self, Outcome};
+use super::{search, Simulator, Status, Tape};
+use crate::data::Machine;
+
+/// Outcomes which a test case may expect.
+const OUTCOMES: [&str; 4] = ["accepted", "rejected", "stuck", "diverges"];
+
+/// Test case written in a comment of the source, such as `# test 1011 -> accepted [1]100`, which expects the machine
+/// to halt in some way when run on the input, optionally leaving the given tape, as printed by `tmc run`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub line: usize,
+    pub input: String,
+    pub outcome: String,
+    pub tape: Option<String>,
+}
+
+/// Finds the test cases in the comments of a source file. Each one takes a whole line, as
+/// `# test INPUT -> OUTCOME [TAPE]`, where the outcome is one of `accepted`, `rejected`, `stuck` or `diverges`.
+pub fn parse(src: &str) -> Result<Vec<Case>, String> {
+    let mut cases = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let case = match line.trim().strip_prefix('#') {
+            Some(comment) => match comment.trim_start().strip_prefix("test ") {
+                Some(case) => case,
+                None => continue,
+            },
+            None => continue,
+        };
+
+        let (input, expected) = case
+            .split_once("->")
+            .ok_or_else(|| format!("Expected '->' in the test case at line {}", i + 1))?;
+        let (outcome, tape) = match expected.trim().split_once(char::is_whitespace) {
+            Some((outcome, tape)) => (outcome, Some(tape.trim().to_owned())),
+            None => (expected.trim(), None),
+        };
+        if !OUTCOMES.contains(&outcome) {
+            return Err(format!(
+                "Unknown outcome '{}' in the test case at line {}, expected one of {}",
+                outcome,
+                i + 1,
+                OUTCOMES.join(", ")
+            ));
+        }
+
+        cases.push(Case {
+            line: i + 1,
+            input: input.trim().to_owned(),
+            outcome: outcome.to_owned(),
+            tape,
+        });
+    }
+    Ok(cases)
+}
+
+/// Runs the machine on the input tape of a test case, returning the outcome and the final tape if they aren't the
+/// expected ones. Nondeterministic machines are searched for an accepting branch, whose tape is the final one.
+pub fn check(
+    machine: &Machine,
+    case: &Case,
+    input: Tape,
+    max_steps: usize,
+) -> Result<(), (String, String)> {
+    let (outcome, tape) = if machine.is_deterministic() {
+        let mut sim = Simulator::new(machine, input);
+        let outcome = match divergence::run(&mut sim, max_steps) {
+            Outcome::Halted(Status::Accepted) => "accepted".to_owned(),
+            Outcome::Halted(Status::Rejected) => "rejected".to_owned(),
+            Outcome::Halted(_) => "stuck".to_owned(),
+            Outcome::Diverges(_) => "diverges".to_owned(),
+            outcome => outcome.to_string(),
+        };
+        (outcome, sim.tape().to_string())
+    } else {
+        let search = search::search(machine, input, max_steps);
+        let tape = match &search.branch {
+            Some(branch) => branch.tape().to_string(),
+            None => String::new(),
+        };
+        let outcome = match search.outcome {
+            search::Outcome::Accepted => "accepted".to_owned(),
+            search::Outcome::Rejected => "rejected".to_owned(),
+            outcome => outcome.to_string(),
+        };
+        (outcome, tape)
+    };
+
+    let tape_matches = case.tape.as_ref().is_none_or(|t| *t == tape);
+    match outcome == case.outcome && tape_matches {
+        true => Ok(()),
+        false => Err((outcome, tape)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Direction, Transition};
+
+    #[test]
+    fn test_cases() {
+        let src =
+            "t: t\n  # test 10 -> accepted [1]0\n#test (1,x) 0 -> rejected\n# tested -> accepted";
+        let cases = parse(src).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].line, 2);
+        assert_eq!(cases[0].tape.as_deref(), Some("[1]0"));
+        assert_eq!(cases[1].input, "(1,x) 0");
+        assert_eq!(cases[1].tape, None);
+
+        assert_eq!(
+            parse("# test 1 -> halted").unwrap_err(),
+            "Unknown outcome 'halted' in the test case at line 1, expected one of accepted, rejected, stuck, diverges"
+        );
+
+        // Moves right over the ones, accepting on a blank.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, Some("1")), (0, None), Direction::Right));
+        m.push_transition(Transition::new((0, Some("")), (1, None), Direction::Stay));
+
+        let case = |input: &str, outcome: &str| Case {
+            line: 1,
+            input: input.to_owned(),
+            outcome: outcome.to_owned(),
+            tape: None,
+        };
+        let run = |case: &Case| check(&m, case, Tape::parse(&case.input), 100);
+        assert_eq!(run(&case("11", "accepted")), Ok(()));
+        assert_eq!(
+            run(&case("10", "accepted")),
+            Err(("stuck".to_owned(), "1[0]".to_owned()))
+        );
+    }
+}
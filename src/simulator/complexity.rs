@@ -0,0 +1,413 @@
+use super::divergence::{self, Outcome};
+use super::{Simulator, Status, Tape};
+use crate::data::Machine;
+
+use std::fmt;
+
+/// Part of an input pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    /// Symbol which appears on every input.
+    Literal(char),
+    /// Sequence of `n` symbols taken from the given class, where `n` is the size of the input.
+    Group(Vec<char>),
+}
+
+/// Describes a family of inputs, such as `[01]+[01]` for two binary numbers of the same length separated by a '+'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    parts: Vec<Part>,
+}
+
+/// Costs measured over every input of a given size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub size: usize,
+    pub inputs: usize,
+    /// Number of inputs which were rejected or on which the machine got stuck.
+    pub rejected: usize,
+    /// Number of inputs on which the machine didn't halt.
+    pub diverged: usize,
+    pub min_steps: usize,
+    pub max_steps: usize,
+    pub avg_steps: f64,
+    /// Maximum number of tape cells visited on a single run.
+    pub max_cells: usize,
+}
+
+/// Growth curve, with its name and the function of the input size which describes it.
+type Model = (&'static str, fn(f64) -> f64);
+
+/// Growth curves which can be fitted to the measurements, from slowest to fastest.
+const MODELS: [Model; 7] = [
+    ("1", |_| 0.0),
+    ("log n", |n| n.log2()),
+    ("n", |n| n),
+    ("n log n", |n| n * n.log2()),
+    ("n^2", |n| n * n),
+    ("n^3", |n| n * n * n),
+    ("2^n", |n| n.exp2()),
+];
+
+/// Curve of the form `a * f(n) + b` fitted to a set of points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fit {
+    pub model: &'static str,
+    pub a: f64,
+    pub b: f64,
+    /// Coefficient of determination of the fit.
+    pub r2: f64,
+}
+
+impl Pattern {
+    /// Parses a pattern, in which `[...]` groups stand for sequences of symbols taken from the brackets.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '[' => {
+                    let mut class = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => class.push(c),
+                            None => return Err(format!("Unclosed '[' in input pattern '{}'", s)),
+                        }
+                    }
+                    if class.is_empty() {
+                        return Err(format!("Empty group '[]' in input pattern '{}'", s));
+                    }
+                    parts.push(Part::Group(class));
+                }
+                ']' => return Err(format!("Unexpected ']' in input pattern '{}'", s)),
+                c => parts.push(Part::Literal(c)),
+            }
+        }
+
+        if !parts.iter().any(|p| matches!(p, Part::Group(_))) {
+            return Err(format!(
+                "Input pattern '{}' has no '[...]' group, so its size can't change",
+                s
+            ));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Returns the number of different inputs of the given size.
+    fn count(&self, size: usize) -> Option<usize> {
+        self.parts.iter().try_fold(1usize, |acc, p| match p {
+            Part::Literal(_) => Some(acc),
+            Part::Group(class) => acc.checked_mul(class.len().checked_pow(size as u32)?),
+        })
+    }
+
+    /// Builds the input of the given size with the given index, where each group is a digit in base of its class.
+    fn input(&self, size: usize, mut index: usize) -> String {
+        let mut input = String::new();
+        for p in self.parts.iter().rev() {
+            match p {
+                Part::Literal(c) => input.push(*c),
+                Part::Group(class) => {
+                    for _ in 0..size {
+                        input.push(class[index % class.len()]);
+                        index /= class.len();
+                    }
+                }
+            }
+        }
+        input.chars().rev().collect()
+    }
+
+    /// Returns the inputs of the given size. If there are more than `samples` inputs, a fixed pseudo-random
+    /// selection of `samples` inputs is returned instead, so that measurements are reproducible.
+    pub fn inputs(&self, size: usize, samples: usize) -> Vec<String> {
+        match self.count(size) {
+            Some(count) if count <= samples => (0..count).map(|i| self.input(size, i)).collect(),
+            _ => {
+                let mut state = 0x9e3779b97f4a7c15u64 ^ size as u64;
+                (0..samples)
+                    .map(|_| {
+                        // Pick each symbol separately (xorshift), as the number of inputs may not fit in an usize.
+                        let mut input = String::new();
+                        for p in self.parts.iter() {
+                            match p {
+                                Part::Literal(c) => input.push(*c),
+                                Part::Group(class) => {
+                                    for _ in 0..size {
+                                        state ^= state << 13;
+                                        state ^= state >> 7;
+                                        state ^= state << 17;
+                                        input.push(class[(state % class.len() as u64) as usize]);
+                                    }
+                                }
+                            }
+                        }
+                        input
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Parses an inclusive range of sizes, either as `MIN..MAX` or as a single `MAX`, which starts at 1.
+pub fn parse_sizes(s: &str) -> Result<(usize, usize), String> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Expected a number but found '{}' in size range '{}'", n, s))
+    };
+
+    let (min, max) = match s.split_once("..") {
+        Some((min, max)) => (parse(min)?, parse(max.trim_start_matches('='))?),
+        None => (1, parse(s)?),
+    };
+    if min > max {
+        return Err(format!("Size range '{}' is empty", s));
+    }
+    Ok((min, max))
+}
+
+/// Runs the machine on the inputs of every size in the given range and measures their costs.
+/// Inputs are converted to tapes with the given function.
+pub fn measure<F: Fn(&str) -> Result<Tape, String>>(
+    machine: &Machine,
+    pattern: &Pattern,
+    sizes: (usize, usize),
+    samples: usize,
+    max_steps: usize,
+    tape: F,
+) -> Result<Vec<Measurement>, String> {
+    if samples == 0 {
+        return Err("At least one input must be sampled for each size".to_owned());
+    }
+    (sizes.0..=sizes.1)
+        .map(|size| {
+            let inputs = pattern.inputs(size, samples);
+            let mut m = Measurement {
+                size,
+                inputs: inputs.len(),
+                rejected: 0,
+                diverged: 0,
+                min_steps: usize::MAX,
+                max_steps: 0,
+                avg_steps: 0.0,
+                max_cells: 0,
+            };
+
+            for input in inputs.iter() {
+                let mut sim = Simulator::new(machine, tape(input)?);
+                match divergence::run(&mut sim, max_steps) {
+                    Outcome::Halted(Status::Accepted) => {}
+                    Outcome::Halted(_) => m.rejected += 1,
+                    Outcome::Diverges(_) | Outcome::StepLimit => m.diverged += 1,
+                }
+
+                let (min, max) = sim.tape().bounds();
+                m.min_steps = m.min_steps.min(sim.steps());
+                m.max_steps = m.max_steps.max(sim.steps());
+                m.avg_steps += sim.steps() as f64 / inputs.len() as f64;
+                m.max_cells = m.max_cells.max((max - min + 1) as usize);
+            }
+
+            Ok(m)
+        })
+        .collect()
+}
+
+/// Fits the growth curve which best describes the given points.
+/// Returns None if there are less than three points, as any curve would fit them.
+pub fn fit(points: &[(usize, f64)]) -> Option<Fit> {
+    if points.len() < 3 {
+        return None;
+    }
+
+    let mut best: Option<(Fit, f64)> = None;
+    for (model, f) in MODELS.iter() {
+        // Least squares regression of y = a * f(n) + b.
+        let xs = points.iter().map(|p| f(p.0 as f64)).collect::<Vec<_>>();
+        let ys = points.iter().map(|p| p.1).collect::<Vec<_>>();
+        let len = points.len() as f64;
+        let mx = xs.iter().sum::<f64>() / len;
+        let my = ys.iter().sum::<f64>() / len;
+        let sxx = xs.iter().map(|x| (x - mx) * (x - mx)).sum::<f64>();
+        let sxy = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(x, y)| (x - mx) * (y - my))
+            .sum::<f64>();
+        let a = if sxx == 0.0 { 0.0 } else { sxy / sxx };
+        let b = my - a * mx;
+        if a < 0.0 || !a.is_finite() {
+            continue;
+        }
+
+        let ss_res = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(x, y)| (y - (a * x + b)).powi(2))
+            .sum::<f64>();
+        let ss_tot = ys.iter().map(|y| (y - my).powi(2)).sum::<f64>();
+        let r2 = if ss_tot == 0.0 {
+            1.0
+        } else {
+            1.0 - ss_res / ss_tot
+        };
+
+        // Faster growing curves are only picked if they fit noticeably better.
+        let better = match &best {
+            Some((_, best_res)) => ss_res < best_res * 0.99 - 1e-9,
+            None => true,
+        };
+        if better {
+            best = Some((Fit { model, a, b, r2 }, ss_res));
+        }
+    }
+
+    best.map(|(fit, _)| fit)
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.model == "1" {
+            write!(f, "O(1) ~ {:.2}", self.b)
+        } else {
+            write!(
+                f,
+                "O({}) ~ {:.2} * {} {} {:.2} (R^2 = {:.4})",
+                self.model,
+                self.a,
+                self.model,
+                if self.b < 0.0 { '-' } else { '+' },
+                self.b.abs(),
+                self.r2
+            )
+        }
+    }
+}
+
+/// Formats the measurements as a table, followed by the curves fitted to the worst case steps and cells.
+pub fn report(measurements: &[Measurement]) -> String {
+    let mut out = format!(
+        "{:>6} {:>7} {:>10} {:>12} {:>10} {:>10}\n",
+        "size", "inputs", "min steps", "avg steps", "max steps", "max cells"
+    );
+    for m in measurements {
+        out += &format!(
+            "{:>6} {:>7} {:>10} {:>12.1} {:>10} {:>10}",
+            m.size, m.inputs, m.min_steps, m.avg_steps, m.max_steps, m.max_cells
+        );
+        if m.rejected > 0 {
+            out += &format!("  ({} rejected)", m.rejected);
+        }
+        if m.diverged > 0 {
+            out += &format!("  ({} didn't halt)", m.diverged);
+        }
+        out += "\n";
+    }
+
+    let fit_line = |name: &str, points: Vec<(usize, f64)>| match fit(&points) {
+        Some(fit) => format!("{}: {}\n", name, fit),
+        None => format!("{}: not enough sizes to fit a curve\n", name),
+    };
+    out += "\n";
+    out += &fit_line(
+        "Steps",
+        measurements
+            .iter()
+            .map(|m| (m.size, m.max_steps as f64))
+            .collect(),
+    );
+    out += &fit_line(
+        "Cells",
+        measurements
+            .iter()
+            .map(|m| (m.size, m.max_cells as f64))
+            .collect(),
+    );
+    if measurements.iter().any(|m| m.diverged > 0) {
+        out += "Some inputs didn't halt, so their step counts are only lower bounds\n";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Direction, Transition};
+
+    #[test]
+    fn test_pattern() {
+        let p = Pattern::parse("[01]+[ab]").unwrap();
+        assert_eq!(
+            p.inputs(1, 100),
+            vec!["0+a", "0+b", "1+a", "1+b"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(p.inputs(2, 16).len(), 16);
+
+        let sampled = p.inputs(8, 10);
+        assert_eq!(sampled.len(), 10);
+        assert!(sampled.iter().all(|s| s.len() == 17 && &s[8..9] == "+"));
+        assert_eq!(sampled, p.inputs(8, 10));
+
+        assert!(Pattern::parse("01").is_err());
+        assert!(Pattern::parse("[01").is_err());
+        assert!(Pattern::parse("[]").is_err());
+        assert_eq!(parse_sizes("2..5"), Ok((2, 5)));
+        assert_eq!(parse_sizes("4"), Ok((1, 4)));
+    }
+
+    #[test]
+    fn test_fit() {
+        let points = |f: fn(f64) -> f64| (1..10).map(|n| (n, f(n as f64))).collect::<Vec<_>>();
+
+        assert_eq!(fit(&points(|_| 5.0)).unwrap().model, "1");
+        assert_eq!(fit(&points(|n| 3.0 * n + 2.0)).unwrap().model, "n");
+        assert_eq!(fit(&points(|n| n * n + n)).unwrap().model, "n^2");
+        assert_eq!(fit(&points(|n| n.exp2())).unwrap().model, "2^n");
+
+        let linear = fit(&points(|n| 3.0 * n + 2.0)).unwrap();
+        assert!((linear.a - 3.0).abs() < 1e-6 && (linear.b - 2.0).abs() < 1e-6);
+        assert!(fit(&[(1, 1.0), (2, 2.0)]).is_none());
+    }
+
+    #[test]
+    fn test_measure() {
+        // Moves right until a blank is found, and then back to the start.
+        let mut m = Machine::new();
+        let back = m.push_state();
+        m.push_transition(Transition::new(
+            (0, Some("")),
+            (back, None),
+            Direction::Left,
+        ));
+        m.push_transition(Transition::new((0, None), (0, None), Direction::Right));
+        m.push_transition(Transition::new(
+            (back, Some("")),
+            (1, None),
+            Direction::Right,
+        ));
+        m.push_transition(Transition::new((back, None), (back, None), Direction::Left));
+
+        let pattern = Pattern::parse("[01]").unwrap();
+        let ms = measure(&m, &pattern, (1, 6), 100, 1000, |i| Ok(Tape::parse(i))).unwrap();
+        assert!(ms.iter().all(|m| m.min_steps == m.max_steps));
+        assert_eq!(ms[0].max_steps, 4);
+        assert_eq!(ms[0].max_cells, 3);
+        assert_eq!(ms[5].inputs, 64);
+        assert_eq!(ms[5].max_steps, 14);
+
+        let report = report(&ms);
+        assert!(report.contains("Steps: O(n) ~ 2.00 * n + 2.00"));
+        assert!(report.contains("Cells: O(n) ~ 1.00 * n + 2.00"));
+
+        assert_eq!(
+            measure(&m, &pattern, (1, 6), 0, 1000, |i| Ok(Tape::parse(i))).unwrap_err(),
+            "At least one input must be sampled for each size"
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use super::divergence::{self, Outcome};
+use super::{Simulator, Tape};
+use crate::data::{Exp, Machine, Node, Pat, TokenLoc};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+/// Part of the source to which machine steps are attributed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegionKind {
+    /// A let binding, with its name.
+    Binding(String),
+    /// A match arm, with its pattern.
+    Arm(String),
+}
+
+/// A let binding or match arm of the source, along with the locations of every expression inside it.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub loc: TokenLoc,
+    /// Index of the match expression, for arms, so that arms of the same match can be grouped together.
+    pub group: usize,
+    locs: HashSet<TokenLoc>,
+}
+
+/// Source file shown in coverage reports.
+pub struct Source {
+    /// Import name of the file, or None for the main file.
+    pub import: Option<String>,
+    /// Path shown in the report.
+    pub path: String,
+    pub text: String,
+}
+
+/// Counts how many times each transition of a machine is taken over several runs.
+pub struct Coverage<'a> {
+    machine: &'a Machine,
+    counts: Vec<usize>,
+    steps: usize,
+}
+
+/// Collects the let bindings and match arms of a parsed program.
+pub fn regions(ast: &Exp<TokenLoc>) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let mut matches = 0;
+    collect_regions(ast, &mut regions, &mut matches);
+    regions
+}
+
+fn collect_regions(exp: &Exp<TokenLoc>, regions: &mut Vec<Region>, matches: &mut usize) {
+    match &exp.0 {
+        Node::Identifier(_) | Node::Symbol(_) | Node::Abort => {}
+        Node::Union { lhs, rhs } => {
+            collect_regions(lhs, regions, matches);
+            collect_regions(rhs, regions, matches);
+        }
+        Node::Match { exp, arms } => {
+            collect_regions(exp, regions, matches);
+            let group = *matches;
+            *matches += 1;
+            for arm in arms {
+                let pat = match &arm.pat {
+                    Pat::Union(pat) => pat.to_string().replace('\n', " ").trim().to_owned(),
+                    Pat::Any => "any".to_owned(),
+                };
+                regions.push(Region {
+                    kind: RegionKind::Arm(pat),
+                    loc: arm.exp.1.clone(),
+                    group,
+                    locs: collect_locs(&arm.exp),
+                });
+                collect_regions(&arm.exp, regions, matches);
+            }
+        }
+        Node::Let { exp, binds } => {
+            for (id, _, bind) in binds {
+                regions.push(Region {
+                    kind: RegionKind::Binding(id.clone()),
+                    loc: bind.1.clone(),
+                    group: 0,
+                    locs: collect_locs(bind),
+                });
+                collect_regions(bind, regions, matches);
+            }
+            collect_regions(exp, regions, matches);
+        }
+        Node::Function { exp, .. } => collect_regions(exp, regions, matches),
+        Node::Application { func, arg } => {
+            collect_regions(func, regions, matches);
+            collect_regions(arg, regions, matches);
+        }
+        Node::Tuple(tracks) => {
+            for track in tracks {
+                if let Pat::Union(exp) = &track.pat {
+                    collect_regions(exp, regions, matches);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the locations of every expression in the given expression.
+fn collect_locs(exp: &Exp<TokenLoc>) -> HashSet<TokenLoc> {
+    fn rec(exp: &Exp<TokenLoc>, locs: &mut HashSet<TokenLoc>) {
+        locs.insert(exp.1.clone());
+        match &exp.0 {
+            Node::Identifier(_) | Node::Symbol(_) | Node::Abort => {}
+            Node::Union { lhs, rhs } => {
+                rec(lhs, locs);
+                rec(rhs, locs);
+            }
+            Node::Match { exp, arms } => {
+                rec(exp, locs);
+                for arm in arms {
+                    if let Pat::Union(pat) = &arm.pat {
+                        rec(pat, locs);
+                    }
+                    rec(&arm.exp, locs);
+                }
+            }
+            Node::Let { exp, binds } => {
+                binds.iter().for_each(|(_, _, b)| rec(b, locs));
+                rec(exp, locs);
+            }
+            Node::Function { exp, .. } => rec(exp, locs),
+            Node::Application { func, arg } => {
+                rec(func, locs);
+                rec(arg, locs);
+            }
+            Node::Tuple(tracks) => {
+                for track in tracks {
+                    if let Pat::Union(exp) = &track.pat {
+                        rec(exp, locs);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut locs = HashSet::new();
+    rec(exp, &mut locs);
+    locs
+}
+
+impl<'a> Coverage<'a> {
+    pub fn new(machine: &'a Machine) -> Self {
+        Self {
+            machine,
+            counts: vec![0; machine.transitions.len()],
+            steps: 0,
+        }
+    }
+
+    /// Runs the machine on the given tape, counting the transitions taken.
+    pub fn run(&mut self, tape: Tape, max_steps: usize) -> (Outcome, usize) {
+        let mut sim = Simulator::new(self.machine, tape);
+        let counts = &mut self.counts;
+        let outcome =
+            divergence::run_with(&mut sim, max_steps, |step| counts[step.transition] += 1);
+        self.steps += sim.steps();
+        (outcome, sim.steps())
+    }
+
+    /// Returns the number of steps spent on each region.
+    /// A step counts towards every region which contains one of the expressions which generated its transition.
+    /// Regions which generated no transitions at all, such as matches resolved during compilation, are None.
+    pub fn region_steps(&self, regions: &[Region]) -> Vec<Option<usize>> {
+        regions
+            .iter()
+            .map(|r| {
+                self.machine
+                    .transitions
+                    .iter()
+                    .zip(self.counts.iter())
+                    .filter(|(t, _)| t.locs.iter().any(|l| r.locs.contains(l)))
+                    .fold(None, |acc, (_, c)| Some(acc.unwrap_or(0) + c))
+            })
+            .collect()
+    }
+
+    /// Returns the number of steps spent on each source line which generated at least one transition.
+    /// Lines which are missing have no transitions, and thus can't be executed.
+    pub fn line_steps(&self) -> BTreeMap<(Option<String>, usize), usize> {
+        let mut lines = BTreeMap::new();
+        for (t, count) in self.machine.transitions.iter().zip(self.counts.iter()) {
+            let touched = t
+                .locs
+                .iter()
+                .map(|l| (l.import.clone(), l.line))
+                .collect::<BTreeSet<_>>();
+            for line in touched {
+                *lines.entry(line).or_insert(0) += count;
+            }
+        }
+        lines
+    }
+
+    /// Formats the sources with the number of steps spent on each line, followed by the match arms which were
+    /// never executed and the steps spent on each let binding.
+    pub fn annotate(&self, regions: &[Region], sources: &[Source]) -> String {
+        let lines = self.line_steps();
+        let mut out = String::new();
+
+        for src in sources {
+            writeln!(out, "-------- {} --------", src.path).unwrap();
+            for (i, text) in src.text.lines().enumerate() {
+                let count = match lines.get(&(src.import.clone(), i + 1)) {
+                    Some(0) => "#####".to_owned(),
+                    Some(n) => n.to_string(),
+                    None => "-".to_owned(),
+                };
+                writeln!(out, "{:>10}:{:>5}: {}", count, i + 1, text).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+
+        let steps = self.region_steps(regions);
+        let arms = regions
+            .iter()
+            .zip(steps.iter())
+            .filter_map(|(r, s)| match &r.kind {
+                RegionKind::Arm(pat) if *s == Some(0) => {
+                    Some(format!("  {} at {}", pat, fmt_loc(&r.loc)))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if arms.is_empty() {
+            writeln!(out, "Every match arm was executed").unwrap();
+        } else {
+            writeln!(out, "Match arms never executed:").unwrap();
+            arms.iter().for_each(|a| writeln!(out, "{}", a).unwrap());
+        }
+
+        let mut binds = regions
+            .iter()
+            .zip(steps.iter())
+            .filter_map(|(r, s)| match &r.kind {
+                RegionKind::Binding(id) => Some((s.as_ref()?, id, &r.loc)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        binds.sort_by(|a, b| b.0.cmp(a.0));
+        writeln!(
+            out,
+            "\nSteps per let binding ({} steps in total):",
+            self.steps
+        )
+        .unwrap();
+        for (s, id, loc) in binds {
+            let percent = if self.steps == 0 {
+                0.0
+            } else {
+                *s as f64 * 100.0 / self.steps as f64
+            };
+            writeln!(
+                out,
+                "{:>10} {:>6.1}%  {} at {}",
+                s,
+                percent,
+                id,
+                fmt_loc(loc)
+            )
+            .unwrap();
+        }
+
+        out
+    }
+
+    /// Formats the coverage as an lcov tracefile. Let bindings are reported as functions, match arms as branches
+    /// and the counts are the number of steps spent on each of them.
+    pub fn lcov(&self, regions: &[Region], sources: &[Source]) -> String {
+        let lines = self.line_steps();
+        let steps = self.region_steps(regions);
+        let mut out = String::new();
+
+        writeln!(out, "TN:").unwrap();
+        for src in sources {
+            let regions = regions
+                .iter()
+                .zip(steps.iter())
+                .filter_map(|(r, s)| Some((r, (*s)?)))
+                .filter(|(r, _)| r.loc.import == src.import)
+                .collect::<Vec<_>>();
+
+            writeln!(out, "SF:{}", src.path).unwrap();
+
+            let mut found = 0;
+            let mut hit = 0;
+            for (r, s) in regions.iter() {
+                if let RegionKind::Binding(id) = &r.kind {
+                    let name = format!("{}:{}", id, r.loc.line);
+                    writeln!(out, "FN:{},{}", r.loc.line, name).unwrap();
+                    writeln!(out, "FNDA:{},{}", s, name).unwrap();
+                    found += 1;
+                    hit += (*s > 0) as usize;
+                }
+            }
+            writeln!(out, "FNF:{}\nFNH:{}", found, hit).unwrap();
+
+            let mut found = 0;
+            let mut hit = 0;
+            let mut arm = 0;
+            let mut last_group = None;
+            for (r, s) in regions.iter() {
+                if let RegionKind::Arm(_) = &r.kind {
+                    if last_group != Some(r.group) {
+                        arm = 0;
+                        last_group = Some(r.group);
+                    }
+                    writeln!(out, "BRDA:{},{},{},{}", r.loc.line, r.group, arm, s).unwrap();
+                    arm += 1;
+                    found += 1;
+                    hit += (*s > 0) as usize;
+                }
+            }
+            writeln!(out, "BRF:{}\nBRH:{}", found, hit).unwrap();
+
+            let mut found = 0;
+            let mut hit = 0;
+            for ((import, line), s) in lines.iter() {
+                if *import == src.import {
+                    writeln!(out, "DA:{},{}", line, s).unwrap();
+                    found += 1;
+                    hit += (*s > 0) as usize;
+                }
+            }
+            writeln!(out, "LF:{}\nLH:{}", found, hit).unwrap();
+            writeln!(out, "end_of_record").unwrap();
+        }
+
+        out
+    }
+}
+
+fn fmt_loc(loc: &TokenLoc) -> String {
+    match &loc.import {
+        Some(import) => format!("{}:{}:{}", import, loc.line, loc.col),
+        None => format!("{}:{}", loc.line, loc.col),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Direction, ExpRef, Transition};
+
+    #[test]
+    fn test_coverage() {
+        let loc = |line, col| TokenLoc {
+            line,
+            col,
+            import: None,
+        };
+
+        // let f = t: match t { '0' -> a, '1' -> b }, in f
+        let id = |s: &str, loc| Exp(Node::Identifier(s.to_owned()), loc);
+        let arm = |sym: &str, exp| crate::data::Arm {
+            catch_id: None,
+            pat: Pat::Union(ExpRef::new(Exp(Node::Symbol(sym.to_owned()), loc(9, 9)))),
+            exp: ExpRef::new(exp),
+        };
+        let body = Exp(
+            Node::Match {
+                exp: ExpRef::new(id("t", loc(1, 10))),
+                arms: vec![arm("0", id("a", loc(2, 5))), arm("1", id("b", loc(3, 5)))],
+            },
+            loc(1, 4),
+        );
+        let ast = Exp(
+            Node::Let {
+                exp: ExpRef::new(id("f", loc(4, 1))),
+                binds: vec![("f".to_owned(), false, ExpRef::new(body))],
+            },
+            loc(1, 1),
+        );
+
+        let mut m = Machine::new();
+        m.push_transition(Transition {
+            locs: vec![loc(2, 5)],
+            ..Transition::new((0, Some("0")), (0, None), Direction::Right)
+        });
+        m.push_transition(Transition {
+            locs: vec![loc(3, 5)],
+            ..Transition::new((0, Some("1")), (1, None), Direction::Stay)
+        });
+
+        let regions = regions(&ast);
+        assert_eq!(regions.len(), 3);
+
+        let mut cov = Coverage::new(&m);
+        cov.run(Tape::parse("00"), 100);
+        assert_eq!(cov.region_steps(&regions), vec![Some(2), Some(2), Some(0)]);
+        assert_eq!(cov.line_steps().get(&(None, 3)), Some(&0));
+
+        cov.run(Tape::parse("001"), 100);
+        assert_eq!(cov.region_steps(&regions), vec![Some(5), Some(4), Some(1)]);
+
+        let mut cov = Coverage::new(&m);
+        cov.run(Tape::parse("1"), 100);
+        let sources = [Source {
+            import: None,
+            path: "f.tmc".to_owned(),
+            text: "let f = t: match t {\n  '0' -> a,\n  '1' -> b,\n} in f".to_owned(),
+        }];
+        let annotated = cov.annotate(&regions, &sources);
+        assert!(annotated.contains("     #####:    2:   '0' -> a,"));
+        assert!(annotated.contains("         1:    3:   '1' -> b,"));
+        assert!(annotated.contains("Match arms never executed:\n  '0' at 2:5"));
+
+        let lcov = cov.lcov(&regions, &sources);
+        assert!(lcov.contains("FN:1,f:1\nFNDA:1,f:1\n"));
+        assert!(lcov.contains("BRDA:2,0,0,0\nBRDA:3,0,1,1\nBRF:2\nBRH:1\n"));
+        assert!(lcov.contains("DA:2,0\nDA:3,1\nLF:2\nLH:1\n"));
+    }
+}
@@ -0,0 +1,353 @@
+use super::divergence::{self, Outcome};
+use super::{fmt_symbol, Simulator, Status, Tape};
+use crate::data::{Direction, Machine, TokenLoc};
+
+use std::io::{BufRead, Write};
+
+/// Number of cells shown on each side of the head.
+const WINDOW_RADIUS: i64 = 8;
+/// Maximum number of steps taken by a single continue or run command, to avoid hanging on infinite loops.
+const CONTINUE_LIMIT: usize = 10_000_000;
+
+const HELP: &str = "\
+Commands:
+  step [n], s [n]          Takes n steps (1 by default).
+  continue, c              Runs until a breakpoint is hit or the machine halts.
+  run, r                   Runs until the machine halts or loops forever, ignoring breakpoints.
+  break state <n>          Stops before a transition from state n.
+  break line [file:]<n>    Stops before a transition generated from the given source line.
+  break write <symbol>     Stops before a transition which changes a cell to the given symbol.
+  delete <n>, d <n>        Deletes the breakpoint with the given number.
+  info, i                  Lists all breakpoints.
+  tape [radius], t         Prints the tape around the head.
+  restart                  Restarts the machine with the initial tape.
+  help, h                  Prints this message.
+  quit, q                  Exits the debugger.
+An empty line repeats the last command.";
+
+/// Represents a condition on which the debugger should stop.
+#[derive(Debug, Clone, PartialEq)]
+enum Breakpoint {
+    State(usize),
+    Line(Option<String>, usize),
+    Write(String),
+}
+
+/// Interactive debugger state.
+struct Debugger<'a, W: Write> {
+    sim: Simulator<'a>,
+    input: Tape,
+    breakpoints: Vec<Option<Breakpoint>>,
+    out: W,
+}
+
+/// Runs an interactive debugger for the given machine, reading commands from `input` and writing to `out`.
+pub fn debug<R: BufRead, W: Write>(
+    machine: &Machine,
+    tape: Tape,
+    input: R,
+    out: W,
+) -> Result<(), String> {
+    let mut dbg = Debugger {
+        sim: Simulator::new(machine, tape.clone()),
+        input: tape,
+        breakpoints: Vec::new(),
+        out,
+    };
+
+    dbg.print_position()?;
+    let mut last = String::new();
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("Couldn't read command: {}", e))?;
+        let line = if line.trim().is_empty() {
+            last.clone()
+        } else {
+            line.trim().to_owned()
+        };
+
+        match dbg.execute(&line) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => writeln!(dbg.out, "Error: {}", e).map_err(|e| e.to_string())?,
+        }
+        last = line;
+    }
+
+    Ok(())
+}
+
+impl<'a, W: Write> Debugger<'a, W> {
+    /// Executes a single command. Returns true if the debugger should exit.
+    fn execute(&mut self, line: &str) -> Result<bool, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => {}
+            ["step" | "s"] => self.step(1)?,
+            ["step" | "s", n] => self.step(parse_number(n)?)?,
+            ["continue" | "c"] => self.resume()?,
+            ["run" | "r"] => self.run()?,
+            ["break" | "b", kind, arg] => {
+                let bp = parse_breakpoint(kind, arg)?;
+                self.breakpoints.push(Some(bp.clone()));
+                self.print(&format!(
+                    "Breakpoint {}: {}",
+                    self.breakpoints.len(),
+                    fmt_breakpoint(&bp)
+                ))?;
+            }
+            ["delete" | "d", n] => {
+                let n = parse_number(n)?;
+                match self.breakpoints.get_mut(n.wrapping_sub(1)) {
+                    Some(bp @ Some(_)) => *bp = None,
+                    _ => return Err(format!("No breakpoint number {}", n)),
+                }
+            }
+            ["info" | "i"] => {
+                let list = self
+                    .breakpoints
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, bp)| {
+                        bp.as_ref()
+                            .map(|bp| format!("{}: {}", i + 1, fmt_breakpoint(bp)))
+                    })
+                    .collect::<Vec<_>>();
+                if list.is_empty() {
+                    self.print("No breakpoints")?;
+                } else {
+                    self.print(&list.join("\n"))?;
+                }
+            }
+            ["tape" | "t"] => self.print(&self.sim.tape().to_string())?,
+            ["tape" | "t", radius] => {
+                let radius = parse_number(radius)? as i64;
+                self.print(&self.sim.tape().window(radius))?;
+            }
+            ["restart"] => {
+                self.sim = Simulator::new(self.sim.machine(), self.input.clone());
+                self.print_position()?;
+            }
+            ["help" | "h"] => self.print(HELP)?,
+            ["quit" | "q"] => return Ok(true),
+            _ => return Err(format!("Unknown command '{}', try 'help'", line)),
+        }
+
+        Ok(false)
+    }
+
+    /// Takes the given number of steps, ignoring breakpoints, and prints the last one.
+    fn step(&mut self, n: usize) -> Result<(), String> {
+        let mut last = None;
+        for _ in 0..n {
+            match self.sim.step() {
+                Some(step) => last = Some(step),
+                None => break,
+            }
+        }
+
+        if let Some(step) = last {
+            self.print(&format!(
+                "state {} at cell {}: read '{}', wrote '{}', moved {}",
+                step.from,
+                step.pos,
+                fmt_symbol(&step.read),
+                fmt_symbol(&step.written),
+                match step.dir {
+                    Direction::Left => "left",
+                    Direction::Right => "right",
+                    Direction::Stay => "nowhere",
+                }
+            ))?;
+        }
+        self.print_position()
+    }
+
+    /// Runs the machine until it halts or a breakpoint is hit.
+    fn resume(&mut self) -> Result<(), String> {
+        // Always take at least one step, so that continuing from a breakpoint doesn't stop immediately.
+        let mut steps = 0;
+        while self.sim.step().is_some() {
+            steps += 1;
+            if let Some(i) = self.hit_breakpoint() {
+                self.print(&format!("Hit breakpoint {}", i + 1))?;
+                break;
+            }
+
+            if steps >= CONTINUE_LIMIT {
+                self.print(&format!("Paused after {} steps", steps))?;
+                break;
+            }
+        }
+        self.print_position()
+    }
+
+    /// Runs the machine until it halts, ignoring breakpoints, and stops early if it clearly never halts.
+    fn run(&mut self) -> Result<(), String> {
+        let start = self.sim.steps();
+        match divergence::run(&mut self.sim, start.saturating_add(CONTINUE_LIMIT)) {
+            Outcome::Halted(_) => {}
+            Outcome::StepLimit => self.print(&format!("Paused after {} steps", CONTINUE_LIMIT))?,
+            outcome => self.print(&format!(
+                "Stopped after {} steps: {}",
+                self.sim.steps() - start,
+                outcome
+            ))?,
+        }
+        self.print_position()
+    }
+
+    /// Returns the index of the first breakpoint which matches the next transition.
+    fn hit_breakpoint(&self) -> Option<usize> {
+        let t = &self.sim.machine().transitions[self.sim.next_transition()?];
+        let read = self.sim.tape().read();
+
+        self.breakpoints.iter().position(|bp| match bp {
+            Some(Breakpoint::State(s)) => *s == self.sim.state(),
+            Some(Breakpoint::Line(import, line)) => t
+                .locs
+                .iter()
+                .any(|loc| loc.line == *line && &loc.import == import),
+            Some(Breakpoint::Write(sym)) => matches!(&t.to.1, Some(s) if s == sym && s != read),
+            None => false,
+        })
+    }
+
+    /// Prints the current state, the source location of the next transition and the tape around the head.
+    fn print_position(&mut self) -> Result<(), String> {
+        let state = self.sim.state();
+        let status = match self.sim.status() {
+            Status::Running => format!("state {}", state),
+            Status::Accepted => "accepted".to_owned(),
+            Status::Rejected => "rejected".to_owned(),
+            Status::Stuck => format!("stuck on state {}", state),
+        };
+
+        let locs = self
+            .sim
+            .next_transition()
+            .map(|i| fmt_locs(&self.sim.machine().transitions[i].locs))
+            .unwrap_or_default();
+
+        let msg = format!(
+            "step {}: {}{}\n  {}",
+            self.sim.steps(),
+            status,
+            locs,
+            self.sim.tape().window(WINDOW_RADIUS)
+        );
+        self.print(&msg)
+    }
+
+    fn print(&mut self, msg: &str) -> Result<(), String> {
+        writeln!(self.out, "{}", msg).map_err(|e| e.to_string())
+    }
+}
+
+/// Formats the source locations of a transition, compactly, as `[import:]line:column`.
+fn fmt_locs(locs: &[TokenLoc]) -> String {
+    let mut locs = locs.iter().collect::<Vec<_>>();
+    locs.sort_by(|a, b| (&a.import, a.line, a.col).cmp(&(&b.import, b.line, b.col)));
+
+    if locs.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " at {}",
+            locs.iter()
+                .map(|l| match &l.import {
+                    Some(import) => format!("{}:{}:{}", import, l.line, l.col),
+                    None => format!("{}:{}", l.line, l.col),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+fn fmt_breakpoint(bp: &Breakpoint) -> String {
+    match bp {
+        Breakpoint::State(s) => format!("state {}", s),
+        Breakpoint::Line(Some(import), line) => format!("line {} of {}", line, import),
+        Breakpoint::Line(None, line) => format!("line {}", line),
+        Breakpoint::Write(sym) => format!("write '{}'", fmt_symbol(sym)),
+    }
+}
+
+fn parse_breakpoint(kind: &str, arg: &str) -> Result<Breakpoint, String> {
+    match kind {
+        "state" => Ok(Breakpoint::State(parse_number(arg)?)),
+        "line" => match arg.rsplit_once(':') {
+            Some((import, line)) => Ok(Breakpoint::Line(
+                Some(import.to_owned()),
+                parse_number(line)?,
+            )),
+            None => Ok(Breakpoint::Line(None, parse_number(arg)?)),
+        },
+        "write" => Ok(Breakpoint::Write(if arg == "_" {
+            String::new()
+        } else {
+            arg.trim_matches('\'').to_owned()
+        })),
+        _ => Err(format!(
+            "Unknown breakpoint kind '{}', expected state, line or write",
+            kind
+        )),
+    }
+}
+
+fn parse_number(s: &str) -> Result<usize, String> {
+    s.parse()
+        .map_err(|_| format!("Expected a number but found '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Transition;
+
+    #[test]
+    fn test_breakpoints() {
+        let loc = |line| TokenLoc {
+            line,
+            col: 1,
+            import: None,
+        };
+
+        // Moves right over every '1', and replaces the first '0' with a '1'.
+        let mut m = Machine::new();
+        m.push_transition(Transition {
+            locs: vec![loc(1)],
+            ..Transition::new((0, Some("1")), (0, None), Direction::Right)
+        });
+        m.push_transition(Transition {
+            locs: vec![loc(2)],
+            ..Transition::new((0, Some("0")), (1, Some("1")), Direction::Stay)
+        });
+
+        let commands = "break line 2\nc\nrestart\ndelete 1\nbreak write 1\nc\nc\nq\n";
+        let mut out = Vec::new();
+        debug(&m, Tape::parse("110"), commands.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("Hit breakpoint 1\nstep 2: state 0 at 2:1"));
+        assert!(out.contains("Breakpoint 2: write '1'"));
+        assert!(out.contains("Hit breakpoint 2\nstep 2: state 0 at 2:1"));
+        assert!(out.contains("step 3: accepted\n  _ _ _ _ _ _ 1 1 [1] _"));
+    }
+
+    #[test]
+    fn test_run_diverges() {
+        // Moves right forever.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, None), (0, None), Direction::Right));
+
+        let mut out = Vec::new();
+        debug(&m, Tape::parse("1"), "run\nq\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains(": diverges, head runs right forever"),
+            "{}",
+            out
+        );
+    }
+}
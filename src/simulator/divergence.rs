@@ -0,0 +1,314 @@
+use super::{Simulator, Status, Step, Tape};
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Number of cells behind the head which are remembered when it reaches a new cell.
+const WINDOW: usize = 64;
+/// Maximum number of new cells remembered in each direction.
+const MAX_RECORDS: usize = 1024;
+
+/// Describes an infinite loop found while running a machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Step at which the loop was detected.
+    pub step: usize,
+    /// Number of steps taken in each iteration of the loop.
+    pub period: usize,
+    /// Number of cells the head moves in each iteration of the loop (0 if the configuration repeats exactly).
+    pub shift: i64,
+    /// States visited in an iteration of the loop, in order of first visit.
+    pub states: Vec<usize>,
+}
+
+/// Result of running a machine with divergence detection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Halted(Status),
+    Diverges(Divergence),
+    StepLimit,
+}
+
+/// Full configuration of the machine at some step.
+struct Snapshot {
+    step: usize,
+    state: usize,
+    tape: Tape,
+}
+
+/// Step at which the head reached a cell further away than any cell visited before or written in the input.
+/// Positions are normalized so that the head is always moving away in the positive direction.
+struct Record {
+    step: usize,
+    state: usize,
+    pos: i64,
+    /// The cells right behind the head, ending on the cell before it.
+    window: Vec<String>,
+    /// Lowest position visited after this record and before the next one.
+    min: i64,
+}
+
+/// Records of the head running away in one direction.
+struct Edge {
+    sign: i64,
+    furthest: i64,
+    records: VecDeque<Record>,
+}
+
+/// Detects configurations which repeat exactly, and translated cycles, in which the head runs away into blank
+/// tape forever while repeating the same pattern of states.
+struct Detector {
+    snapshot: Snapshot,
+    next_snapshot: usize,
+    edges: [Edge; 2],
+}
+
+/// Runs the simulator until it halts, until a loop is detected, or until the given number of steps has been taken.
+pub fn run(sim: &mut Simulator, max_steps: usize) -> Outcome {
+    run_with(sim, max_steps, |_| {})
+}
+
+/// Same as `run`, but calls `f` on every step taken.
+pub fn run_with<F: FnMut(&Step)>(sim: &mut Simulator, max_steps: usize, mut f: F) -> Outcome {
+    let mut detector = Detector::new(sim);
+
+    while sim.steps() < max_steps {
+        match sim.step() {
+            Some(step) => f(&step),
+            None => return Outcome::Halted(sim.status()),
+        }
+
+        if let Some((period, shift)) = detector.observe(sim) {
+            return Outcome::Diverges(Divergence {
+                step: sim.steps(),
+                period,
+                shift,
+                states: loop_states(sim, period),
+            });
+        }
+    }
+
+    match sim.status() {
+        Status::Running => Outcome::StepLimit,
+        status => Outcome::Halted(status),
+    }
+}
+
+/// Collects the states visited in the next `period` steps, in order of first visit.
+fn loop_states(sim: &Simulator, period: usize) -> Vec<usize> {
+    let mut sim = sim.clone();
+    let mut states = Vec::new();
+    for _ in 0..period {
+        if !states.contains(&sim.state()) {
+            states.push(sim.state());
+        }
+        sim.step();
+    }
+    states
+}
+
+impl Detector {
+    fn new(sim: &Simulator) -> Self {
+        // The input must be left behind before the head is considered to be running away.
+        let tape = sim.tape();
+        let (min, max) = tape.bounds();
+        let last = (min..=max)
+            .rev()
+            .find(|&p| !tape.get(p).is_empty())
+            .unwrap_or(0);
+        let first = (min..=max).find(|&p| !tape.get(p).is_empty()).unwrap_or(0);
+
+        let edge = |sign: i64, furthest: i64| Edge {
+            sign,
+            furthest: furthest.max(sign * tape.head()),
+            records: VecDeque::new(),
+        };
+
+        Self {
+            snapshot: Snapshot {
+                step: sim.steps(),
+                state: sim.state(),
+                tape: tape.clone(),
+            },
+            next_snapshot: sim.steps() + 1,
+            edges: [edge(1, last), edge(-1, -first)],
+        }
+    }
+
+    /// Checks the current configuration for loops. Returns the period of the loop and how much the head moves in
+    /// each iteration, if a loop was found.
+    fn observe(&mut self, sim: &Simulator) -> Option<(usize, i64)> {
+        // Exact repetitions are found by comparing against snapshots taken at powers of two (Brent's algorithm).
+        let tape = sim.tape();
+        if sim.state() == self.snapshot.state
+            && tape.head() == self.snapshot.tape.head()
+            && same_cells(tape, &self.snapshot.tape)
+        {
+            return Some((sim.steps() - self.snapshot.step, 0));
+        }
+
+        if sim.steps() >= self.next_snapshot {
+            self.snapshot = Snapshot {
+                step: sim.steps(),
+                state: sim.state(),
+                tape: tape.clone(),
+            };
+            self.next_snapshot *= 2;
+        }
+
+        for edge in self.edges.iter_mut() {
+            if let Some(found) = edge.observe(sim) {
+                return Some(found);
+            }
+        }
+
+        None
+    }
+}
+
+impl Edge {
+    /// Returns the cell at the given normalized position.
+    fn get<'a>(&self, tape: &'a Tape, pos: i64) -> &'a str {
+        tape.get(self.sign * pos)
+    }
+
+    fn observe(&mut self, sim: &Simulator) -> Option<(usize, i64)> {
+        let tape = sim.tape();
+        let pos = self.sign * tape.head();
+
+        if pos <= self.furthest {
+            if let Some(last) = self.records.back_mut() {
+                last.min = last.min.min(pos);
+            }
+            return None;
+        }
+        self.furthest = pos;
+
+        // The behaviour of the machine between two records only depends on the cells between the lowest position
+        // visited and the head. If those cells are the same on both records, relative to the head, and the state is
+        // the same, then the machine will keep repeating itself further away.
+        let mut min = pos;
+        for old in self.records.iter().rev() {
+            min = min.min(old.min);
+            let back = (old.pos - min) as usize;
+            if back > WINDOW {
+                break;
+            }
+
+            if old.state == sim.state() {
+                let shift = pos - old.pos;
+                let same = (0..back)
+                    .all(|i| old.window[WINDOW - 1 - i] == self.get(tape, pos - 1 - i as i64));
+
+                if same {
+                    return Some((sim.steps() - old.step, self.sign * shift));
+                }
+            }
+        }
+
+        if self.records.len() == MAX_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(Record {
+            step: sim.steps(),
+            state: sim.state(),
+            pos,
+            window: (0..WINDOW as i64)
+                .map(|i| self.get(tape, pos - WINDOW as i64 + i).to_owned())
+                .collect(),
+            min: pos,
+        });
+
+        None
+    }
+}
+
+/// Checks if two tapes have the same symbols on every cell.
+fn same_cells(lhs: &Tape, rhs: &Tape) -> bool {
+    let (lmin, lmax) = lhs.bounds();
+    let (rmin, rmax) = rhs.bounds();
+    (lmin.min(rmin)..=lmax.max(rmax)).all(|p| lhs.get(p) == rhs.get(p))
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Halted(Status::Accepted) => write!(f, "accepted"),
+            Outcome::Halted(Status::Rejected) => write!(f, "rejected"),
+            Outcome::Halted(_) => write!(f, "stuck, no transition for the current symbol"),
+            Outcome::StepLimit => write!(f, "step limit reached"),
+            Outcome::Diverges(d) => {
+                let states = d
+                    .states
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                write!(f, "diverges, ")?;
+                match d.shift {
+                    0 => write!(f, "configuration repeats every {} steps", d.period)?,
+                    shift => write!(
+                        f,
+                        "head runs {} forever, moving {} cell{} every {} steps",
+                        if shift > 0 { "right" } else { "left" },
+                        shift.abs(),
+                        if shift.abs() == 1 { "" } else { "s" },
+                        d.period
+                    )?,
+                }
+                write!(f, " through states {}", states)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Direction, Machine, Transition};
+
+    #[test]
+    fn test_exact_cycle() {
+        // Bounces between two cells forever.
+        let mut m = Machine::new();
+        let s = m.push_state();
+        m.push_transition(Transition::new((0, None), (s, None), Direction::Right));
+        m.push_transition(Transition::new((s, None), (0, None), Direction::Left));
+
+        let outcome = run(&mut Simulator::new(&m, Tape::parse("ab")), 1000);
+        match outcome {
+            Outcome::Diverges(d) => {
+                assert_eq!(d.period, 2);
+                assert_eq!(d.shift, 0);
+                assert_eq!(d.states.len(), 2);
+            }
+            o => panic!("Expected divergence, got {:?}", o),
+        }
+    }
+
+    #[test]
+    fn test_translated_cycle() {
+        // Looks for a '#' to the left, going two cells left and one right each time.
+        let mut m = Machine::new();
+        let s = m.push_state();
+        let t = m.push_state();
+        m.push_transition(Transition::new((0, Some("#")), (1, None), Direction::Stay));
+        m.push_transition(Transition::new((0, None), (s, None), Direction::Left));
+        m.push_transition(Transition::new((s, None), (t, None), Direction::Left));
+        m.push_transition(Transition::new((t, None), (0, None), Direction::Right));
+
+        let outcome = run(&mut Simulator::new(&m, Tape::parse("0110")), 1000);
+        match outcome {
+            Outcome::Diverges(d) => {
+                assert_eq!(d.period, 3);
+                assert_eq!(d.shift, -1);
+                assert_eq!(d.states, vec![t, 0, s]);
+            }
+            o => panic!("Expected divergence, got {:?}", o),
+        }
+
+        let outcome = run(&mut Simulator::new(&m, Tape::parse("#0110")), 1000);
+        assert_eq!(outcome, Outcome::Halted(Status::Accepted));
+    }
+}
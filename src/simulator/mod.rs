@@ -0,0 +1,13 @@
+mod runner;
+mod tape;
+
+pub mod cases;
+pub mod complexity;
+pub mod coverage;
+pub mod debugger;
+pub mod divergence;
+pub mod search;
+pub mod tracer;
+
+pub use runner::*;
+pub use tape::*;
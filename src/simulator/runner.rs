@@ -0,0 +1,212 @@
+use super::Tape;
+use crate::data::{Direction, Machine, Transition};
+
+/// Represents the status of a simulated machine.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+    Running,
+    Accepted,
+    Rejected,
+    /// The machine is in a state with no transition for the current symbol.
+    Stuck,
+}
+
+/// Describes a single transition taken by the simulator.
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// Index of the transition taken.
+    pub transition: usize,
+    pub from: usize,
+    pub to: usize,
+    pub pos: i64,
+    pub read: String,
+    pub written: String,
+    pub dir: Direction,
+}
+
+/// Runs a turing machine over a tape, one transition at a time.
+/// Transitions which match the read symbol exactly take priority over transitions with a None (any) symbol.
+#[derive(Clone)]
+pub struct Simulator<'a> {
+    machine: &'a Machine,
+    outgoing: Vec<Vec<usize>>,
+    state: usize,
+    tape: Tape,
+    steps: usize,
+}
+
+impl<'a> Simulator<'a> {
+    /// Creates a new simulator for the given machine, starting on the initial state.
+    pub fn new(machine: &'a Machine, tape: Tape) -> Self {
+        let mut outgoing = vec![Vec::new(); machine.state_count];
+        for (i, t) in machine.transitions.iter().enumerate() {
+            outgoing[t.from.0].push(i);
+        }
+
+        Self {
+            machine,
+            outgoing,
+            state: 0,
+            tape,
+            steps: 0,
+        }
+    }
+
+    /// Returns the machine being simulated.
+    pub fn machine(&self) -> &'a Machine {
+        self.machine
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> usize {
+        self.state
+    }
+
+    /// Returns the current tape.
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// Returns the number of steps taken so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the index of the transition which will be taken on the next step, if any.
+    pub fn next_transition(&self) -> Option<usize> {
+        if self.state == 1 || self.state == 2 {
+            return None;
+        }
+
+        let read = self.tape.read();
+        let outgoing = &self.outgoing[self.state];
+        outgoing
+            .iter()
+            .find(|&&i| matches!(&self.machine.transitions[i].from.1, Some(s) if s == read))
+            .or_else(|| {
+                outgoing
+                    .iter()
+                    .find(|&&i| self.machine.transitions[i].from.1.is_none())
+            })
+            .copied()
+    }
+
+    /// Returns the indices of every transition which may be taken on the next step. There is more than one only if
+    /// the machine is nondeterministic, in which case `next_transition` takes the first one.
+    pub fn branches(&self) -> Vec<usize> {
+        if self.state == 1 || self.state == 2 {
+            return Vec::new();
+        }
+
+        let outgoing = self.outgoing[self.state].iter().copied();
+        self.machine.branches_among(outgoing, self.tape.read())
+    }
+
+    /// Returns the current status of the machine.
+    pub fn status(&self) -> Status {
+        match self.state {
+            1 => Status::Accepted,
+            2 => Status::Rejected,
+            _ if self.next_transition().is_none() => Status::Stuck,
+            _ => Status::Running,
+        }
+    }
+
+    /// Takes a single step, returning the step taken, or None if the machine has halted.
+    pub fn step(&mut self) -> Option<Step> {
+        let index = self.next_transition()?;
+        Some(self.take(index))
+    }
+
+    /// Takes a step through the given transition, which must be one of the current branches.
+    pub fn take(&mut self, index: usize) -> Step {
+        let Transition { to, dir, .. } = &self.machine.transitions[index];
+
+        let read = self.tape.read().to_owned();
+        let written = to.1.clone().unwrap_or_else(|| read.clone());
+        let step = Step {
+            transition: index,
+            from: self.state,
+            to: to.0,
+            pos: self.tape.head(),
+            read,
+            written,
+            dir: *dir,
+        };
+
+        self.tape.write(&step.written);
+        self.tape.shift(step.dir);
+        self.state = step.to;
+        self.steps += 1;
+        step
+    }
+
+    /// Runs the machine until it halts or until the given number of steps has been taken.
+    pub fn run(&mut self, max_steps: usize) -> Status {
+        for _ in 0..max_steps {
+            if self.step().is_none() {
+                break;
+            }
+        }
+        self.status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flip_bits() {
+        // Flips every bit until a blank is found, and then accepts.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new(
+            (0, Some("0")),
+            (0, Some("1")),
+            Direction::Right,
+        ));
+        m.push_transition(Transition::new(
+            (0, Some("1")),
+            (0, Some("0")),
+            Direction::Right,
+        ));
+        m.push_transition(Transition::new((0, Some("")), (1, None), Direction::Stay));
+
+        let mut sim = Simulator::new(&m, Tape::parse("0110"));
+        assert_eq!(sim.run(100), Status::Accepted);
+        assert_eq!(sim.steps(), 5);
+        assert_eq!(sim.tape().to_string(), "1001[_]");
+    }
+
+    #[test]
+    fn test_exact_symbol_priority() {
+        // The exact transition must be taken even though the wildcard one comes first.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, None), (2, None), Direction::Stay));
+        m.push_transition(Transition::new(
+            (0, Some("a")),
+            (1, Some("b")),
+            Direction::Left,
+        ));
+
+        let mut sim = Simulator::new(&m, Tape::parse("a"));
+        assert_eq!(sim.run(100), Status::Accepted);
+        assert_eq!(sim.tape().to_string(), "[_]b");
+
+        let mut sim = Simulator::new(&m, Tape::parse("c"));
+        assert_eq!(sim.run(100), Status::Rejected);
+        assert_eq!(sim.tape().to_string(), "[c]");
+    }
+
+    #[test]
+    fn test_stuck() {
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, Some("a")), (3, None), Direction::Right));
+        m.push_state();
+
+        let mut sim = Simulator::new(&m, Tape::parse("a"));
+        assert_eq!(sim.run(100), Status::Stuck);
+        assert_eq!(sim.state(), 3);
+        assert_eq!(sim.steps(), 1);
+    }
+}
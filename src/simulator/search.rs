@@ -0,0 +1,146 @@
+use super::{Simulator, Status, Tape};
+use crate::data::Machine;
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// Result of searching the branches of a nondeterministic machine for one which accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Accepted,
+    /// Every branch rejected the input or got stuck.
+    Rejected,
+    StepLimit,
+}
+
+/// Result of a search, along with the accepting branch if one was found.
+pub struct Search<'a> {
+    pub outcome: Outcome,
+    pub branch: Option<Simulator<'a>>,
+    /// Number of distinct configurations reached by the search.
+    pub configurations: usize,
+}
+
+/// Explores the branches of a machine in breadth-first order until one of them accepts, so that the accepting branch
+/// found takes as few steps as possible. Configurations already reached by another branch aren't explored again,
+/// and at most the given number of steps is taken over all branches.
+pub fn search(machine: &Machine, tape: Tape, max_steps: usize) -> Search<'_> {
+    let start = Simulator::new(machine, tape);
+    let mut seen = HashSet::from([key(&start)]);
+    let mut queue = VecDeque::from([start]);
+    let mut steps = 0;
+
+    while let Some(sim) = queue.pop_front() {
+        if sim.status() == Status::Accepted {
+            return Search {
+                outcome: Outcome::Accepted,
+                branch: Some(sim),
+                configurations: seen.len(),
+            };
+        }
+
+        for index in sim.branches() {
+            if steps == max_steps {
+                return Search {
+                    outcome: Outcome::StepLimit,
+                    branch: None,
+                    configurations: seen.len(),
+                };
+            }
+            steps += 1;
+
+            let mut next = sim.clone();
+            next.take(index);
+            if seen.insert(key(&next)) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    Search {
+        outcome: Outcome::Rejected,
+        branch: None,
+        configurations: seen.len(),
+    }
+}
+
+/// Identifies the configuration of a branch by its state, the non-blank part of its tape and the position of the
+/// head relative to it.
+fn key(sim: &Simulator) -> (usize, i64, Vec<String>) {
+    let tape = sim.tape();
+    let (min, max) = tape.bounds();
+    let cells = (min..=max).map(|p| tape.get(p)).collect::<Vec<_>>();
+    let start = cells
+        .iter()
+        .position(|c| !c.is_empty())
+        .unwrap_or(cells.len());
+    let end = cells
+        .iter()
+        .rposition(|c| !c.is_empty())
+        .map_or(start, |e| e + 1);
+
+    (
+        sim.state(),
+        tape.head() - min - start as i64,
+        cells[start..end].iter().map(|c| c.to_string()).collect(),
+    )
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Accepted => write!(f, "accepted"),
+            Outcome::Rejected => write!(f, "rejected on every branch"),
+            Outcome::StepLimit => write!(f, "step limit reached"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Direction, Transition};
+
+    #[test]
+    fn test_search() {
+        // Either loops forever moving right, or accepts if the current cell holds a '1'.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, None), (0, None), Direction::Right));
+        m.push_transition(Transition::new((0, None), (3, None), Direction::Stay));
+        m.push_transition(Transition::new(
+            (3, Some("1")),
+            (1, Some("x")),
+            Direction::Stay,
+        ));
+        m.push_transition(Transition::new((3, None), (2, None), Direction::Stay));
+        m.push_state();
+
+        let search = super::search(&m, Tape::parse("001"), 1000);
+        assert_eq!(search.outcome, Outcome::Accepted);
+        let branch = search.branch.unwrap();
+        assert_eq!(branch.steps(), 4);
+        assert_eq!(branch.tape().to_string(), "00[x]");
+
+        // Without a '1', the looping branch never stops reaching new configurations.
+        let search = super::search(&m, Tape::parse("000"), 1000);
+        assert_eq!(search.outcome, Outcome::StepLimit);
+        assert!(search.branch.is_none());
+    }
+
+    #[test]
+    fn test_search_rejected() {
+        // Both branches halt without accepting, and the second one gets there through a configuration already
+        // reached by the first.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, None), (3, None), Direction::Right));
+        m.push_transition(Transition::new((0, None), (4, None), Direction::Stay));
+        m.push_transition(Transition::new((3, None), (2, None), Direction::Stay));
+        m.push_transition(Transition::new((4, None), (3, None), Direction::Right));
+        m.push_state();
+        m.push_state();
+
+        let search = super::search(&m, Tape::parse("0"), 1000);
+        assert_eq!(search.outcome, Outcome::Rejected);
+        assert_eq!(search.configurations, 4);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::data::Direction;
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Two-way infinite tape used by the simulator.
+/// Only the cells which were visited or written to are stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tape {
+    cells: VecDeque<String>,
+    offset: i64,
+    head: i64,
+}
+
+impl Tape {
+    /// Creates a new tape with the given symbols, with the head on the first one.
+    pub fn new(symbols: Vec<String>) -> Self {
+        let mut cells = VecDeque::from(symbols);
+        if cells.is_empty() {
+            cells.push_back(String::new());
+        }
+
+        Self {
+            cells,
+            offset: 0,
+            head: 0,
+        }
+    }
+
+    /// Parses a tape from a string.
+    /// If the string contains whitespace, each whitespace separated word is a symbol.
+    /// Otherwise, each character is a symbol. In both cases, '_' stands for the blank symbol.
+    pub fn parse(input: &str) -> Self {
+        Self::parse_with_blank(input, "_")
+    }
+
+    /// Same as `parse`, but with the given symbol standing for the blank symbol.
+    pub fn parse_with_blank(input: &str, blank: &str) -> Self {
+        let convert = |s: &str| {
+            if s == blank {
+                String::new()
+            } else {
+                s.to_owned()
+            }
+        };
+
+        if input.contains(char::is_whitespace) {
+            Self::new(input.split_whitespace().map(convert).collect())
+        } else {
+            Self::new(
+                input
+                    .chars()
+                    .map(|c| convert(c.encode_utf8(&mut [0; 4])))
+                    .collect(),
+            )
+        }
+    }
+
+    /// Returns the position of the head.
+    pub fn head(&self) -> i64 {
+        self.head
+    }
+
+    /// Returns the symbol at the given position.
+    pub fn get(&self, pos: i64) -> &str {
+        if pos < self.offset || pos - self.offset >= self.cells.len() as i64 {
+            ""
+        } else {
+            &self.cells[(pos - self.offset) as usize]
+        }
+    }
+
+    /// Returns the symbol under the head.
+    pub fn read(&self) -> &str {
+        self.get(self.head)
+    }
+
+    /// Writes a symbol to the cell under the head.
+    pub fn write(&mut self, symbol: &str) {
+        let index = (self.head - self.offset) as usize;
+        self.cells[index] = symbol.to_owned();
+    }
+
+    /// Moves the head in the given direction, growing the stored cells if necessary.
+    pub fn shift(&mut self, dir: Direction) {
+        match dir {
+            Direction::Left => {
+                self.head -= 1;
+                if self.head < self.offset {
+                    self.cells.push_front(String::new());
+                    self.offset -= 1;
+                }
+            }
+            Direction::Right => {
+                self.head += 1;
+                if self.head - self.offset >= self.cells.len() as i64 {
+                    self.cells.push_back(String::new());
+                }
+            }
+            Direction::Stay => {}
+        }
+    }
+
+    /// Returns the range of positions which were visited or written to, inclusive.
+    pub fn bounds(&self) -> (i64, i64) {
+        (self.offset, self.offset + self.cells.len() as i64 - 1)
+    }
+
+    /// Formats the cells around the head, with the head between brackets.
+    pub fn window(&self, radius: i64) -> String {
+        (self.head - radius..=self.head + radius)
+            .map(|pos| {
+                if pos == self.head {
+                    format!("[{}]", fmt_symbol(self.get(pos)))
+                } else {
+                    fmt_symbol(self.get(pos)).to_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Formats a symbol so that blanks are visible.
+pub fn fmt_symbol(symbol: &str) -> &str {
+    if symbol.is_empty() {
+        "_"
+    } else {
+        symbol
+    }
+}
+
+impl fmt::Display for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Print every non blank cell, and the cells between them and the head.
+        let first = (self.offset..self.head)
+            .find(|&p| !self.get(p).is_empty())
+            .unwrap_or(self.head);
+        let (_, max) = self.bounds();
+        let last = (self.head..=max)
+            .rev()
+            .find(|&p| !self.get(p).is_empty())
+            .unwrap_or(self.head);
+
+        let separator = if (first..=last).all(|p| self.get(p).chars().count() <= 1) {
+            ""
+        } else {
+            " "
+        };
+
+        let cells = (first..=last)
+            .map(|pos| {
+                if pos == self.head {
+                    format!("[{}]", fmt_symbol(self.get(pos)))
+                } else {
+                    fmt_symbol(self.get(pos)).to_owned()
+                }
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", cells.join(separator))
+    }
+}
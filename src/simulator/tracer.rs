@@ -0,0 +1,137 @@
+use super::{Simulator, Status, Step};
+use crate::data::Direction;
+
+use std::io::Write;
+
+/// Formats in which an execution trace can be written.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// One JSON object per line.
+    JsonLines,
+    /// Comma separated values, with a header line.
+    Csv,
+}
+
+/// Runs the simulator until it halts or reaches the step limit, writing every step to `out` as it is taken.
+/// Nothing is buffered other than by the writer itself, so traces with millions of steps can be written.
+pub fn trace<W: Write>(
+    sim: &mut Simulator,
+    max_steps: usize,
+    format: Format,
+    mut out: W,
+) -> Result<Status, String> {
+    let io_err = |e: std::io::Error| format!("Couldn't write trace: {}", e);
+
+    if format == Format::Csv {
+        writeln!(out, "step,state,next,pos,read,write,move").map_err(io_err)?;
+    }
+
+    while sim.steps() < max_steps {
+        let index = sim.steps();
+        let step = match sim.step() {
+            Some(step) => step,
+            None => break,
+        };
+
+        match format {
+            Format::JsonLines => write_json(&mut out, index, &step),
+            Format::Csv => write_csv(&mut out, index, &step),
+        }
+        .map_err(io_err)?;
+    }
+
+    out.flush().map_err(io_err)?;
+    Ok(sim.status())
+}
+
+fn write_json<W: Write>(out: &mut W, index: usize, step: &Step) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{{\"step\":{},\"state\":{},\"next\":{},\"pos\":{},\"read\":{},\"write\":{},\"move\":\"{}\"}}",
+        index,
+        step.from,
+        step.to,
+        step.pos,
+        json_string(&step.read),
+        json_string(&step.written),
+        direction(step.dir),
+    )
+}
+
+fn write_csv<W: Write>(out: &mut W, index: usize, step: &Step) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{},{},{},{},{},{},{}",
+        index,
+        step.from,
+        step.to,
+        step.pos,
+        csv_field(&step.read),
+        csv_field(&step.written),
+        direction(step.dir),
+    )
+}
+
+fn direction(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Left => "L",
+        Direction::Right => "R",
+        Direction::Stay => "S",
+    }
+}
+
+/// Quotes and escapes a string so that it is a valid JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quotes a CSV field if it contains special characters.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Machine, Transition};
+    use crate::simulator::Tape;
+
+    #[test]
+    fn test_trace_formats() {
+        // Replaces the first symbol with a ',' and moves right.
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, None), (1, Some(",")), Direction::Right));
+
+        let mut out = Vec::new();
+        let mut sim = Simulator::new(&m, Tape::parse("\""));
+        let status = trace(&mut sim, 10, Format::JsonLines, &mut out).unwrap();
+        assert_eq!(status, Status::Accepted);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"step\":0,\"state\":0,\"next\":1,\"pos\":0,\"read\":\"\\\"\",\"write\":\",\",\"move\":\"R\"}\n"
+        );
+
+        let mut out = Vec::new();
+        let mut sim = Simulator::new(&m, Tape::parse("_"));
+        trace(&mut sim, 10, Format::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "step,state,next,pos,read,write,move\n0,0,1,0,,\",\",R\n"
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::data::{Direction, Machine, Transition};
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Symbol written for set bits. Unset bits are blank, so that blank cells encode blank symbols.
+pub const ONE: &str = "1";
+
+/// Encodes each symbol as a fixed-width block of binary cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reduction {
+    /// Number of cells used by each symbol.
+    pub width: usize,
+    /// Symbols encoded, in order of their codes. The blank is always the first one, and is encoded with zeros.
+    pub symbols: Vec<String>,
+}
+
+/// Machine built by a reduction, with the states which write blocks and move between them shared by transitions.
+struct Builder<'a> {
+    reduction: &'a Reduction,
+    m: Machine,
+    writes: HashMap<(usize, usize, Direction, usize), usize>,
+    moves: HashMap<(Direction, usize, usize), usize>,
+}
+
+impl Reduction {
+    /// Creates a reduction for the given symbols, plus the blank.
+    pub fn new(symbols: &BTreeSet<String>) -> Self {
+        let symbols = std::iter::once(String::new())
+            .chain(symbols.iter().filter(|s| !s.is_empty()).cloned())
+            .collect::<Vec<_>>();
+        let mut width = 1;
+        while 1 << width < symbols.len() {
+            width += 1;
+        }
+        Self { width, symbols }
+    }
+
+    /// Returns the bits of the code of the given symbol, most significant first.
+    fn bits(&self, code: usize) -> impl Iterator<Item = bool> + '_ {
+        (0..self.width).rev().map(move |i| (code >> i) & 1 == 1)
+    }
+
+    fn code(&self, symbol: &str) -> Option<usize> {
+        self.symbols.iter().position(|s| s == symbol)
+    }
+
+    /// Encodes the cells of an input tape.
+    pub fn encode(&self, symbols: &[String]) -> Result<Vec<String>, String> {
+        let mut cells = Vec::new();
+        for sym in symbols {
+            let code = self
+                .code(sym)
+                .ok_or(format!("Symbol '{}' isn't part of the alphabet", sym))?;
+            cells.extend(self.bits(code).map(bit));
+        }
+        Ok(cells)
+    }
+
+    /// Converts a machine into one which only reads and writes blanks and ones, with each symbol stored in a block
+    /// of `width` cells. The head always starts and ends each step on the first cell of a block.
+    ///
+    /// Each step of the original machine reads the block from left to right, remembering the bits read in the
+    /// state, and then writes the new block from right to left, before moving to the next block. Transitions which
+    /// read or write symbols which aren't encoded are dropped, as they can't be reached.
+    pub fn apply(&self, machine: &Machine) -> Machine {
+        let mut b = Builder {
+            reduction: self,
+            m: Machine {
+                state_count: machine.state_count,
+                transitions: Vec::new(),
+            },
+            writes: HashMap::new(),
+            moves: HashMap::new(),
+        };
+
+        let mut states = machine
+            .transitions
+            .iter()
+            .map(|t| t.from.0)
+            .collect::<Vec<_>>();
+        states.sort_unstable();
+        states.dedup();
+
+        for state in states {
+            // Transition taken for each code, if any.
+            let transitions = (0..1 << self.width)
+                .map(|code| {
+                    let sym = self.symbols.get(code)?;
+                    let outgoing = machine.transitions.iter().filter(|t| t.from.0 == state);
+                    let t = outgoing
+                        .clone()
+                        .find(|t| t.from.1.as_ref() == Some(sym))
+                        .or_else(|| outgoing.clone().find(|t| t.from.1.is_none()))?;
+                    match &t.to.1 {
+                        Some(w) => Some((t, self.code(w)?)),
+                        None => Some((t, code)),
+                    }
+                })
+                .collect::<Vec<_>>();
+            b.read(state, 0, 0, &transitions);
+        }
+
+        b.m
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Adds the transitions of a state which has read the first `len` bits of a block, `prefix` being their value.
+    fn read(
+        &mut self,
+        state: usize,
+        prefix: usize,
+        len: usize,
+        transitions: &[Option<(&Transition, usize)>],
+    ) {
+        let width = self.reduction.width;
+
+        for b in [false, true] {
+            let prefix = prefix << 1 | b as usize;
+            let rest = width - len - 1;
+            if transitions[prefix << rest..(prefix + 1) << rest]
+                .iter()
+                .all(|t| t.is_none())
+            {
+                continue;
+            }
+
+            if rest > 0 {
+                let next = self.m.push_state();
+                self.read(next, prefix, len + 1, transitions);
+                self.m.push_transition(Transition {
+                    from: (state, Some(bit(b))),
+                    to: (next, Some(bit(b))),
+                    dir: Direction::Right,
+                    locs: Vec::new(),
+                });
+            } else if let Some((t, code)) = transitions[prefix] {
+                // The last bit is overwritten right away, and the rest of the block on the way back.
+                let last = self.reduction.bits(code).last().unwrap();
+                let (to, dir) = match width {
+                    1 => (t.to.0, t.dir),
+                    _ => (self.write(code, width - 2, t.dir, t.to.0), Direction::Left),
+                };
+                self.m.push_transition(Transition {
+                    from: (state, Some(bit(b))),
+                    to: (to, Some(bit(last))),
+                    dir,
+                    locs: t.locs.clone(),
+                });
+            }
+        }
+    }
+
+    /// Returns a state which writes the bits of a code up to the given index, from right to left, and then moves to
+    /// the next block in the given direction.
+    fn write(&mut self, code: usize, index: usize, dir: Direction, to: usize) -> usize {
+        if let Some(&state) = self.writes.get(&(code, index, dir, to)) {
+            return state;
+        }
+
+        let state = self.m.push_state();
+        self.writes.insert((code, index, dir, to), state);
+        let (next, next_dir) = match index {
+            0 => (self.moves(dir, self.reduction.width - 1, to), dir),
+            _ => (self.write(code, index - 1, dir, to), Direction::Left),
+        };
+        let b = self.reduction.bits(code).nth(index).unwrap();
+        self.m.push_transition(Transition {
+            from: (state, None),
+            to: (next, Some(bit(b))),
+            dir: next_dir,
+            locs: Vec::new(),
+        });
+        state
+    }
+
+    /// Returns a state which moves the head the given number of cells in the given direction.
+    fn moves(&mut self, dir: Direction, count: usize, to: usize) -> usize {
+        if count == 0 || dir == Direction::Stay {
+            return to;
+        }
+        if let Some(&state) = self.moves.get(&(dir, count, to)) {
+            return state;
+        }
+
+        let state = self.m.push_state();
+        self.moves.insert((dir, count, to), state);
+        let next = self.moves(dir, count - 1, to);
+        self.m.push_transition(Transition {
+            from: (state, None),
+            to: (next, None),
+            dir,
+            locs: Vec::new(),
+        });
+        state
+    }
+}
+
+/// Returns the cell symbol for a bit.
+fn bit(b: bool) -> String {
+    match b {
+        true => ONE.to_owned(),
+        false => String::new(),
+    }
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (code, sym) in self.symbols.iter().enumerate() {
+            let bits = self
+                .bits(code)
+                .map(|b| if b { '1' } else { '0' })
+                .collect::<String>();
+            writeln!(f, "'{}' -> {}", sym, bits)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, Status, Tape};
+
+    #[test]
+    fn test_binary() {
+        // Replaces 'a' by 'c' and 'b' by blank until it finds a blank, and then moves back to the start.
+        let mut m = Machine::new();
+        let back = m.push_state();
+        for (read, write) in [("a", "c"), ("b", "")] {
+            m.push_transition(Transition::new(
+                (0, Some(read)),
+                (0, Some(write)),
+                Direction::Right,
+            ));
+        }
+        m.push_transition(Transition::new(
+            (0, Some("")),
+            (back, None),
+            Direction::Left,
+        ));
+        m.push_transition(Transition::new(
+            (back, Some("c")),
+            (1, None),
+            Direction::Stay,
+        ));
+        m.push_transition(Transition::new((back, None), (back, None), Direction::Left));
+
+        let input = ["a", "b", "a"].map(|s| s.to_owned());
+        let mut sim = Simulator::new(&m, Tape::new(input.to_vec()));
+        assert_eq!(sim.run(100), Status::Accepted);
+        let expected = sim.tape().to_string();
+
+        let symbols = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let reduction = Reduction::new(&symbols);
+        assert_eq!(reduction.width, 2);
+        let binary = reduction.apply(&m);
+        assert!(binary
+            .transitions
+            .iter()
+            .all(|t| [None, Some(""), Some(ONE)].contains(&t.to.1.as_deref())));
+
+        let mut sim = Simulator::new(&binary, Tape::new(reduction.encode(&input).unwrap()));
+        assert_eq!(sim.run(1000), Status::Accepted);
+
+        // Decode the final tape and check that it's the same as the one from the original machine.
+        let (min, max) = sim.tape().bounds();
+        let cells = (min..=max + 1)
+            .map(|p| sim.tape().get(p).to_owned())
+            .collect::<Vec<_>>();
+        let decoded = cells
+            .chunks_exact(2)
+            .map(|c| c.iter().fold(0, |code, b| code << 1 | (b == ONE) as usize))
+            .map(|code| reduction.symbols[code].clone())
+            .collect::<Vec<_>>();
+        let head = (sim.tape().head() - min) / 2;
+        let mut tape = Tape::new(decoded);
+        for _ in 0..head {
+            tape.shift(Direction::Right);
+        }
+        assert_eq!(
+            tape.to_string().trim_matches('_'),
+            expected.trim_matches('_')
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::data::{Machine, MultiMachine};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+/// Characters given to symbols which can't be used as they are, in order of preference.
+const CANDIDATES: &str =
+    "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789#$%&+-./:<=>?@^|~!";
+
+/// Maps the symbols of a machine onto single characters, for formats which don't support longer symbols.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoding {
+    pub table: BTreeMap<String, char>,
+}
+
+impl Encoding {
+    /// Builds an encoding for the given symbols, which never uses the reserved characters. Symbols with a custom
+    /// mapping use it, symbols which are already valid characters are kept, and every other symbol gets the first
+    /// character which isn't taken yet.
+    pub fn new(
+        symbols: &BTreeSet<String>,
+        reserved: &HashSet<char>,
+        custom: &[(String, char)],
+    ) -> Result<Self, String> {
+        let mut table = BTreeMap::new();
+        let mut taken = HashSet::new();
+
+        for (sym, c) in custom.iter().filter(|(sym, _)| symbols.contains(sym)) {
+            if reserved.contains(c) {
+                return Err(format!(
+                    "Symbol '{}' can't be encoded as '{}', reserved character",
+                    sym, c
+                ));
+            }
+            if let Some(old) = table.insert(sym.clone(), *c) {
+                return Err(format!(
+                    "Symbol '{}' is encoded both as '{}' and as '{}'",
+                    sym, old, c
+                ));
+            }
+            if !taken.insert(*c) {
+                return Err(format!(
+                    "Character '{}' is used to encode more than one symbol",
+                    c
+                ));
+            }
+        }
+
+        // Symbols which are valid characters are kept before any other symbol is given a character.
+        let valid = |sym: &str| {
+            let mut chars = sym.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !reserved.contains(&c) && !c.is_whitespace() => Some(c),
+                _ => None,
+            }
+        };
+        for sym in symbols.iter() {
+            if table.contains_key(sym) {
+                continue;
+            }
+            if let Some(c) = valid(sym).filter(|c| !taken.contains(c)) {
+                table.insert(sym.clone(), c);
+                taken.insert(c);
+            }
+        }
+
+        let mut candidates = CANDIDATES
+            .chars()
+            .filter(|c| !reserved.contains(c) && !symbols.contains(&c.to_string()));
+        for sym in symbols.iter() {
+            if table.contains_key(sym) {
+                continue;
+            }
+            let c = candidates
+                .by_ref()
+                .find(|c| !taken.contains(c))
+                .ok_or(format!(
+                    "Too many symbols to encode, no character left for '{}'",
+                    sym
+                ))?;
+            table.insert(sym.clone(), c);
+            taken.insert(c);
+        }
+
+        Ok(Self { table })
+    }
+
+    /// Builds an encoding for every symbol read or written by the machine, except the blank.
+    pub fn for_machine(
+        machine: &Machine,
+        reserved: &HashSet<char>,
+        custom: &[(String, char)],
+    ) -> Result<Self, String> {
+        let symbols = machine
+            .transitions
+            .iter()
+            .flat_map(|t| t.from.1.iter().chain(t.to.1.iter()))
+            .filter(|s| !s.is_empty())
+            .cloned()
+            .collect();
+        Self::new(&symbols, reserved, custom)
+    }
+
+    /// Checks if every symbol is encoded as itself.
+    pub fn is_identity(&self) -> bool {
+        self.table
+            .iter()
+            .all(|(sym, c)| sym.chars().eq(std::iter::once(*c)))
+    }
+
+    /// Replaces every symbol in the machine by its encoding.
+    pub fn apply(&self, mut machine: Machine) -> Machine {
+        let encode = |sym: &mut Option<String>| {
+            if let Some(c) = sym.as_ref().and_then(|s| self.table.get(s)) {
+                *sym = Some(c.to_string());
+            }
+        };
+        for t in machine.transitions.iter_mut() {
+            encode(&mut t.from.1);
+            encode(&mut t.to.1);
+        }
+        machine
+    }
+
+    /// Replaces every symbol on each tape of the machine by its encoding.
+    pub fn apply_multi(&self, mut machine: MultiMachine) -> MultiMachine {
+        for t in machine.transitions.iter_mut() {
+            for sym in t.from.1.iter_mut().chain(t.to.1.iter_mut()) {
+                if let Some(c) = sym.as_ref().and_then(|s| self.table.get(s)) {
+                    *sym = Some(c.to_string());
+                }
+            }
+        }
+        machine
+    }
+}
+
+/// Parses a custom symbol mapping, written as `SYMBOL=C`.
+pub fn parse_mapping(s: &str) -> Result<(String, char), String> {
+    let (sym, c) = s
+        .rsplit_once('=')
+        .ok_or(format!("Invalid symbol mapping '{}', expected SYMBOL=C", s))?;
+    let mut chars = c.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !sym.is_empty() => Ok((sym.to_owned(), c)),
+        _ => Err(format!(
+            "Invalid symbol mapping '{}', expected a symbol and a single character",
+            s
+        )),
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (sym, c) in self.table.iter() {
+            writeln!(f, "'{}' -> '{}'", sym, c)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        let symbols = ["#", "0", "_", "a", "carry", "done"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let reserved = ['_', ';', '*'].into_iter().collect();
+
+        let encoding = Encoding::new(&symbols, &reserved, &[]).unwrap();
+        let table = encoding
+            .table
+            .iter()
+            .map(|(s, c)| (s.as_str(), *c))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            table,
+            vec![
+                ("#", '#'),
+                ("0", '0'),
+                ("_", 'b'),
+                ("a", 'a'),
+                ("carry", 'c'),
+                ("done", 'd')
+            ]
+        );
+        assert!(!encoding.is_identity());
+
+        let custom = [parse_mapping("carry=a").unwrap()];
+        let encoding = Encoding::new(&symbols, &reserved, &custom).unwrap();
+        assert_eq!(encoding.table["carry"], 'a');
+        assert_eq!(encoding.table["_"], 'b');
+        assert_eq!(encoding.table["a"], 'c');
+
+        let custom = [parse_mapping("carry=*").unwrap()];
+        assert!(Encoding::new(&symbols, &reserved, &custom).is_err());
+        assert!(parse_mapping("carry=cc").is_err());
+    }
+}
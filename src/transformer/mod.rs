@@ -0,0 +1,4 @@
+pub mod binary;
+pub mod encoding;
+pub mod one_way;
+pub mod tracks;
@@ -0,0 +1,221 @@
+use crate::data::{Direction, Machine, Target, Transition};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Converts a machine which assumes a two-way infinite tape into one which only needs a one-way infinite tape.
+///
+/// A prologue shifts the input one cell to the right and writes the left marker on the first cell. Whenever the
+/// machine moves left onto the marker, the visited part of the tape is shifted one cell to the right, so that the
+/// head finds a new blank cell instead of falling off the left end. To know where the visited part of the tape ends,
+/// blank cells are replaced by the visited blank symbol once they are written to.
+pub fn transform(
+    machine: Machine,
+    alphabet: &HashSet<String>,
+    target: &Target,
+) -> Result<Machine, String> {
+    let marker = &target.left_marker;
+    let visited = &target.visited_blank;
+
+    // Symbols which may be found on the visited part of the tape.
+    let mut symbols = alphabet.iter().cloned().collect::<BTreeSet<_>>();
+    for t in machine.transitions.iter() {
+        symbols.extend(t.from.1.iter().chain(t.to.1.iter()).cloned());
+    }
+    symbols.remove("");
+
+    for sym in [marker, visited] {
+        if sym.is_empty() || symbols.contains(sym) {
+            return Err(format!(
+                "Symbol '{}' is reserved for one-way tapes, and can't be blank or be used by the machine",
+                sym
+            ));
+        }
+    }
+    if marker == visited {
+        return Err(format!(
+            "The left marker and the visited blank must be different, but both are '{}'",
+            marker
+        ));
+    }
+    symbols.insert(visited.clone());
+
+    let mut m = Machine {
+        state_count: machine.state_count,
+        transitions: Vec::new(),
+    };
+
+    // The initial state is taken by the prologue, so the old one is moved to a new state.
+    let start = m.push_state();
+    let rename = |s: usize| if s == 0 { start } else { s };
+
+    // Blanks written or kept when reading a blank are replaced by visited blanks.
+    let written = |w: &Option<String>| match w {
+        Some(s) if s.is_empty() => Some(visited.clone()),
+        w => w.clone(),
+    };
+
+    let reads_blank = machine
+        .transitions
+        .iter()
+        .filter(|t| t.from.1.as_deref() == Some(""))
+        .map(|t| t.from.0)
+        .collect::<HashSet<_>>();
+
+    // Left moves go through a state which checks if the head is on the marker.
+    let mut checks = HashMap::new();
+    for t in machine.transitions.iter() {
+        let from = rename(t.from.0);
+        let mut to = rename(t.to.0);
+        if t.dir == Direction::Left {
+            to = *checks.entry(to).or_insert_with(|| m.push_state());
+        }
+
+        let transition = |read: Option<&String>, write: Option<String>| Transition {
+            from: (from, read.cloned()),
+            to: (to, write),
+            dir: t.dir,
+            locs: t.locs.clone(),
+        };
+
+        match &t.from.1 {
+            Some(s) if s.is_empty() => {
+                m.push_transition(transition(
+                    Some(s),
+                    written(&t.to.1).or_else(|| Some(visited.clone())),
+                ));
+                m.push_transition(transition(Some(visited), written(&t.to.1)));
+            }
+            None => {
+                if !reads_blank.contains(&t.from.0) {
+                    m.push_transition(transition(
+                        Some(&String::new()),
+                        written(&t.to.1).or_else(|| Some(visited.clone())),
+                    ));
+                }
+                m.push_transition(transition(None, written(&t.to.1)));
+            }
+            read => m.push_transition(transition(read.as_ref(), written(&t.to.1))),
+        }
+    }
+
+    let mut checks = checks.into_iter().collect::<Vec<_>>();
+    checks.sort();
+    for (dst, check) in checks {
+        let carry = shift(&mut m, &symbols, marker, dst);
+        m.push_transition(Transition {
+            from: (check, Some(marker.clone())),
+            to: (carry[visited], None),
+            dir: Direction::Right,
+            locs: Vec::new(),
+        });
+        m.push_transition(Transition {
+            from: (check, None),
+            to: (dst, None),
+            dir: Direction::Stay,
+            locs: Vec::new(),
+        });
+    }
+
+    // Prologue, which writes the marker and shifts the input to the right.
+    let carry = shift(&mut m, &symbols, marker, start);
+    m.push_transition(Transition {
+        from: (0, Some(String::new())),
+        to: (start, Some(marker.clone())),
+        dir: Direction::Right,
+        locs: Vec::new(),
+    });
+    for sym in symbols.iter() {
+        m.push_transition(Transition {
+            from: (0, Some(sym.clone())),
+            to: (carry[sym], Some(marker.clone())),
+            dir: Direction::Right,
+            locs: Vec::new(),
+        });
+    }
+
+    Ok(m)
+}
+
+/// Adds states which shift the visited part of the tape, starting on the cell under the head, one cell to the right.
+/// Returns the state which writes each symbol on the first cell. Afterwards, the head moves back to the cell right
+/// after the marker and the machine goes to `dst`.
+fn shift(
+    m: &mut Machine,
+    symbols: &BTreeSet<String>,
+    marker: &str,
+    dst: usize,
+) -> HashMap<String, usize> {
+    let carry = symbols
+        .iter()
+        .map(|s| (s.clone(), m.push_state()))
+        .collect::<HashMap<_, _>>();
+    let back = m.push_state();
+
+    for (carried, &state) in carry.iter() {
+        for sym in symbols.iter() {
+            m.push_transition(Transition {
+                from: (state, Some(sym.clone())),
+                to: (carry[sym], Some(carried.clone())),
+                dir: Direction::Right,
+                locs: Vec::new(),
+            });
+        }
+        m.push_transition(Transition {
+            from: (state, Some(String::new())),
+            to: (back, Some(carried.clone())),
+            dir: Direction::Left,
+            locs: Vec::new(),
+        });
+    }
+
+    m.push_transition(Transition {
+        from: (back, Some(marker.to_owned())),
+        to: (dst, None),
+        dir: Direction::Right,
+        locs: Vec::new(),
+    });
+    m.push_transition(Transition {
+        from: (back, None),
+        to: (back, None),
+        dir: Direction::Left,
+        locs: Vec::new(),
+    });
+
+    carry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Simulator, Status, Tape};
+
+    #[test]
+    fn test_one_way() {
+        // Moves left of the input twice, writing an 'x' on the first cell and a blank on the second.
+        let mut m = Machine::new();
+        let s = m.push_state();
+        m.push_transition(Transition::new((0, None), (s, None), Direction::Left));
+        m.push_transition(Transition::new((s, None), (1, Some("x")), Direction::Left));
+
+        let mut sim = Simulator::new(&m, Tape::parse("ab"));
+        assert_eq!(sim.run(100), Status::Accepted);
+        assert_eq!(sim.tape().to_string(), "[_]xab");
+
+        let alphabet = ["a", "b", "x"].iter().map(|s| s.to_string()).collect();
+        let m = transform(m, &alphabet, &Target::default()).unwrap();
+        let mut sim = Simulator::new(&m, Tape::parse("ab"));
+        assert_eq!(sim.run(1000), Status::Accepted);
+        assert_eq!(sim.tape().to_string(), ">[.]xab");
+        assert_eq!(sim.tape().bounds().0, 0);
+
+        let mut sim = Simulator::new(&m, Tape::parse(""));
+        assert_eq!(sim.run(1000), Status::Accepted);
+        assert_eq!(sim.tape().to_string(), ">[.]x.");
+
+        let target = Target {
+            left_marker: "a".to_owned(),
+            ..Default::default()
+        };
+        assert!(transform(m, &alphabet, &target).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::data::{tuple_symbol, Direction, Machine, MultiMachine, Transition};
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+/// Symbol which marks the cell under the head of a tape, on the track which follows the tape's symbols.
+pub const HEAD: &str = "^";
+
+/// Cell of the single tape, holding the symbol and head marker of each simulated tape.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Cell {
+    symbols: Vec<String>,
+    heads: Vec<bool>,
+}
+
+impl Cell {
+    fn symbol(&self) -> String {
+        let parts = self
+            .symbols
+            .iter()
+            .zip(self.heads.iter())
+            .flat_map(|(s, &h)| [s.clone(), if h { HEAD.to_owned() } else { String::new() }])
+            .collect::<Vec<_>>();
+        tuple_symbol(&parts)
+    }
+}
+
+/// States of the single tape machine.
+/// Each step of the simulated machine starts on the leftmost head. The cells are scanned to the right until every
+/// head is found, and then scanned back to the left, writing each symbol and moving each head marker, after which
+/// the machine looks for the leftmost head again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// Looking for the heads, knowing the symbols under the heads found so far.
+    Collect(usize, Vec<Option<String>>),
+    /// Writing the symbols under the heads which weren't handled yet, after taking the given transition.
+    Apply(Step, Vec<bool>),
+    /// Placing the markers of heads which moved right, on the cell after the one being handled.
+    Bounce(Step, Vec<bool>, Vec<bool>),
+    /// Leaving the cell which was just handled, with the heads which moved left.
+    Leave(Step, Vec<bool>),
+    /// Placing the markers of heads which moved left past every other head, and starting the next step.
+    Land(usize, Vec<bool>),
+    /// Looking for the leftmost head, to start the next step.
+    Seek(usize),
+    /// The simulated machine has no transition for the symbols read.
+    Stuck,
+}
+
+/// Step being applied: the transition taken and the symbols read on each tape.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Step {
+    transition: usize,
+    read: Vec<String>,
+    /// Heads whose markers must be placed on the current cell, which moved left from the previous one.
+    carry: Vec<bool>,
+}
+
+/// What a state does when reading a cell.
+#[derive(Debug, Clone, PartialEq)]
+struct Action {
+    write: Cell,
+    dir: Direction,
+    next: Key,
+}
+
+/// Converts a machine with several tapes into a machine with a single tape divided into tracks. Each cell of the tape
+/// holds a tuple with, for each simulated tape, its symbol and whether its head is on that cell.
+///
+/// Only the given symbols (and the blank) are expected on the simulated tapes, so that the number of cells stays
+/// finite. Transitions which need other symbols can't be taken.
+pub fn reduce(machine: &MultiMachine, symbols: &BTreeSet<String>) -> Machine {
+    let mut symbols = symbols.clone();
+    symbols.insert(String::new());
+    let cells = cells(&symbols, machine.tapes);
+    let blank = Cell {
+        symbols: vec![String::new(); machine.tapes],
+        heads: vec![false; machine.tapes],
+    };
+
+    let mut m = Machine::new();
+    let mut states = HashMap::new();
+    let mut queue = VecDeque::new();
+    let mut state = |key: Key, m: &mut Machine, queue: &mut VecDeque<(Key, usize)>| match &key {
+        Key::Collect(q, found) if *q < 3 && found.iter().all(|f| f.is_none()) => *q,
+        _ => *states.entry(key.clone()).or_insert_with(|| {
+            let s = m.push_state();
+            queue.push_back((key, s));
+            s
+        }),
+    };
+
+    let start = Key::Collect(0, vec![None; machine.tapes]);
+    queue.push_back((start, 0));
+    while let Some((key, s)) = queue.pop_front() {
+        // Cells on which the state behaves as on blank cells share a wildcard transition, if it keeps the cell.
+        let default = action(machine, &key, &blank);
+        let wildcard = matches!(&default, Some(a) if a.write == blank);
+        if let Some(a) = default.as_ref().filter(|_| wildcard) {
+            let next = state(a.next.clone(), &mut m, &mut queue);
+            m.push_transition(Transition {
+                from: (s, None),
+                to: (next, None),
+                dir: a.dir,
+                locs: Vec::new(),
+            });
+        }
+
+        for cell in cells.iter() {
+            let a = match action(machine, &key, cell) {
+                Some(a) => a,
+                None => continue,
+            };
+            let same = wildcard && {
+                let d = default.as_ref().unwrap();
+                a.write == *cell && a.dir == d.dir && a.next == d.next
+            };
+            if same {
+                continue;
+            }
+
+            let locs = match &key {
+                Key::Apply(step, _) if step.carry.iter().all(|c| !c) => {
+                    machine.transitions[step.transition].locs.clone()
+                }
+                _ => Vec::new(),
+            };
+            let next = state(a.next, &mut m, &mut queue);
+            m.push_transition(Transition {
+                from: (s, Some(cell.symbol())),
+                to: (next, Some(a.write.symbol())),
+                dir: a.dir,
+                locs,
+            });
+        }
+    }
+
+    m
+}
+
+/// Encodes the input of the first tape, with the heads of every tape on its first cell.
+pub fn encode(input: &[String], tapes: usize) -> Vec<String> {
+    let len = input.len().max(1);
+    (0..len)
+        .map(|i| {
+            let mut symbols = vec![String::new(); tapes];
+            symbols[0] = input.get(i).cloned().unwrap_or_default();
+            Cell {
+                symbols,
+                heads: vec![i == 0; tapes],
+            }
+            .symbol()
+        })
+        .collect()
+}
+
+/// Returns the symbols of the single tape used to simulate the given number of tapes holding the given symbols,
+/// except the blank.
+pub fn alphabet(symbols: &BTreeSet<String>, tapes: usize) -> BTreeSet<String> {
+    let mut symbols = symbols.clone();
+    symbols.insert(String::new());
+    cells(&symbols, tapes)
+        .iter()
+        .map(|c| c.symbol())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Returns every cell which can be formed with the given symbols.
+fn cells(symbols: &BTreeSet<String>, tapes: usize) -> Vec<Cell> {
+    let mut cells = vec![Cell {
+        symbols: Vec::new(),
+        heads: Vec::new(),
+    }];
+    for _ in 0..tapes {
+        cells = cells
+            .into_iter()
+            .flat_map(|c| {
+                symbols.iter().flat_map(move |s| {
+                    let c = c.clone();
+                    [false, true].into_iter().map(move |h| {
+                        let mut c = c.clone();
+                        c.symbols.push(s.clone());
+                        c.heads.push(h);
+                        c
+                    })
+                })
+            })
+            .collect();
+    }
+    cells
+}
+
+/// Returns what a state does when reading a cell, or None if it has no transition for it.
+fn action(machine: &MultiMachine, key: &Key, cell: &Cell) -> Option<Action> {
+    let tapes = machine.tapes;
+    let stay = |next: Key| Action {
+        write: cell.clone(),
+        dir: Direction::Stay,
+        next,
+    };
+
+    Some(match key {
+        Key::Collect(q, found) => {
+            let mut found = found.clone();
+            for (i, f) in found.iter_mut().enumerate() {
+                if cell.heads[i] && f.is_none() {
+                    *f = Some(cell.symbols[i].clone());
+                }
+            }
+
+            if found.iter().all(|f| f.is_some()) {
+                let read = found.into_iter().map(|f| f.unwrap()).collect::<Vec<_>>();
+                stay(match machine.find(*q, &read) {
+                    Some(transition) => Key::Apply(
+                        Step {
+                            transition,
+                            read,
+                            carry: vec![false; tapes],
+                        },
+                        vec![false; tapes],
+                    ),
+                    None => Key::Stuck,
+                })
+            } else {
+                Action {
+                    write: cell.clone(),
+                    dir: Direction::Right,
+                    next: Key::Collect(*q, found),
+                }
+            }
+        }
+
+        Key::Apply(step, done) => {
+            let t = &machine.transitions[step.transition];
+            let mut write = cell.clone();
+            let mut done = done.clone();
+            let mut right = vec![false; tapes];
+            let mut left = vec![false; tapes];
+
+            for i in 0..tapes {
+                if step.carry[i] {
+                    write.heads[i] = true;
+                } else if cell.heads[i] && !done[i] {
+                    done[i] = true;
+                    write.symbols[i] = t.to.1[i].clone().unwrap_or_else(|| step.read[i].clone());
+                    match t.dirs[i] {
+                        Direction::Left => left[i] = true,
+                        Direction::Right => right[i] = true,
+                        Direction::Stay => continue,
+                    }
+                    write.heads[i] = false;
+                }
+            }
+
+            let step = Step {
+                carry: vec![false; tapes],
+                ..step.clone()
+            };
+            if right.iter().any(|&r| r) {
+                Action {
+                    write,
+                    dir: Direction::Right,
+                    next: Key::Bounce(
+                        step,
+                        done,
+                        right.iter().zip(left).map(|(r, l)| *r || l).collect(),
+                    ),
+                }
+            } else {
+                let leave = leave(machine, &step, &done, &left);
+                Action {
+                    write,
+                    dir: leave.dir,
+                    next: leave.next,
+                }
+            }
+        }
+
+        Key::Bounce(step, done, moved) => {
+            // Heads which moved right are marked here, while heads which moved left are remembered.
+            let t = &machine.transitions[step.transition];
+            let mut write = cell.clone();
+            let mut left = vec![false; tapes];
+            for i in 0..tapes {
+                if moved[i] {
+                    match t.dirs[i] {
+                        Direction::Right => write.heads[i] = true,
+                        _ => left[i] = true,
+                    }
+                }
+            }
+            Action {
+                write,
+                dir: Direction::Left,
+                next: Key::Leave(
+                    step.clone(),
+                    done.iter().chain(left.iter()).copied().collect(),
+                ),
+            }
+        }
+
+        Key::Leave(step, state) => {
+            let (done, left) = state.split_at(tapes);
+            let leave = leave(machine, step, done, left);
+            Action {
+                write: cell.clone(),
+                dir: leave.dir,
+                next: leave.next,
+            }
+        }
+
+        Key::Land(q, left) => {
+            let mut write = cell.clone();
+            for (head, l) in write.heads.iter_mut().zip(left) {
+                *head |= l;
+            }
+            Action {
+                write,
+                dir: Direction::Stay,
+                next: Key::Collect(*q, vec![None; tapes]),
+            }
+        }
+
+        Key::Seek(q) => match cell.heads.iter().any(|&h| h) {
+            true => stay(Key::Collect(*q, vec![None; tapes])),
+            false => Action {
+                write: cell.clone(),
+                dir: Direction::Right,
+                next: Key::Seek(*q),
+            },
+        },
+
+        Key::Stuck => return None,
+    })
+}
+
+/// Returns how to leave a cell once it has been handled, given the heads handled so far and the heads which moved
+/// left from the cell. The written cell in the returned action is unused.
+fn leave(machine: &MultiMachine, step: &Step, done: &[bool], left: &[bool]) -> Action {
+    let q = machine.transitions[step.transition].to.0;
+    let unused = Cell {
+        symbols: Vec::new(),
+        heads: Vec::new(),
+    };
+
+    if done.iter().all(|&d| d) {
+        // Every head was handled, so the cell is the leftmost one which had a head.
+        if left.iter().any(|&l| l) {
+            Action {
+                write: unused,
+                dir: Direction::Left,
+                next: Key::Land(q, left.to_vec()),
+            }
+        } else {
+            Action {
+                write: unused,
+                dir: Direction::Stay,
+                next: match q {
+                    1 | 2 => Key::Collect(q, vec![None; machine.tapes]),
+                    _ => Key::Seek(q),
+                },
+            }
+        }
+    } else {
+        Action {
+            write: unused,
+            dir: Direction::Left,
+            next: Key::Apply(
+                Step {
+                    carry: left.to_vec(),
+                    ..step.clone()
+                },
+                done.to_vec(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::MultiTransition;
+    use crate::simulator::{Simulator, Status, Tape};
+
+    #[test]
+    fn test_tracks() {
+        // Copies the input of the first tape to the second one, and then moves both heads back.
+        let mut m = MultiMachine::new(2);
+        let back = m.push_state();
+        let sym = |s: &str| Some(s.to_owned());
+        for s in ["a", "b"] {
+            m.push_transition(MultiTransition {
+                from: (0, vec![sym(s), None]),
+                to: (0, vec![None, sym(s)]),
+                dirs: vec![Direction::Right, Direction::Right],
+                locs: Vec::new(),
+            });
+        }
+        m.push_transition(MultiTransition {
+            from: (0, vec![sym(""), None]),
+            to: (back, vec![None, None]),
+            dirs: vec![Direction::Left, Direction::Left],
+            locs: Vec::new(),
+        });
+        m.push_transition(MultiTransition {
+            from: (back, vec![sym(""), None]),
+            to: (1, vec![None, None]),
+            dirs: vec![Direction::Right, Direction::Stay],
+            locs: Vec::new(),
+        });
+        m.push_transition(MultiTransition {
+            from: (back, vec![None, None]),
+            to: (back, vec![None, None]),
+            dirs: vec![Direction::Left, Direction::Left],
+            locs: Vec::new(),
+        });
+
+        let symbols = ["a", "b"].iter().map(|s| s.to_string()).collect();
+        let single = reduce(&m, &symbols);
+        let input = ["a", "b", "b"].map(|s| s.to_owned());
+        let mut sim = Simulator::new(&single, Tape::new(encode(&input, 2)));
+        assert_eq!(sim.run(10000), Status::Accepted);
+
+        let tape = sim.tape();
+        let (min, max) = tape.bounds();
+        let cells = (min..=max).map(|p| tape.get(p)).collect::<Vec<_>>();
+        assert_eq!(cells, vec!["(,,,^)", "(a,^,a,)", "(b,,b,)", "(b,,b,)", ""]);
+        // The machine stops on the leftmost head, which is the one of the second tape.
+        assert_eq!(tape.head(), -1);
+    }
+}
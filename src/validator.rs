@@ -0,0 +1,213 @@
+use crate::data::{Machine, Transition};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// How serious a problem found in a machine is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The exported machine wouldn't behave as the generated one.
+    Error,
+    /// The machine works, but may hide a bug in the compiler or in the program.
+    Warning,
+}
+
+/// Problem found in a machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Checks a machine before it is exported, for tapes which only hold the given symbols.
+///
+/// Several transitions for the same state and symbol are errors, unless the machine is allowed to be
+/// nondeterministic. A wildcard transition overlapping a concrete symbol of the same state, states unreachable from
+/// the initial state and symbols on which a reachable state gets stuck are warnings.
+pub fn validate(
+    machine: &Machine,
+    symbols: &BTreeSet<String>,
+    nondeterministic: bool,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    let mut outgoing = vec![Vec::new(); machine.state_count];
+    let mut incoming = vec![Vec::new(); machine.state_count];
+    for t in machine.transitions.iter() {
+        outgoing[t.from.0].push(t);
+        incoming[t.to.0].push(t);
+    }
+
+    for (state, transitions) in outgoing.iter().enumerate() {
+        let mut reads = BTreeMap::new();
+        for t in transitions {
+            reads.entry(&t.from.1).or_insert_with(Vec::new).push(*t);
+        }
+
+        for (read, ts) in reads.iter() {
+            if ts.len() > 1 && !nondeterministic {
+                issues.push(Issue {
+                    severity: Severity::Error,
+                    message: format!(
+                        "State {} has {} transitions for {}, generated at {}",
+                        state,
+                        ts.len(),
+                        fmt_read(read),
+                        fmt_locs(ts)
+                    ),
+                });
+            }
+        }
+
+        if let Some(wildcards) = reads.get(&None) {
+            let overlapped = reads
+                .keys()
+                .filter_map(|r| r.as_ref())
+                .map(|s| format!("'{}'", s))
+                .collect::<Vec<_>>();
+            if !overlapped.is_empty() {
+                issues.push(Issue {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "State {} matches {} both exactly and with the wildcard generated at {}, which relies on exact matches taking priority",
+                        state,
+                        overlapped.join(", "),
+                        fmt_locs(wildcards)
+                    ),
+                });
+            }
+        }
+    }
+
+    let mut reachable = vec![false; machine.state_count];
+    let mut stack = vec![0];
+    reachable[0] = true;
+    while let Some(state) = stack.pop() {
+        for t in outgoing[state].iter() {
+            if !reachable[t.to.0] {
+                reachable[t.to.0] = true;
+                stack.push(t.to.0);
+            }
+        }
+    }
+
+    let unreachable = (3..machine.state_count)
+        .filter(|&s| !reachable[s])
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    if !unreachable.is_empty() {
+        issues.push(Issue {
+            severity: Severity::Warning,
+            message: match unreachable.len() {
+                1 => format!(
+                    "State {} is unreachable from the initial state",
+                    unreachable[0]
+                ),
+                _ => format!(
+                    "States {} are unreachable from the initial state",
+                    unreachable.join(", ")
+                ),
+            },
+        });
+    }
+
+    let symbols = symbols
+        .iter()
+        .cloned()
+        .chain(std::iter::once(String::new()))
+        .collect::<BTreeSet<_>>();
+    for state in (0..machine.state_count).filter(|&s| reachable[s] && s != 1 && s != 2) {
+        if outgoing[state].iter().any(|t| t.from.1.is_none()) {
+            continue;
+        }
+        let stuck = symbols
+            .iter()
+            .filter(|&s| !outgoing[state].iter().any(|t| t.from.1.as_ref() == Some(s)))
+            .map(|s| format!("'{}'", s))
+            .collect::<Vec<_>>();
+        if stuck.is_empty() {
+            continue;
+        }
+
+        let mut message = format!(
+            "State {} has no transition for {}, on which the machine gets stuck",
+            state,
+            stuck.join(", ")
+        );
+        if !incoming[state].is_empty() {
+            message += &format!(", reached from {}", fmt_locs(&incoming[state]));
+        }
+        issues.push(Issue {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    issues
+}
+
+/// Formats the symbol read by a transition.
+fn fmt_read(read: &Option<String>) -> String {
+    match read {
+        Some(s) => format!("symbol '{}'", s),
+        None => "any symbol".to_owned(),
+    }
+}
+
+/// Formats the source locations which generated some transitions.
+fn fmt_locs(transitions: &[&Transition]) -> String {
+    let locs = transitions
+        .iter()
+        .flat_map(|t| t.locs.iter())
+        .map(|l| l.to_string())
+        .collect::<BTreeSet<_>>();
+    match locs.is_empty() {
+        true => "an unknown location".to_owned(),
+        false => locs.into_iter().collect::<Vec<_>>().join("; "),
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "Error: {}", self.message),
+            Severity::Warning => write!(f, "Warning: {}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Direction;
+
+    #[test]
+    fn test_validate() {
+        let mut m = Machine::new();
+        m.push_transition(Transition::new((0, Some("a")), (3, None), Direction::Right));
+        m.push_transition(Transition::new((0, Some("a")), (1, None), Direction::Right));
+        m.push_transition(Transition::new((0, None), (2, None), Direction::Right));
+        m.push_transition(Transition::new((3, Some("")), (1, None), Direction::Right));
+        m.push_transition(Transition::new((4, None), (1, None), Direction::Right));
+        m.push_state();
+        m.push_state();
+
+        let symbols = BTreeSet::from(["a".to_owned(), "b".to_owned()]);
+        let messages = |nondeterministic| {
+            validate(&m, &symbols, nondeterministic)
+                .into_iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(false),
+            vec![
+                "Error: State 0 has 2 transitions for symbol 'a', generated at an unknown location",
+                "Warning: State 0 matches 'a' both exactly and with the wildcard generated at an unknown location, which relies on exact matches taking priority",
+                "Warning: State 4 is unreachable from the initial state",
+                "Warning: State 3 has no transition for 'a', 'b', on which the machine gets stuck, reached from an unknown location",
+            ]
+        );
+        assert!(messages(true).iter().all(|m| m.starts_with("Warning")));
+    }
+}